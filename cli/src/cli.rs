@@ -1,5 +1,7 @@
 use std::{path::PathBuf, sync::LazyLock};
 
+use todo_list::csv;
+
 static DEFAULT_DB_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
     let mut path =
         dirs::data_local_dir().expect("this will only ever run on systems with a local dir");
@@ -30,15 +32,118 @@ impl From<Level> for log::LevelFilter {
     }
 }
 
+/// A file format which lists can be exported to and imported from
+#[derive(Debug, Clone, Copy, derive_more::Display, clap::ValueEnum)]
+#[display(rename_all = "kebab-case")]
+pub(crate) enum Format {
+    /// One task per line; see <https://github.com/todotxt/todo.txt>
+    TodoTxt,
+    /// Every list and item, losslessly
+    Json,
+    /// An iCalendar file of to-dos, with a calendar per list
+    Ical,
+    /// A GitHub-flavoured checklist per list; importing always creates new lists
+    Markdown,
+    /// A spreadsheet row per item
+    Csv,
+}
+
+/// Parse a `HEADER=FIELD` column mapping
+fn parse_column(arg: &str) -> Result<(String, csv::Field), String> {
+    let (header, field) = arg
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected HEADER=FIELD, found {arg:?}"))?;
+    let field = field.parse().map_err(|_| {
+        let fields = csv::Field::ALL.map(csv::Field::name).join(", ");
+        format!("unknown field {field:?}; expected one of {fields}")
+    })?;
+    Ok((header.to_owned(), field))
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub(crate) enum Command {
+    /// Export every list
+    Export {
+        /// Format to write
+        #[arg(short, long, default_value_t = Format::TodoTxt)]
+        format: Format,
+
+        /// File to write; defaults to stdout
+        file: Option<PathBuf>,
+    },
+    /// Import lists, merging them with the existing ones
+    Import {
+        /// Format to read
+        #[arg(short, long, default_value_t = Format::TodoTxt)]
+        format: Format,
+
+        /// Delete lists and items which are not in the imported file
+        #[arg(long)]
+        replace: bool,
+
+        /// Read the CSV column with this header as this field
+        ///
+        /// Columns named after fields are read as those fields anyway.
+        #[arg(long = "map", value_name = "HEADER=FIELD", value_parser = parse_column)]
+        columns: Vec<(String, csv::Field)>,
+
+        /// File to read; defaults to stdin
+        file: Option<PathBuf>,
+    },
+    /// Copy the database while it may be in use
+    Backup {
+        /// File to write; defaults to a new timestamped backup, rotated like the automatic ones
+        file: Option<PathBuf>,
+    },
+    /// Replace the database with a backup, backing up the current one first without rotating
+    ///
+    /// The backup must be encrypted with the same key as the database, if any.
+    Restore {
+        /// Backup to restore
+        file: PathBuf,
+    },
+    /// Exchange changes with a sync server
+    ///
+    /// The whole database is pushed each time, deletions included. The client's id and how far it has pulled are
+    /// kept in a `.sync` file beside the database.
+    #[cfg(feature = "sync")]
+    Sync {
+        /// The server's url, such as http://127.0.0.1:8417
+        url: String,
+    },
+}
+
 #[derive(Debug, clap::Parser)]
 pub(crate) struct Args {
     /// Path to the database
     #[arg(short='p', long, default_value = DEFAULT_DB_PATH.clone().into_os_string())]
     pub(crate) db_path: PathBuf,
 
+    /// Encrypt the database, prompting for its passphrase
+    ///
+    /// A new database is created encrypted. In the interactive interface, <p> sets, changes, or removes the passphrase.
+    #[arg(short, long)]
+    pub(crate) encrypted: bool,
+
+    /// Encrypt the database with the raw 256-bit key in this file, instead of a passphrase
+    ///
+    /// The file holds the key as 32 bytes or 64 hex digits, for keys kept in a keyring or on a hardware token.
+    #[arg(short, long, value_name = "PATH", conflicts_with = "encrypted")]
+    pub(crate) key_file: Option<PathBuf>,
+
+    /// How many automatic backups of the database to keep; 0 turns them off
+    ///
+    /// The database is backed up into `backups/` beside it before migrations and key changes.
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    pub(crate) keep_backups: usize,
+
     /// Enable logging
     ///
     /// If this flag is set without an explicit level argument, defaults to "info".
     #[arg(short, long, value_name = "LEVEL", num_args = 0..=1, default_missing_value = "info")]
     pub(crate) log: Option<Level>,
+
+    /// Run a command instead of the interactive interface
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
 }
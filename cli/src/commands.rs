@@ -0,0 +1,152 @@
+//! Non-interactive commands, which run against the database and exit.
+
+use std::{
+    io::{Read as _, Write as _},
+    path::Path,
+};
+
+use anyhow::{Context as _, Result, bail};
+use todo_list::{ImportMode, ImportReport, csv, encryption::Key, ical, markdown, todo_txt};
+
+use crate::cli::{Command, Format};
+
+pub(crate) async fn run(
+    command: Command,
+    db_path: impl AsRef<Path>,
+    key: Option<&Key>,
+    keep_backups: usize,
+) -> Result<()> {
+    let mut connection = crate::database::open(db_path, key, keep_backups)
+        .await
+        .context("opening database")?;
+
+    match command {
+        Command::Export { format, file } => {
+            let exported = match format {
+                Format::TodoTxt => todo_txt::export(&connection).await,
+                Format::Json => todo_list::export_json(&connection).await,
+                Format::Ical => ical::export(&connection).await,
+                Format::Markdown => markdown::export_all(&connection).await,
+                Format::Csv => csv::export(&connection).await,
+            }
+            .with_context(|| format!("exporting {format}"))?;
+
+            match file {
+                Some(path) => std::fs::write(&path, exported)
+                    .with_context(|| format!("writing {}", path.display()))?,
+                None => std::io::stdout()
+                    .write_all(exported.as_bytes())
+                    .context("writing to stdout")?,
+            }
+        }
+        Command::Import {
+            format,
+            replace,
+            columns,
+            file,
+        } => {
+            if replace && matches!(format, Format::Markdown) {
+                bail!("markdown import only creates new lists, so it cannot replace");
+            }
+            // checklist items outside any heading are titled after the file they came from
+            let default_title = file
+                .as_deref()
+                .and_then(Path::file_stem)
+                .map_or("Imported".into(), |stem| stem.to_string_lossy());
+
+            let text = match &file {
+                Some(path) => std::fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?,
+                None => {
+                    let mut text = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut text)
+                        .context("reading from stdin")?;
+                    text
+                }
+            };
+
+            let mode = if replace {
+                ImportMode::Replace
+            } else {
+                ImportMode::Merge
+            };
+            let report = match format {
+                Format::TodoTxt => todo_txt::import(&connection, &text, mode).await,
+                Format::Json => todo_list::import_json(&connection, &text, mode).await,
+                Format::Ical => ical::import(&connection, &text, mode).await,
+                Format::Markdown => markdown::import(&connection, &text, &default_title)
+                    .await
+                    .map(|lists| ImportReport {
+                        created: lists.len()
+                            + lists.iter().map(|list| list.items().len()).sum::<usize>(),
+                        ..Default::default()
+                    }),
+                Format::Csv => {
+                    let mapping = columns
+                        .iter()
+                        .fold(csv::ColumnMapping::default(), |mapping, (header, field)| {
+                            mapping.map(header, *field)
+                        });
+                    csv::import(&connection, &text, &mapping, mode)
+                        .await
+                        .map(|imported| {
+                            for error in &imported.errors {
+                                eprintln!("skipped {error}");
+                            }
+                            imported.report
+                        })
+                }
+            }
+            .with_context(|| format!("importing {format}"))?;
+
+            eprintln!(
+                "created {}, updated {}, deleted {}, skipped {} deleted before",
+                report.created, report.updated, report.deleted, report.skipped
+            );
+        }
+        Command::Backup { file } => {
+            let path = match file {
+                Some(path) => {
+                    todo_list::backup(&connection, &path, key, crate::database::log_progress)
+                        .with_context(|| format!("backing up to {}", path.display()))?;
+                    path
+                }
+                // an explicit request means a backup even if automatic ones are off
+                None => crate::database::back_up(&connection, key, keep_backups.max(1))?
+                    .expect("backups are kept"),
+            };
+            eprintln!("backed up to {}", path.display());
+        }
+        Command::Restore { file } => {
+            // rotating could delete the very backup being restored
+            if keep_backups > 0 {
+                let path = crate::database::back_up_unrotated(&connection, key)?;
+                eprintln!("backed up the current database to {}", path.display());
+            }
+            todo_list::restore(&mut connection, &file, key, crate::database::log_progress)
+                .with_context(|| format!("restoring {}", file.display()))?;
+            // the backup may predate migrations which opening the database applied
+            todo_list::apply_schema(&connection)
+                .await
+                .context("applying schema to restored database")?;
+            eprintln!("restored {}", file.display());
+        }
+        #[cfg(feature = "sync")]
+        Command::Sync { url } => {
+            let report = crate::sync::run(&connection, &url)
+                .await
+                .with_context(|| format!("syncing with {url}"))?;
+            eprintln!(
+                "pushed {}, pulled {}: applied {}, conflicts {}, skipped {}",
+                report.pushed,
+                report.pulled,
+                report.applied.applied,
+                report.applied.conflicts,
+                report.applied.skipped
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use glob::glob;
+use log::{debug, info, warn};
+use rusqlite::Connection;
+use time::{UtcDateTime, format_description::StaticFormatDescription, macros::format_description};
+use todo_list::{
+    BackupProgress,
+    encryption::{self, Key, Strength, WrongPassphrase},
+};
+
+/// Timestamps of backups, which sort in the order they were taken
+const BACKUP_TIMESTAMP: StaticFormatDescription =
+    format_description!("[year][month][day]T[hour][minute][second].[subsecond digits:3]Z");
+
+/// Open the database at `db_path`, creating it and its parent directories if necessary, and apply the schema.
+///
+/// If a key is given, the database is encrypted with it; a new database is created encrypted.
+///
+/// If the database exists but its schema is out of date, it is backed up before being migrated, keeping the newest
+/// `keep_backups` backups. If the database did not exist and the schema can't be applied, whatever was created is
+/// removed again.
+pub(crate) async fn open(
+    db_path: impl AsRef<Path>,
+    key: Option<&Key>,
+    keep_backups: usize,
+) -> Result<Connection> {
+    let db_path = std::path::absolute(db_path).context("absolutizing path")?;
+
+    let db_exists = std::fs::exists(&db_path).context("checking for db path existence")?;
+
+    // ensure parent path exists
+    let parent = db_path
+        .parent()
+        .ok_or(anyhow!("cannot use `/` as the db"))?;
+    std::fs::create_dir_all(parent).context("creating db parent dir")?;
+
+    let connection = async {
+        let connection = connect(&db_path, key)?;
+        if db_exists
+            && todo_list::schema_version(&connection).context("reading schema version")?
+                < todo_list::SCHEMA_VERSION
+        {
+            back_up(&connection, key, keep_backups).context("backing up before migrating")?;
+        }
+        todo_list::apply_schema(&connection)
+            .await
+            .context("applying schema to database file")?;
+        anyhow::Ok(connection)
+    }
+    .await
+    .inspect_err(|_err| {
+        if !db_exists {
+            // best effort
+            // first the db itself
+            let _ = std::fs::remove_file(&db_path);
+            // then ancillary files by glob if necessary
+            if let Ok(paths) = glob(&format!("{}*", db_path.to_string_lossy())) {
+                for path in paths.flatten() {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+        }
+    })?;
+
+    Ok(connection)
+}
+
+/// Connect to the database, unlocking it before anything else happens on the connection
+fn connect(db_path: &Path, key: Option<&Key>) -> Result<Connection> {
+    let connection = Connection::open(db_path).context("connecting to database")?;
+    match key {
+        Some(key) => encryption::unlock(&connection, key).map_err(|err| {
+            if err.is::<WrongPassphrase>() {
+                let what = match key {
+                    Key::Passphrase(_) => "passphrase",
+                    Key::Raw(_) => "key",
+                };
+                anyhow!("wrong {what}, or {} is not a database", db_path.display())
+            } else {
+                err.context("unlocking database")
+            }
+        })?,
+        None => {
+            if encryption::file_is_encrypted(db_path).context("reading database header")? {
+                bail!(
+                    "{} is encrypted, or not a database; pass --encrypted to enter its passphrase",
+                    db_path.display()
+                )
+            }
+        }
+    }
+    Ok(connection)
+}
+
+/// Ask for the database's passphrase on the terminal.
+///
+/// When creating a database, the passphrase must be entered twice, so that a typo doesn't lock it forever.
+pub(crate) fn prompt_passphrase(db_path: impl AsRef<Path>) -> Result<String> {
+    let db_exists = std::fs::exists(db_path).context("checking for db path existence")?;
+    let passphrase = rpassword::prompt_password("Passphrase: ").context("reading passphrase")?;
+    if passphrase.is_empty() {
+        bail!("an encrypted database needs a passphrase");
+    }
+    if !db_exists {
+        if Strength::of(&passphrase) == Strength::Weak {
+            bail!("that passphrase is weak; try a longer one, or a few unrelated words");
+        }
+        let confirmation =
+            rpassword::prompt_password("Confirm passphrase: ").context("reading passphrase")?;
+        if confirmation != passphrase {
+            bail!("passphrases do not match");
+        }
+    }
+    Ok(passphrase)
+}
+
+/// The path of the file behind `connection`, which changing its key backs up
+pub(crate) fn file_path(connection: &Connection) -> Result<PathBuf> {
+    connection
+        .path()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("the database has no file"))
+}
+
+/// Back the database up into `backups/` beside it, and delete all but the newest `keep` of its backups there.
+///
+/// Backups are encrypted with `key`, which the database is unlocked with. Returns where the backup went, or `None`
+/// if `keep` is 0, which turns backups off.
+pub(crate) fn back_up(
+    connection: &Connection,
+    key: Option<&Key>,
+    keep: usize,
+) -> Result<Option<PathBuf>> {
+    if keep == 0 {
+        return Ok(None);
+    }
+    let backup_path = back_up_unrotated(connection, key)?;
+
+    let (dir, prefix) = backup_location(connection)?;
+    // timestamps sort oldest first
+    let mut backups = std::fs::read_dir(&dir)
+        .context("listing backups")?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| is_backup(name, &prefix))
+        })
+        .collect::<Vec<_>>();
+    backups.sort();
+    for old in &backups[..backups.len().saturating_sub(keep)] {
+        if let Err(err) = std::fs::remove_file(old) {
+            warn!(path:% = old.display(), err:%; "failed to delete old backup");
+        }
+    }
+
+    Ok(Some(backup_path))
+}
+
+/// Back the database up into `backups/` beside it, like [`back_up`], but delete none of its older backups.
+pub(crate) fn back_up_unrotated(connection: &Connection, key: Option<&Key>) -> Result<PathBuf> {
+    let (dir, prefix) = backup_location(connection)?;
+    std::fs::create_dir_all(&dir).context("creating backup dir")?;
+
+    let timestamp = UtcDateTime::now()
+        .format(BACKUP_TIMESTAMP)
+        .context("formatting backup timestamp")?;
+    let backup_path = dir.join(format!("{prefix}{timestamp}.sqlite"));
+    todo_list::backup(connection, &backup_path, key, log_progress)
+        .context("backing up database")?;
+    info!(path:% = backup_path.display(); "backed up database");
+    Ok(backup_path)
+}
+
+/// The directory the database's backups go in, and the prefix of their file names
+fn backup_location(connection: &Connection) -> Result<(PathBuf, String)> {
+    let db_path = file_path(connection)?;
+    let dir = db_path
+        .parent()
+        .ok_or(anyhow!("cannot use `/` as the db"))?
+        .join("backups");
+    let stem = db_path
+        .file_stem()
+        .map_or("db".into(), |stem| stem.to_string_lossy());
+    Ok((dir, format!("{stem}-")))
+}
+
+/// Whether `name` is a backup of the database whose backups start with `prefix`.
+///
+/// Another database's name can start with that prefix too, so the rest must be exactly a timestamp.
+fn is_backup(name: &str, prefix: &str) -> bool {
+    name.strip_prefix(prefix)
+        .and_then(|name| name.strip_suffix(".sqlite"))
+        .is_some_and(|timestamp| UtcDateTime::parse(timestamp, BACKUP_TIMESTAMP).is_ok())
+}
+
+/// Report how far a backup or restore has got to the log
+pub(crate) fn log_progress(progress: BackupProgress) {
+    debug!("copied" = progress.copied, "total" = progress.total; "copying database");
+}
@@ -1,9 +1,14 @@
 mod cli;
+mod commands;
+mod database;
 mod helpers;
+#[cfg(feature = "sync")]
+mod sync;
 mod tui_app;
 
 use anyhow::{Context as _, Result, anyhow};
 use clap::Parser;
+use todo_list::encryption::Key;
 
 use cli::Args;
 
@@ -18,9 +23,28 @@ fn main() -> Result<()> {
         tui_logger::set_default_level(log_level);
     }
 
+    let key = if args.encrypted {
+        Some(Key::Passphrase(database::prompt_passphrase(&args.db_path)?))
+    } else if let Some(key_file) = &args.key_file {
+        Some(Key::from_file(key_file).context("loading key")?)
+    } else {
+        None
+    };
+
+    if let Some(command) = args.command {
+        return smol::block_on(commands::run(
+            command,
+            &args.db_path,
+            key.as_ref(),
+            args.keep_backups,
+        ));
+    }
+
     let logging_enabled = args.log.is_some();
-    let mut app = smol::block_on(async move { App::new(&args.db_path, logging_enabled).await })
-        .context("creating app")?;
+    let mut app = smol::block_on(async move {
+        App::new(&args.db_path, key, args.keep_backups, logging_enabled).await
+    })
+    .context("creating app")?;
 
     helpers::install_panic_hook();
     let mut terminal = helpers::init_terminal().context("initializing terminal")?;
@@ -37,9 +61,9 @@ fn main() -> Result<()> {
 
         // process whatever messages are necessary, including follow-ons
         let mut current_msg =
-            Message::from_event(&app.state).context("getting message from current event")?;
+            Message::from_event(&mut app).context("getting message from current event")?;
         while let Some(msg) = current_msg {
-            current_msg = smol::block_on(async { app.update(msg).await });
+            current_msg = app.update(msg);
         }
     }
 
@@ -0,0 +1,59 @@
+//! Exchange the database's changes with a sync server.
+//!
+//! The CLI can't keep a recorder alive between runs, so each sync pushes a snapshot of the whole database. The
+//! client's id and cursor are kept in a file beside the database, so that its next snapshot replaces this one on
+//! the server, and it only pulls what it hasn't seen.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use rusqlite::Connection;
+use sync_service::{
+    client::{Client, HttpTransport, SyncReport},
+    protocol::Cursor,
+};
+use todo_list::{Uuid, sync::ConflictPolicy};
+
+/// Push the database to the server at `url`, then pull and apply changes from other clients.
+pub(crate) async fn run(connection: &Connection, url: &str) -> Result<SyncReport> {
+    let path = state_path(connection)?;
+    let (id, cursor) = load_state(&path)?;
+    let transport = HttpTransport::new(url)?;
+    let mut client = Client::new(transport, id, cursor);
+    let report = client
+        .sync_snapshot(connection, ConflictPolicy::LastWriteWins)
+        .await?;
+    std::fs::write(&path, format!("{} {}\n", client.id(), client.cursor()))
+        .with_context(|| format!("writing {}", path.display()))?;
+    Ok(report)
+}
+
+/// Where the client's id and cursor are kept: beside the database, named after it
+fn state_path(connection: &Connection) -> Result<PathBuf> {
+    let mut path = crate::database::file_path(connection)?.into_os_string();
+    path.push(".sync");
+    Ok(path.into())
+}
+
+/// The client's id and cursor, or a fresh client if the database has never synced
+fn load_state(path: &Path) -> Result<(Uuid, Cursor)> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Ok((Uuid::now_v7(), Cursor::default()));
+        }
+        Err(err) => return Err(err).with_context(|| format!("reading {}", path.display())),
+    };
+    let (id, cursor) = text
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("{} is not a sync state file", path.display()))?;
+    let id = Uuid::parse_str(id)
+        .with_context(|| format!("reading client id from {}", path.display()))?;
+    let cursor =
+        Cursor::parse(cursor).with_context(|| format!("reading cursor from {}", path.display()))?;
+    Ok((id, cursor))
+}
@@ -1,57 +1,82 @@
 mod update;
 mod view;
 
-use std::path::Path;
+use std::{future::Future, path::Path};
 
-use anyhow::{Context as _, Result, anyhow};
-use glob::glob;
+use anyhow::{Context as _, Result};
+use smol::channel::{Receiver, Sender};
+use todo_list::{encryption::Key, threaded::Database};
 
-use rusqlite::Connection;
-
-use crate::tui_app::State;
+use crate::tui_app::{Message, State};
 
 #[derive(Debug)]
 pub(crate) struct App {
-    pub(crate) connection: Connection,
+    pub(crate) database: Database,
     pub(crate) state: State,
     pub(crate) logging_enabled: bool,
+    /// The key the database is encrypted with, if it is
+    pub(crate) key: Option<Key>,
+    /// How many automatic backups of the database to keep
+    pub(crate) keep_backups: usize,
+    /// Number of database operations which have been started but whose results have not yet been processed
+    pending_operations: usize,
+    /// Background operations report their results here
+    outcome_sender: Sender<Message>,
+    outcome_receiver: Receiver<Message>,
 }
 
 impl App {
-    pub(crate) async fn new(db_path: impl AsRef<Path>, logging_enabled: bool) -> Result<Self> {
-        let db_path = std::path::absolute(db_path).context("absolutizing path")?;
-
-        let db_exists = std::fs::exists(&db_path).context("checking for db path existence")?;
-
-        // ensure parent path exists
-        let parent = db_path
-            .parent()
-            .ok_or(anyhow!("cannot use `/` as the db"))?;
-        std::fs::create_dir_all(parent).context("creating db parent dir")?;
-
-        let connection = rusqlite::Connection::open(&db_path).context("connecting to database")?;
-
-        if !db_exists {
-            todo_list::apply_schema(&connection)
-                .await
-                .context("applying schema to new database file")
-                .inspect_err(|_err| {
-                    // best effort
-                    // first the db itself
-                    let _ = std::fs::remove_file(&db_path);
-                    // then ancillary files by glob if necessary
-                    if let Ok(paths) = glob(&format!("{}*", db_path.to_string_lossy())) {
-                        for path in paths.flatten() {
-                            let _ = std::fs::remove_file(path);
-                        }
-                    }
-                })?;
-        }
+    pub(crate) async fn new(
+        db_path: impl AsRef<Path>,
+        key: Option<Key>,
+        keep_backups: usize,
+        logging_enabled: bool,
+    ) -> Result<Self> {
+        let connection = crate::database::open(db_path, key.as_ref(), keep_backups)
+            .await
+            .context("opening database")?;
+
+        let database = Database::new(connection).context("starting database thread")?;
+        let (outcome_sender, outcome_receiver) = smol::channel::unbounded();
 
         Ok(Self {
-            connection,
+            database,
             state: State::Initial,
             logging_enabled,
+            key,
+            keep_backups,
+            pending_operations: 0,
+            outcome_sender,
+            outcome_receiver,
         })
     }
+
+    /// `true` while any database operation is in flight
+    pub(crate) fn is_busy(&self) -> bool {
+        self.pending_operations > 0
+    }
+
+    /// Run a database operation in the background.
+    ///
+    /// The message it produces (or [`Message::OperationFailed`]) is delivered by [`Self::completed_operation`].
+    pub(crate) fn spawn_operation<F>(&mut self, operation: F)
+    where
+        F: Future<Output = Result<Message>> + Send + 'static,
+    {
+        let sender = self.outcome_sender.clone();
+        self.pending_operations += 1;
+        smol::spawn(async move {
+            let message = operation.await.unwrap_or_else(Message::OperationFailed);
+            // the receiver lives as long as the app, so this can only fail during shutdown
+            let _ = sender.send(message).await;
+        })
+        .detach();
+    }
+
+    /// Get the result of a completed background operation, if any, without blocking.
+    pub(crate) fn completed_operation(&mut self) -> Option<Message> {
+        let message = self.outcome_receiver.try_recv().ok()?;
+        self.pending_operations -= 1;
+        Some(message)
+    }
 }
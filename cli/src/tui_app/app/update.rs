@@ -2,37 +2,32 @@
 
 use anyhow::{Context as _, anyhow};
 use ratatui::widgets::ListState;
+use todo_list::encryption::{self, Key, Strength};
 
 use crate::tui_app::{App, Message, State, TextInputMode};
 
 impl App {
     /// Process an incoming message, updating the app state appropriately.
-    pub(crate) async fn update(&mut self, msg: Message) -> Option<Message> {
-        /// if this is an error, produce the error state and return
-        macro_rules! or_err_state {
-            ($e:expr) => {{
-                match $e {
-                    Ok(ok) => ok,
-                    Err(err) => {
-                        self.state = State::Error(err);
-                        return None;
-                    }
-                }
-            }};
-        }
-
+    ///
+    /// This never blocks: database operations are started in the background
+    /// and report back with a follow-up message when complete.
+    pub(crate) fn update(&mut self, msg: Message) -> Option<Message> {
         match msg {
             Message::Quit => {
                 self.state = State::Exit;
             }
             Message::LoadTodos => {
-                let (ids, labels) = or_err_state!(
-                    todo_list::TodoList::list_all(&self.connection)
+                let database = self.database.clone();
+                self.spawn_operation(async move {
+                    let todos = database
+                        .list_all()
                         .await
-                        .context("listing all todo lists")
-                )
-                .into_iter()
-                .unzip::<_, _, Vec<_>, Vec<_>>();
+                        .context("listing all todo lists")?;
+                    Ok(Message::TodosLoaded(todos))
+                });
+            }
+            Message::TodosLoaded(todos) => {
+                let (ids, labels) = todos.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
 
                 self.state = State::ListSelect {
                     ids,
@@ -40,6 +35,27 @@ impl App {
                     list_state: ListState::default(),
                 };
             }
+            Message::TodoListLoaded(todo_list) => {
+                self.state = State::ListView {
+                    todo_list,
+                    item_list_state: ListState::default(),
+                };
+            }
+            Message::TodoListUpdated(updated) => {
+                // the user may have navigated away in the meantime; only update the list if it is still in view
+                if let State::ListView { todo_list, .. } = &mut self.state
+                    && todo_list.id() == updated.id()
+                {
+                    *todo_list = updated;
+                }
+            }
+            Message::KeyChanged(key) => {
+                self.key = key;
+                return Some(Message::LoadTodos);
+            }
+            Message::OperationFailed(err) => {
+                self.state = State::Error(err);
+            }
             Message::DecrementItem => match &mut self.state {
                 State::ListSelect { list_state, .. } => {
                     list_state.select_previous();
@@ -71,16 +87,11 @@ impl App {
                 }
             },
             Message::SelectTodoList(list_id) => {
-                let todo_list = or_err_state!(
-                    todo_list::TodoList::load(&self.connection, list_id)
-                        .await
-                        .context("loading todo list")
-                );
-
-                self.state = State::ListView {
-                    todo_list,
-                    item_list_state: ListState::default(),
-                };
+                let database = self.database.clone();
+                self.spawn_operation(async move {
+                    let todo_list = database.load(list_id).await.context("loading todo list")?;
+                    Ok(Message::TodoListLoaded(todo_list))
+                });
             }
             Message::NewTodoList => {
                 self.state = State::TextInput {
@@ -91,9 +102,7 @@ impl App {
             }
             Message::DeleteList => {
                 let State::ListSelect {
-                    ids,
-                    list_state,
-                    ..
+                    ids, list_state, ..
                 } = &mut self.state
                 else {
                     self.state = State::Error(anyhow!(
@@ -105,15 +114,23 @@ impl App {
 
                 let selected_idx = list_state.selected()?;
                 let &list_id = ids.get(selected_idx)?;
-                
-                or_err_state!(
-                    todo_list::TodoList::delete(&self.connection, list_id)
-                        .await
-                        .context("deleting todo list")
-                );
 
-                // Reload the list view to reflect the deletion
-                return Some(Message::LoadTodos);
+                let database = self.database.clone();
+                self.spawn_operation(async move {
+                    database
+                        .delete(list_id)
+                        .await
+                        .context("deleting todo list")?;
+                    // Reload the list view to reflect the deletion
+                    Ok(Message::LoadTodos)
+                });
+            }
+            Message::SetPassphrase => {
+                self.state = State::TextInput {
+                    mode: TextInputMode::Passphrase,
+                    buffer: String::new(),
+                    cursor_pos: 0,
+                };
             }
             Message::NewItem => {
                 let State::ListView { todo_list, .. } = &self.state else {
@@ -172,12 +189,16 @@ impl App {
                 let selected_idx = item_list_state.selected()?;
                 let items = todo_list.items().keys().copied().collect::<Vec<_>>();
                 let &item_id = items.get(selected_idx)?;
-                or_err_state!(
-                    todo_list
-                        .remove_item(&self.connection, item_id)
+
+                let todo_list = todo_list.clone();
+                let database = self.database.clone();
+                self.spawn_operation(async move {
+                    let (todo_list, _) = database
+                        .remove_item(todo_list, item_id)
                         .await
-                        .context("deleting item")
-                );
+                        .context("deleting item")?;
+                    Ok(Message::TodoListUpdated(todo_list))
+                });
             }
             Message::ToggleItemComplete => {
                 let State::ListView {
@@ -197,12 +218,16 @@ impl App {
                 let &item_id = items.get(selected_idx)?;
                 let item = todo_list.item_mut(item_id)?;
                 item.set_is_completed(!item.is_completed());
-                or_err_state!(
-                    todo_list
-                        .save(&self.connection)
+
+                let todo_list = todo_list.clone();
+                let database = self.database.clone();
+                self.spawn_operation(async move {
+                    let todo_list = database
+                        .save(todo_list)
                         .await
-                        .context("saving after toggle")
-                );
+                        .context("saving after toggle")?;
+                    Ok(Message::TodoListUpdated(todo_list))
+                });
             }
             Message::CommitTextInput => {
                 let State::TextInput { mode, buffer, .. } = &self.state else {
@@ -213,71 +238,127 @@ impl App {
                     return None;
                 };
 
-                let buffer = buffer.trim();
+                if let TextInputMode::Passphrase = mode {
+                    // spaces count in passphrases, and an empty one removes encryption
+                    if buffer.is_empty() {
+                        if self.key.is_none() {
+                            return Some(Message::CancelTextInput);
+                        }
+                        self.state = State::TextInput {
+                            mode: TextInputMode::ConfirmDecrypt,
+                            buffer: String::new(),
+                            cursor_pos: 0,
+                        };
+                        return None;
+                    }
+                    // rekeying would refuse it, and that failure would end the app; the dialog's title
+                    // already says why nothing happens, so stay in it for a stronger one
+                    if Strength::of(buffer) == Strength::Weak {
+                        return None;
+                    }
+                    let new = Key::Passphrase(buffer.clone());
+                    let current = self.key.clone();
+                    let keep_backups = self.keep_backups;
+                    let database = self.database.clone();
+                    self.spawn_operation(async move {
+                        let key = new.clone();
+                        database
+                            .call(move |connection| {
+                                crate::database::back_up(
+                                    connection,
+                                    current.as_ref(),
+                                    keep_backups,
+                                )?;
+                                let db_path = crate::database::file_path(connection)?;
+                                encryption::rekey(
+                                    connection,
+                                    db_path.as_path(),
+                                    current.as_ref(),
+                                    &new,
+                                )
+                            })
+                            .await
+                            .context("setting passphrase")?;
+                        Ok(Message::KeyChanged(Some(key)))
+                    });
+                    return None;
+                }
+
+                if let TextInputMode::ConfirmDecrypt = mode {
+                    let Some(current) = self.key.clone() else {
+                        return Some(Message::CancelTextInput);
+                    };
+                    if buffer.trim() != "decrypt" {
+                        return Some(Message::CancelTextInput);
+                    }
+
+                    let keep_backups = self.keep_backups;
+                    let database = self.database.clone();
+                    self.spawn_operation(async move {
+                        database
+                            .call(move |connection| {
+                                crate::database::back_up(connection, Some(&current), keep_backups)?;
+                                let db_path = crate::database::file_path(connection)?;
+                                encryption::remove_encryption(
+                                    connection,
+                                    db_path.as_path(),
+                                    &current,
+                                    true,
+                                )
+                            })
+                            .await
+                            .context("removing encryption")?;
+                        Ok(Message::KeyChanged(None))
+                    });
+                    return None;
+                }
+
+                let buffer = buffer.trim().to_owned();
                 if buffer.is_empty() {
                     // Empty input, just cancel
                     return Some(Message::CancelTextInput);
                 }
 
-                match mode {
-                    TextInputMode::NewList => {
-                        let todo_list = or_err_state!(
-                            todo_list::TodoList::new(&self.connection, buffer.to_owned())
+                let mode = mode.clone();
+                let database = self.database.clone();
+                self.spawn_operation(async move {
+                    let todo_list = match mode {
+                        TextInputMode::NewList => database
+                            .new_list(buffer)
+                            .await
+                            .context("creating new todo list")?,
+                        TextInputMode::NewItem { list_id } => {
+                            // Load the list, add item, then return to view
+                            let todo_list = database
+                                .load(list_id)
                                 .await
-                                .context("creating new todo list")
-                        );
-
-                        self.state = State::ListView {
-                            todo_list,
-                            item_list_state: ListState::default(),
-                        };
-                    }
-                    TextInputMode::NewItem { list_id } => {
-                        let list_id = *list_id;
-
-                        // Load the list, add item, then return to view
-                        let mut todo_list = or_err_state!(
-                            todo_list::TodoList::load(&self.connection, list_id)
+                                .context("loading list for new item")?;
+                            let (todo_list, _) = database
+                                .add_item(todo_list, buffer)
                                 .await
-                                .context("loading list for new item")
-                        );
-
-                        or_err_state!(
+                                .context("adding new item")?;
                             todo_list
-                                .add_item(&self.connection, buffer.to_string())
+                        }
+                        TextInputMode::Passphrase | TextInputMode::ConfirmDecrypt => {
+                            unreachable!("passphrases are handled before trimming")
+                        }
+                        TextInputMode::EditItem { list_id, item_id } => {
+                            let mut todo_list = database
+                                .load(list_id)
                                 .await
-                                .context("adding new item")
-                        );
-
-                        self.state = State::ListView {
-                            todo_list,
-                            item_list_state: ListState::default(),
-                        };
-                    }
-                    TextInputMode::EditItem { list_id, item_id } => {
-                        let list_id = *list_id;
-                        let item_id = *item_id;
-
-                        let mut todo_list = or_err_state!(
-                            todo_list::TodoList::load(&self.connection, list_id)
-                                .await
-                                .context("loading list for edit item")
-                        );
-
-                        let item = todo_list.item_mut(item_id)?;
-                        item.set_description(buffer.to_string());
-                        or_err_state!(
+                                .context("loading list for edit item")?;
                             todo_list
-                                .save(&self.connection)
+                                .item_mut(item_id)
+                                .ok_or_else(|| anyhow!("edited item no longer exists"))?
+                                .set_description(buffer);
+                            database
+                                .save(todo_list)
                                 .await
-                                .context("saving edited item")
-                        );
-                        self.state = State::ListView {
-                            todo_list,
-                            item_list_state: ListState::default(),
-                        };
-                    }
-                }
+                                .context("saving edited item")?
+                        }
+                    };
+                    Ok(Message::TodoListLoaded(todo_list))
+                });
             }
             Message::CancelTextInput => {
                 let State::TextInput { mode, .. } = &self.state else {
@@ -289,7 +370,9 @@ impl App {
                 };
 
                 match mode {
-                    TextInputMode::NewList => {
+                    TextInputMode::NewList
+                    | TextInputMode::Passphrase
+                    | TextInputMode::ConfirmDecrypt => {
                         // Go back to list select
                         return Some(Message::LoadTodos);
                     }
@@ -8,6 +8,7 @@ use ratatui::{
     text::{Line, Span},
     widgets::{Block, HighlightSpacing, List, ListDirection, Paragraph, Wrap},
 };
+use todo_list::encryption::Strength;
 
 use crate::tui_app::{App, State, TextInputMode};
 
@@ -28,6 +29,11 @@ impl App {
         // Render the main content in the top area (or full area if no logging)
         self.render_main_content(frame, main_area);
 
+        // Indicate when the database is working in the background
+        if self.is_busy() {
+            Self::render_busy_indicator(frame, main_area);
+        }
+
         // Render the logger widget in the bottom area if enabled
         if let Some(log_area) = log_area {
             self.render_logger(frame, log_area);
@@ -41,13 +47,19 @@ impl App {
             State::ListSelect {
                 labels, list_state, ..
             } => {
+                let title = if self.key.is_some() {
+                    "Select a Todo list (encrypted)"
+                } else {
+                    "Select a Todo list"
+                };
                 let block = Self::make_block(
-                    "Select a Todo list",
+                    title,
                     [
                         ("Navigate", "↑↓"),
                         ("Select", "enter"),
                         ("New", "n"),
                         ("Delete", "x"),
+                        ("Passphrase", "p"),
                         ("Quit", "q/esc"),
                     ],
                 );
@@ -125,16 +137,38 @@ impl App {
                 let modal_area = Self::centered_rect(60, area);
 
                 let title = match mode {
-                    TextInputMode::NewList => " Create New Todo List ",
-                    TextInputMode::NewItem { .. } => " Create New Item ",
-                    TextInputMode::EditItem { .. } => " Edit Item ",
+                    TextInputMode::NewList => " Create New Todo List ".into(),
+                    TextInputMode::NewItem { .. } => " Create New Item ".into(),
+                    TextInputMode::EditItem { .. } => " Edit Item ".into(),
+                    TextInputMode::Passphrase if buffer.is_empty() && self.key.is_some() => {
+                        " Change Passphrase (empty to decrypt) ".into()
+                    }
+                    TextInputMode::Passphrase if buffer.is_empty() => {
+                        " Set Passphrase to Encrypt ".into()
+                    }
+                    // weak passphrases aren't accepted, so say so before they are entered
+                    TextInputMode::Passphrase => {
+                        format!(" Passphrase Strength: {} ", Strength::of(buffer))
+                    }
+                    TextInputMode::ConfirmDecrypt => {
+                        " Type \"decrypt\" to Store the Database Unencrypted ".into()
+                    }
                 };
 
-                let block = Self::make_block(title, [("Confirm", "enter"), ("Cancel", "esc")])
+                let block = Self::make_block(&title, [("Confirm", "enter"), ("Cancel", "esc")])
                     .border_set(border::ROUNDED);
 
                 let cursor_pos = *cursor_pos;
 
+                // never show a passphrase
+                let masked;
+                let buffer = if let TextInputMode::Passphrase = mode {
+                    masked = "*".repeat(buffer.len());
+                    &masked
+                } else {
+                    buffer
+                };
+
                 // Create the text display with cursor
                 let text_with_cursor = if buffer.is_empty() {
                     vec![Line::from(vec![Span::styled(
@@ -176,6 +210,24 @@ impl App {
         }
     }
 
+    /// Render a busy indicator over the top right corner of the area
+    fn render_busy_indicator(frame: &mut Frame, area: Rect) {
+        let indicator = Line::from(" working… ".italic()).right_aligned();
+        let [top, _] = *Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).split(area)
+        else {
+            panic!("2 assignments must match 2 constraints")
+        };
+        let [_, corner, _] = *Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Length(indicator.width() as u16),
+            Constraint::Length(1),
+        ])
+        .split(top) else {
+            panic!("3 assignments must match 3 constraints")
+        };
+        frame.render_widget(indicator, corner);
+    }
+
     /// Render the logger widget
     fn render_logger(&self, frame: &mut Frame, area: Rect) {
         let logger_widget = tui_logger::TuiLoggerWidget::default()
@@ -2,17 +2,28 @@ use std::time::Duration;
 
 use anyhow::{Context as _, Result};
 use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
-use todo_list::TodoListId;
+use todo_list::{TodoList, TodoListId, encryption::Key};
 
-use crate::tui_app::State;
+use crate::tui_app::{App, State};
 
 pub(crate) enum Message {
     LoadTodos,
+    /// Background operation: all todo lists have been listed
+    TodosLoaded(Vec<(TodoListId, String)>),
+    /// Background operation: a todo list has been loaded and should be viewed from the top
+    TodoListLoaded(TodoList),
+    /// Background operation: the todo list currently in view has been modified
+    TodoListUpdated(TodoList),
+    /// Background operation: the database is now encrypted with this key, or not at all
+    KeyChanged(Option<Key>),
+    /// Background operation: something went wrong
+    OperationFailed(anyhow::Error),
     DecrementItem,
     IncrementItem,
     SelectTodoList(TodoListId),
     NewTodoList,
     DeleteList,
+    SetPassphrase,
     NewItem,
     EditItem,
     DeleteItem,
@@ -31,9 +42,18 @@ pub(crate) enum Message {
 }
 
 impl Message {
-    pub(crate) fn from_event(state: &State) -> Result<Option<Message>> {
-        // some states automatically transition
-        if let Some(message) = Self::automatic_state_transitions(state) {
+    pub(crate) fn from_event(app: &mut App) -> Result<Option<Message>> {
+        // results of background operations take priority
+        if let Some(message) = app.completed_operation() {
+            return Ok(Some(message));
+        }
+
+        let state = &app.state;
+
+        // some states automatically transition, but not while we wait for the transition to complete
+        if !app.is_busy()
+            && let Some(message) = Self::automatic_state_transitions(state)
+        {
             return Ok(Some(message));
         }
 
@@ -45,7 +65,10 @@ impl Message {
             return Ok(None);
         };
 
-        if let Some(message) = Self::stateful_keys(state, key_event) {
+        // while the database is busy, the state may be about to change underneath us, so only allow quitting
+        if !app.is_busy()
+            && let Some(message) = Self::stateful_keys(state, key_event)
+        {
             return Ok(Some(message));
         }
 
@@ -75,6 +98,7 @@ impl Message {
                 }
                 KeyCode::Char('n') => Some(Self::NewTodoList),
                 KeyCode::Char('x') => Some(Self::DeleteList),
+                KeyCode::Char('p') => Some(Self::SetPassphrase),
                 _ => None,
             },
             State::ListView { .. } => {
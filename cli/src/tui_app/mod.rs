@@ -2,4 +2,8 @@ mod app;
 mod message;
 mod state;
 
-pub(crate) use self::{app::App, message::Message, state::{State, TextInputMode}};
+pub(crate) use self::{
+    app::App,
+    message::Message,
+    state::{State, TextInputMode},
+};
@@ -39,6 +39,10 @@ pub(crate) enum TextInputMode {
         list_id: TodoListId,
         item_id: ItemId,
     },
+    /// Setting, changing, or removing the database's passphrase
+    Passphrase,
+    /// Confirming that the database should be stored unencrypted
+    ConfirmDecrypt,
 }
 
 impl State {
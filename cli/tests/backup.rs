@@ -0,0 +1,48 @@
+//! Backups taken by the CLI can be restored, and rotating them leaves other databases' backups alone.
+
+mod common;
+
+use common::{TempDir, add_list, run, titles};
+
+#[test]
+fn restoring_the_oldest_backup() {
+    let dir = TempDir::new();
+    let db = dir.db("todos.sqlite");
+    let keep = ["--keep-backups", "3"];
+
+    add_list(&db, "First");
+    for title in ["Second", "Third", "Fourth"] {
+        run(&db, &[&keep[..], &["backup"]].concat(), "");
+        add_list(&db, title);
+    }
+    let backups = dir.backups();
+    assert_eq!(backups.len(), 3);
+
+    let oldest = dir.0.join("backups").join(&backups[0]);
+    run(
+        &db,
+        &[&keep[..], &["restore", oldest.to_str().unwrap()]].concat(),
+        "",
+    );
+    assert_eq!(titles(&db), ["First"]);
+    // the current database was backed up too, without deleting anything
+    assert_eq!(dir.backups().len(), 4);
+    assert!(oldest.exists());
+}
+
+#[test]
+fn rotation_leaves_other_databases_backups_alone() {
+    let dir = TempDir::new();
+    let work = dir.db("todos-work.sqlite");
+    let todos = dir.db("todos.sqlite");
+
+    run(&work, &["backup"], "");
+    for _ in 0..2 {
+        run(&todos, &["--keep-backups", "1", "backup"], "");
+    }
+
+    let backups = dir.backups();
+    assert_eq!(backups.len(), 2, "{backups:?}");
+    assert!(backups[0].starts_with("todos-2"), "{backups:?}");
+    assert!(backups[1].starts_with("todos-work-"), "{backups:?}");
+}
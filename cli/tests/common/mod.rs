@@ -0,0 +1,82 @@
+//! Fixtures shared by the integration tests; each test uses only some of them.
+#![allow(dead_code)]
+
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use todo_list::Uuid;
+
+/// A directory in the temp dir holding a database and its backups, removed again when dropped
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("todo-list-cli-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(&dir).expect("creating temp dir");
+        Self(dir)
+    }
+
+    pub fn db(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    /// The backups of every database in the directory, oldest first for each
+    pub fn backups(&self) -> Vec<String> {
+        let mut names = std::fs::read_dir(self.0.join("backups"))
+            .expect("listing backups")
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run the CLI against the database at `db`, feeding it `stdin`
+pub fn run(db: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_todo-list"))
+        .arg("--db-path")
+        .arg(db)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("starting the cli");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().expect("running the cli");
+    assert!(
+        output.status.success(),
+        "{args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+pub fn add_list(db: &Path, title: &str) {
+    let markdown = format!("# {title}\n\n- [ ] something\n");
+    run(db, &["import", "--format", "markdown"], &markdown);
+}
+
+pub fn titles(db: &Path) -> Vec<String> {
+    let output = run(db, &["export", "--format", "markdown"], "");
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter_map(|line| line.strip_prefix("# "))
+        .map(ToOwned::to_owned)
+        .collect()
+}
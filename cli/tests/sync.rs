@@ -0,0 +1,39 @@
+//! Databases synced with the CLI end up with the same lists, and each resumes where it left off.
+
+mod common;
+
+use common::{TempDir, add_list, run, titles};
+use rusqlite::Connection;
+use sync_service::server::Server;
+
+fn sorted_titles(db: &std::path::Path) -> Vec<String> {
+    let mut titles = titles(db);
+    titles.sort();
+    titles
+}
+
+#[test]
+fn databases_converge_through_the_server() {
+    let server = Server::new("127.0.0.1:0", Connection::open_in_memory().unwrap())
+        .unwrap()
+        .spawn()
+        .unwrap();
+    let url = server.url();
+    let dir = TempDir::new();
+    let laptop = dir.db("laptop.sqlite");
+    let desktop = dir.db("desktop.sqlite");
+
+    add_list(&laptop, "Groceries");
+    run(&laptop, &["sync", &url], "");
+    add_list(&desktop, "Chores");
+    run(&desktop, &["sync", &url], "");
+    run(&laptop, &["sync", &url], "");
+    assert_eq!(sorted_titles(&laptop), ["Chores", "Groceries"]);
+    assert_eq!(sorted_titles(&desktop), ["Chores", "Groceries"]);
+
+    // the laptop remembered its cursor, so there is nothing new for it to pull
+    assert!(dir.db("laptop.sqlite.sync").exists());
+    let output = run(&laptop, &["sync", &url], "");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("pulled 0"), "{stderr}");
+}
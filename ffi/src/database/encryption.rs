@@ -1,36 +1,304 @@
 use crate::{Context as _, Database, Result};
 use anyhow::anyhow;
-use sqlite_wasm_vfs::relaxed_idb::{RelaxedIdbError, RelaxedIdbUtil};
+use rusqlite::{Connection, OptionalExtension as _};
+use todo_list::encryption::{DatabaseFile, Key, Stage};
 use wasm_bindgen::prelude::*;
 
-// SQLite magic header for unencrypted databases
-const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+use super::{Backend, Vfs};
 
-/// Check whether a database file is encrypted, without requiring an open connection to that database
-pub(super) fn db_file_is_encrypted(db_name: &str, vfs_util: &RelaxedIdbUtil) -> Result<bool> {
-    let maybe_data = vfs_util.export_db(db_name);
-    if let Err(RelaxedIdbError::Generic(ref msg)) = maybe_data
-        && msg.ends_with("does not exist")
-    {
-        return Ok(false);
+/// Plaintext database beside the others in each backend, remembering each encrypted database's cipher configuration
+/// and page size.
+///
+/// An encrypted database can't say how it was encrypted, or how big its pages are: its header is encrypted along with
+/// everything else.
+pub(super) const CIPHER_REGISTRY: &str = ".todo-list-ciphers";
+
+/// A cipher scheme to encrypt a database with
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Aes128Cbc = "aes128cbc",
+    Aes256Cbc = "aes256cbc",
+    ChaCha20 = "chacha20",
+    SqlCipher = "sqlcipher",
+    Rc4 = "rc4",
+    Ascon128 = "ascon128",
+    Aegis = "aegis",
+}
+
+impl TryFrom<Cipher> for todo_list::encryption::Cipher {
+    type Error = crate::Error;
+
+    fn try_from(cipher: Cipher) -> Result<Self> {
+        Ok(match cipher {
+            Cipher::Aes128Cbc => Self::Aes128Cbc,
+            Cipher::Aes256Cbc => Self::Aes256Cbc,
+            Cipher::ChaCha20 => Self::ChaCha20,
+            Cipher::SqlCipher => Self::SqlCipher,
+            Cipher::Rc4 => Self::Rc4,
+            Cipher::Ascon128 => Self::Ascon128,
+            Cipher::Aegis => Self::Aegis,
+            // wasm-bindgen's catch-all for strings from JS which aren't ciphers
+            Cipher::__Invalid => return Err(anyhow!("unknown cipher").into()),
+        })
     }
-    let data = maybe_data
-        .map_err(|err| anyhow!("{err}"))
-        .context("exporting database to check header")?;
+}
 
-    if data.len() <= SQLITE_MAGIC.len() {
-        return Err(anyhow!("database file present but shorter than SQLITE_MAGIC").into());
+/// How a database is encrypted.
+///
+/// See `todo_list::encryption::CipherConfig` for what each parameter means.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct CipherConfig {
+    pub cipher: Cipher,
+    /// Iterations of the cipher's key derivation function, if not its default
+    pub kdf_iter: Option<u32>,
+    /// Which older version of the cipher's library to be compatible with, if any
+    pub legacy: Option<u32>,
+}
+
+#[wasm_bindgen]
+impl CipherConfig {
+    #[wasm_bindgen(constructor)]
+    pub fn new(cipher: Cipher, kdf_iter: Option<u32>, legacy: Option<u32>) -> Self {
+        Self {
+            cipher,
+            kdf_iter,
+            legacy,
+        }
     }
+}
+
+impl TryFrom<CipherConfig> for todo_list::encryption::CipherConfig {
+    type Error = crate::Error;
+
+    fn try_from(config: CipherConfig) -> Result<Self> {
+        Ok(Self {
+            cipher: config.cipher.try_into()?,
+            kdf_iter: config.kdf_iter,
+            legacy: config.legacy,
+        })
+    }
+}
+
+fn cipher_registry(vfs: Vfs) -> Result<Connection> {
+    let connection = vfs
+        .open(CIPHER_REGISTRY)
+        .context("opening cipher registry")?;
+    connection
+        .execute_batch(
+            "CREATE TABLE IF NOT EXISTS cipher_configs (
+                name TEXT PRIMARY KEY NOT NULL,
+                cipher TEXT NOT NULL,
+                kdf_iter INTEGER,
+                legacy INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS page_sizes (
+                name TEXT PRIMARY KEY NOT NULL,
+                page_size INTEGER NOT NULL
+            )",
+        )
+        .context("creating cipher registry")?;
+    Ok(connection)
+}
 
-    let unencrypted = data.starts_with(SQLITE_MAGIC);
-    Ok(!unencrypted)
+/// The cipher configuration the named database was last encrypted with, if it isn't the default
+pub(super) fn remembered_config(
+    vfs: Vfs,
+    db_name: &str,
+) -> Result<Option<todo_list::encryption::CipherConfig>> {
+    let remembered = cipher_registry(vfs)?
+        .query_row(
+            "SELECT cipher, kdf_iter, legacy FROM cipher_configs WHERE name = ?1",
+            [db_name],
+            |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .context("reading cipher registry")?;
+    let Some((cipher, kdf_iter, legacy)) = remembered else {
+        return Ok(None);
+    };
+    Ok(Some(todo_list::encryption::CipherConfig {
+        cipher: cipher.parse().context("parsing remembered cipher")?,
+        kdf_iter,
+        legacy,
+    }))
 }
 
-/// Check whether a particular database is encrypted by its filename, without requiring an open connection to that database
+/// Remember the cipher configuration the named database is encrypted with, or forget it if it isn't encrypted
+pub(super) fn remember_config(
+    vfs: Vfs,
+    db_name: &str,
+    config: Option<todo_list::encryption::CipherConfig>,
+) -> Result<()> {
+    let registry = cipher_registry(vfs)?;
+    match config {
+        Some(config) => registry.execute(
+            "INSERT OR REPLACE INTO cipher_configs (name, cipher, kdf_iter, legacy) VALUES (?1, ?2, ?3, ?4)",
+            (db_name, config.cipher.name(), config.kdf_iter, config.legacy),
+        ),
+        None => registry.execute("DELETE FROM cipher_configs WHERE name = ?1", [db_name]),
+    }
+    .context("writing cipher registry")?;
+    Ok(())
+}
+
+/// The page size the named encrypted database was last opened or encrypted with, if it has been since it was stored
+pub(super) fn remembered_page_size(vfs: Vfs, db_name: &str) -> Result<Option<usize>> {
+    let page_size = cipher_registry(vfs)?
+        .query_row(
+            "SELECT page_size FROM page_sizes WHERE name = ?1",
+            [db_name],
+            |row| row.get::<_, u32>(0),
+        )
+        .optional()
+        .context("reading page size from cipher registry")?;
+    page_size
+        .map(usize::try_from)
+        .transpose()
+        .context("page size out of range")
+}
+
+/// Remember the page size of the named database, or forget it if it isn't encrypted
+pub(super) fn remember_page_size(vfs: Vfs, db_name: &str, page_size: Option<usize>) -> Result<()> {
+    let registry = cipher_registry(vfs)?;
+    match page_size {
+        Some(page_size) => registry.execute(
+            "INSERT OR REPLACE INTO page_sizes (name, page_size) VALUES (?1, ?2)",
+            (
+                db_name,
+                u32::try_from(page_size).context("page size out of range")?,
+            ),
+        ),
+        None => registry.execute("DELETE FROM page_sizes WHERE name = ?1", [db_name]),
+    }
+    .context("writing page size to cipher registry")?;
+    Ok(())
+}
+
+/// The page size of the database behind `connection`, which must be unlocked if it is encrypted
+pub(super) fn page_size(connection: &Connection) -> Result<usize> {
+    let page_size = connection
+        .pragma_query_value(None, "page_size", |row| row.get::<_, u32>(0))
+        .context("reading page size")?;
+    usize::try_from(page_size).context("page size out of range")
+}
+
+/// How strong a passphrase is, judged by how long it would take to guess
 #[wasm_bindgen]
-pub async fn db_is_encrypted(db_name: &str) -> Result<bool> {
-    let vfs_util = super::get_vfs_util().await?;
-    db_file_is_encrypted(db_name, &vfs_util)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassphraseStrength {
+    /// Too easily guessed to encrypt a database with
+    Weak = "weak",
+    Fair = "fair",
+    Strong = "strong",
+}
+
+impl From<todo_list::encryption::Strength> for PassphraseStrength {
+    fn from(strength: todo_list::encryption::Strength) -> Self {
+        use todo_list::encryption::Strength;
+        match strength {
+            Strength::Weak => Self::Weak,
+            Strength::Fair => Self::Fair,
+            Strength::Strong => Self::Strong,
+        }
+    }
+}
+
+/// Judge how strong a passphrase is, as `Database.set_key` will.
+///
+/// Weak passphrases are refused; this lets a form say so while the passphrase is typed.
+#[wasm_bindgen]
+pub fn passphrase_strength(passphrase: &str) -> PassphraseStrength {
+    todo_list::encryption::Strength::of(passphrase).into()
+}
+
+/// A database in a backend, backed up beside itself with a `.pre-rekey` suffix while its key changes
+struct VfsFile<'a> {
+    name: &'a str,
+    vfs: Vfs,
+    /// Needed to import the backup again, as an encrypted file can't tell it
+    page_size: usize,
+    /// The cipher configuration before the key changes, if not the default
+    before: Option<todo_list::encryption::CipherConfig>,
+    /// The cipher configuration after the key changes, if not the default
+    after: Option<todo_list::encryption::CipherConfig>,
+}
+
+impl VfsFile<'_> {
+    fn backup_name(&self) -> String {
+        format!("{}.pre-rekey", self.name)
+    }
+}
+
+// changes are visible as soon as they are made, so these don't wait for IndexedDB to store them
+impl DatabaseFile for VfsFile<'_> {
+    fn open(&self, stage: Stage) -> anyhow::Result<Connection> {
+        let connection = self.vfs.open(self.name).context("VfsFile::open")?;
+        let config = match stage {
+            Stage::Before => self.before,
+            Stage::After => self.after,
+        };
+        if let Some(config) = config {
+            config
+                .apply(&connection)
+                .context("VfsFile::open: configuring cipher")?;
+        }
+        Ok(connection)
+    }
+
+    fn back_up(&self) -> anyhow::Result<()> {
+        let data = self.vfs.export_db(self.name).context("VfsFile::back_up")?;
+        let backup = self.backup_name();
+        if self.vfs.exists(&backup)? {
+            self.vfs
+                .delete_db(&backup)
+                .context("VfsFile::back_up: deleting earlier backup")?;
+        }
+        self.vfs
+            .import_db(&backup, &data, Some(self.page_size))
+            .context("VfsFile::back_up")?;
+        Ok(())
+    }
+
+    fn restore(&self) -> anyhow::Result<()> {
+        let data = self
+            .vfs
+            .export_db(&self.backup_name())
+            .context("VfsFile::restore: reading backup")?;
+        self.vfs.delete_db(self.name).context("VfsFile::restore")?;
+        self.vfs
+            .import_db(self.name, &data, Some(self.page_size))
+            .context("VfsFile::restore")?;
+        self.remove_backup()
+    }
+
+    fn remove_backup(&self) -> anyhow::Result<()> {
+        self.vfs
+            .delete_db(&self.backup_name())
+            .context("VfsFile::remove_backup")?;
+        Ok(())
+    }
+}
+
+/// Check whether a database file is encrypted, without requiring an open connection to that database
+pub(super) fn db_file_is_encrypted(db_name: &str, vfs: Vfs) -> Result<bool> {
+    if !vfs.exists(db_name).context("checking for database")? {
+        return Ok(false);
+    }
+    let header = vfs
+        .read_head(db_name, todo_list::encryption::SQLITE_MAGIC.len())
+        .context("reading database header")?;
+    todo_list::encryption::is_encrypted(&header).context("checking database header")
+}
+
+/// Check whether a particular database is encrypted by its filename, without requiring an open connection to that database.
+///
+/// `backend` is where the database is stored, IndexedDB by default.
+#[wasm_bindgen]
+pub async fn db_is_encrypted(db_name: &str, backend: Option<Backend>) -> Result<bool> {
+    let vfs = Vfs::get(backend).await?;
+    db_file_is_encrypted(db_name, vfs)
 }
 
 impl Database {
@@ -45,15 +313,72 @@ impl Database {
     /// The encryption key is derived from the passphrase in a mechanism distinct to the cipher in use.
     ///
     /// Returns an error if the database key was incorrect.
-    pub(super) fn decrypt(&self, passphrase: &str) -> Result<()> {
-        self.connection
-            .pragma_update(None, "key", passphrase)
-            .context("setting pragma key")?;
-        // the pragma itself gives no indication of whether or not the encryption key was correct.
-        // its documentation suggests this as a simple fast query which can determine if decryption works.
-        self.connection
-            .execute("SELECT * FROM sqlite_master LIMIT 0", [])
-            .context("executing sample query failed; check the encryption key")?;
+    pub(super) fn decrypt(
+        &mut self,
+        key: Key,
+        config: Option<todo_list::encryption::CipherConfig>,
+    ) -> Result<()> {
+        if let Some(config) = config {
+            config
+                .apply(self.connection()?)
+                .context("configuring cipher")?;
+        }
+        todo_list::encryption::unlock(self.connection()?, &key)
+            .context("unlocking database; check the encryption key")?;
+        self.key = Some(key);
+        Ok(())
+    }
+
+    /// Re-encrypt the database with `new`, or decrypt it.
+    ///
+    /// The database is backed up beside itself while its key changes, and put back if the new key doesn't open it.
+    fn rekey(&mut self, new: Option<Key>, config: Option<CipherConfig>) -> Result<()> {
+        let config = config
+            .map(todo_list::encryption::CipherConfig::try_from)
+            .transpose()?;
+        let before = match self.key {
+            Some(_) => remembered_config(self.vfs, &self.name)
+                .context("looking up the database's cipher configuration")?,
+            None => None,
+        };
+        if let Some(config) = config {
+            config
+                .apply(self.connection()?)
+                .context("configuring cipher")?;
+        }
+        let page_size = page_size(self.connection()?)?;
+
+        let connection = self
+            .connection
+            .as_mut()
+            .ok_or_else(|| super::closed(&self.name))?;
+        let file = VfsFile {
+            name: &self.name,
+            vfs: self.vfs,
+            page_size,
+            before,
+            after: new.as_ref().and(config.or(before)),
+        };
+        match (&new, &self.key) {
+            (Some(new), current) => {
+                todo_list::encryption::rekey(connection, &file, current.as_ref(), new)
+            }
+            (None, Some(current)) => {
+                todo_list::encryption::remove_encryption(connection, &file, current, true)
+            }
+            (None, None) => return Ok(()),
+        }
+        .context("rekeying database")?;
+        let encrypted = new.is_some();
+        self.key = new;
+
+        if !encrypted || config.is_some() {
+            remember_config(self.vfs, &self.name, config.filter(|_| encrypted)).context(
+                "remembering the database's cipher configuration; pass it explicitly when connecting",
+            )?;
+        }
+        remember_page_size(self.vfs, &self.name, encrypted.then_some(page_size))
+            .context("remembering the database's page size")?;
         Ok(())
     }
 }
@@ -62,27 +387,50 @@ impl Database {
 impl Database {
     /// Set the encryption key for the database.
     ///
-    /// This updates the stored data such that it is all encrypted with the key derived from teh provided passphrase.
+    /// This updates the stored data such that it is all encrypted with the key derived from the provided passphrase.
     ///
     /// The passphrase is not the actual encryption key.
     /// The encryption key is derived from the passphrase in a mechanism distinct to the cipher in use.
     ///
-    /// This operation has three use cases:
+    /// This operation has two use cases:
     ///
     ///   1. Encrypt an existing unencrypted database
     ///   2. Change the encryption key of an existing encrypted database.
-    ///   3. Remove encryption from an existing encrypted database.
     ///
-    /// Removing encryption is accomplished by providing an empty passphrase.
-    pub fn set_key(&self, passphrase: &str) -> Result<()> {
-        self.connection
-            .pragma_update(None, "rekey", passphrase)
-            .context("rekeying database")?;
-        Ok(())
+    /// Empty passphrases and weak ones, as judged by `passphrase_strength`, are refused. To remove encryption, use
+    /// `remove_key`.
+    ///
+    /// `config` chooses the cipher to encrypt with, and is remembered for connecting later. Without it, an encrypted
+    /// database keeps its cipher, and an unencrypted one gets the default.
+    pub fn set_key(&mut self, passphrase: &str, config: Option<CipherConfig>) -> Result<()> {
+        self.rekey(Some(Key::Passphrase(passphrase.to_owned())), config)
+    }
+
+    /// Remove encryption from the database, leaving it readable by anyone with access to the browser's storage.
+    ///
+    /// That can't be undone for copies made in the meantime, so `confirm` must be `true`; otherwise this fails
+    /// without changing anything.
+    pub fn remove_key(&mut self, confirm: bool) -> Result<()> {
+        if self.key.is_none() {
+            return Ok(());
+        }
+        if !confirm {
+            return Err(anyhow!("removing encryption must be confirmed").into());
+        }
+        self.rekey(None, None)
+    }
+
+    /// Encrypt the database with a raw 256-bit key, given as 64 hex digits, instead of a passphrase.
+    ///
+    /// The key is used as it is, without deriving anything from it; this suits keys kept in a keyring or hardware.
+    /// `config` works as it does for `set_key`.
+    pub fn set_raw_key(&mut self, hex_key: &str, config: Option<CipherConfig>) -> Result<()> {
+        let key = Key::from_hex(hex_key).context("parsing raw key")?;
+        self.rekey(Some(key), config)
     }
 
     /// Check if the database is encrypted by examining the first 16 bytes.
     pub fn is_encrypted(&self) -> Result<bool> {
-        db_file_is_encrypted(&self.name, &self.vfs_util)
+        db_file_is_encrypted(&self.name, self.vfs)
     }
 }
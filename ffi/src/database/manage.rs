@@ -0,0 +1,200 @@
+//! Managing the databases stored in a backend, without connecting to them.
+//!
+//! Every function takes the `backend` to manage, IndexedDB by default. None of them check whether a database is
+//! connected; deleting or renaming one which is leaves its connection writing to a file nobody can open again.
+
+use anyhow::anyhow;
+use wasm_bindgen::prelude::*;
+
+use crate::{Context as _, Error, ErrorCode, Result};
+
+use super::{Backend, Vfs, encryption};
+
+/// Suffixes of files kept beside databases, which aren't databases of their own
+const ANCILLARY_SUFFIXES: [&str; 4] = ["-journal", "-wal", "-shm", ".pre-rekey"];
+
+/// A database stored in a backend
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct DatabaseInfo {
+    /// The name to connect to it by
+    pub name: String,
+    /// Its size in bytes
+    pub size: usize,
+    /// Whether it is encrypted, and so must be connected to with a key
+    pub encrypted: bool,
+}
+
+/// Whether `name` is a file of this crate's own rather than a database
+fn is_hidden(name: &str) -> bool {
+    name == encryption::CIPHER_REGISTRY
+        || ANCILLARY_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+/// Write `bytes` into the backend as the database `name`, which must not exist yet, returning whether it is encrypted.
+///
+/// An encrypted database's header can't tell its page size, so it needs to be given one, which is remembered.
+pub(super) async fn import_bytes(
+    vfs: Vfs,
+    name: &str,
+    bytes: &[u8],
+    page_size: Option<usize>,
+) -> Result<bool> {
+    if is_hidden(name) {
+        return Err(anyhow!("{name} is reserved").into());
+    }
+    if bytes.is_empty() {
+        return Err(anyhow!("an empty file is not a database").into());
+    }
+    if vfs.exists(name).context("checking for database")? {
+        return Err(Error::new(
+            ErrorCode::AlreadyExists,
+            format!("a database named {name} already exists"),
+        ));
+    }
+
+    let encrypted =
+        todo_list::encryption::is_encrypted(bytes).context("checking database header")?;
+    let page_size = match (encrypted, page_size) {
+        (false, _) => None,
+        (true, Some(page_size)) => Some(page_size),
+        (true, None) => {
+            return Err(anyhow!(
+                "{name} is encrypted, so its page size can't be read from it and must be given"
+            )
+            .into());
+        }
+    };
+    vfs.import_db(name, bytes, page_size)
+        .context("importing database")?
+        .stored()
+        .await
+        .context("storing imported database")?;
+    encryption::remember_page_size(vfs, name, page_size)
+        .context("remembering the database's page size")?;
+    Ok(encrypted)
+}
+
+fn export(vfs: Vfs, name: &str) -> Result<Vec<u8>> {
+    if is_hidden(name) || !vfs.exists(name).context("checking for database")? {
+        return Err(Error::new(
+            ErrorCode::NotFound,
+            format!("no database named {name}"),
+        ));
+    }
+    vfs.export_db(name).context("exporting database")
+}
+
+/// Copy the database `from` in one backend to `to` in another, along with its remembered cipher configuration.
+///
+/// An encrypted database is copied with `page_size` if given, or else the page size remembered for it; it is an
+/// error if there is neither, as it hasn't been opened since it was stored.
+pub(super) async fn copy(
+    from_vfs: Vfs,
+    from: &str,
+    to_vfs: Vfs,
+    to: &str,
+    page_size: Option<usize>,
+) -> Result<()> {
+    let bytes = export(from_vfs, from)?;
+    let config = encryption::remembered_config(from_vfs, from)
+        .context("reading the cipher configuration")?;
+    let page_size = match page_size {
+        Some(page_size) => Some(page_size),
+        None => {
+            encryption::remembered_page_size(from_vfs, from).context("reading the page size")?
+        }
+    };
+    if page_size.is_none()
+        && todo_list::encryption::is_encrypted(&bytes).context("checking database header")?
+    {
+        return Err(anyhow!(
+            "{from}'s page size isn't known; connect to it with its key once before copying it"
+        )
+        .into());
+    }
+    import_bytes(to_vfs, to, &bytes, page_size).await?;
+    encryption::remember_config(to_vfs, to, config)
+        .context("remembering the copy's cipher configuration")?;
+    Ok(())
+}
+
+/// List the databases stored in a backend, sorted by name
+#[wasm_bindgen]
+pub async fn list_databases(backend: Option<Backend>) -> Result<Vec<DatabaseInfo>> {
+    let vfs = Vfs::get(backend).await?;
+    let mut names = vfs.list();
+    names.retain(|name| !is_hidden(name));
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let size = vfs
+                .file_size(&name)
+                .context(format!("getting the size of {name}"))?;
+            let encrypted = encryption::db_file_is_encrypted(&name, vfs)
+                .context(format!("checking whether {name} is encrypted"))?;
+            Ok(DatabaseInfo {
+                name,
+                size,
+                encrypted,
+            })
+        })
+        .collect()
+}
+
+/// Delete a database, along with its remembered cipher configuration.
+///
+/// Nothing may be connected to it.
+#[wasm_bindgen]
+pub async fn delete_database(name: &str, backend: Option<Backend>) -> Result<()> {
+    let vfs = Vfs::get(backend).await?;
+    if is_hidden(name) || !vfs.exists(name).context("checking for database")? {
+        return Err(Error::new(
+            ErrorCode::NotFound,
+            format!("no database named {name}"),
+        ));
+    }
+    vfs.delete_db(name)
+        .context("deleting database")?
+        .stored()
+        .await
+        .context("storing deleted database")?;
+    encryption::remember_config(vfs, name, None)
+        .context("forgetting the database's cipher configuration")?;
+    encryption::remember_page_size(vfs, name, None)
+        .context("forgetting the database's page size")?;
+    Ok(())
+}
+
+/// Copy a database to a new name, along with its remembered cipher configuration.
+///
+/// An encrypted copy opens with the same key as the original.
+#[wasm_bindgen]
+pub async fn copy_database(from: &str, to: &str, backend: Option<Backend>) -> Result<()> {
+    let vfs = Vfs::get(backend).await?;
+    copy(vfs, from, vfs, to, None).await
+}
+
+/// Rename a database, which must not be connected to.
+#[wasm_bindgen]
+pub async fn rename_database(from: &str, to: &str, backend: Option<Backend>) -> Result<()> {
+    copy_database(from, to, backend).await?;
+    delete_database(from, backend).await
+}
+
+/// Copy a database from one backend to another under the same name, which must not be in use there yet.
+///
+/// The original is left where it was; delete it with `delete_database` once the copy is known to work.
+#[wasm_bindgen]
+pub async fn migrate_database(name: &str, from: Backend, to: Backend) -> Result<()> {
+    if from == to {
+        return Err(anyhow!("{name} is stored in that backend already").into());
+    }
+    let from_vfs = Vfs::get(Some(from)).await?;
+    let to_vfs = Vfs::get(Some(to)).await?;
+    copy(from_vfs, name, to_vfs, name, None).await
+}
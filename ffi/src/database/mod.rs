@@ -1,106 +1,225 @@
 mod encryption;
+mod manage;
+mod open;
+mod vfs;
 
-use std::sync::LazyLock;
+pub use encryption::{Cipher, CipherConfig, PassphraseStrength};
+pub use manage::DatabaseInfo;
+pub use open::OpenOptions;
+pub use vfs::Backend;
 
-use crate::{Context as _, Result};
-use anyhow::anyhow;
+use crate::{Context as _, Error, ErrorCode, Result};
 use rusqlite::Connection;
-use sqlite_wasm_rs::WasmOsCallback;
-use sqlite_wasm_vfs::relaxed_idb::{self, RelaxedIdbCfg, RelaxedIdbUtil};
+use todo_list::encryption::Key;
+use vfs::Vfs;
 
 use wasm_bindgen::prelude::*;
 
-static RUSQLITE_FLAGS: LazyLock<rusqlite::OpenFlags> = LazyLock::new(|| {
-    rusqlite::OpenFlags::SQLITE_OPEN_CREATE | rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE
-});
-const VFS_NAME: &str = "multipleciphers-relaxed-idb";
-
-/// Get the VFS utility by reinstalling the VFS
-// note: `RelaxedIdbCfg` sets values including the name, which gets used as the IDB database name
-async fn get_vfs_util() -> Result<RelaxedIdbUtil> {
-    relaxed_idb::install::<WasmOsCallback>(&RelaxedIdbCfg::default(), false)
-        .await
-        .map_err(|err| anyhow!("{err}"))
-        .context("failed to install relaxed-idb vfs")
+/// The error for using a database after closing it
+fn closed(name: &str) -> crate::Error {
+    crate::Error::new(
+        ErrorCode::Closed,
+        format!("database {name} is closed; connect to it again to use it"),
+    )
 }
 
-/// A connection to a database
+/// A connection to a database.
+///
+/// Call `close` when done with it. Calling `free` instead also closes it, but then any further call throws from
+/// wasm-bindgen's glue code rather than explaining itself.
 #[wasm_bindgen]
 pub struct Database {
-    pub(crate) connection: Connection,
-    /// The name of the database, used as the path in IndexedDB
+    /// The connection, until the database is closed
+    connection: Option<Connection>,
+    /// The name of the database, used as its path in the backend
     name: String,
-    /// VFS utils
-    vfs_util: RelaxedIdbUtil,
+    /// The backend the database is stored in
+    vfs: Vfs,
+    /// The key the database is unlocked with, if it is encrypted
+    key: Option<Key>,
 }
 
 #[wasm_bindgen]
 impl Database {
-    /// Connect to an unencrypted database
-    pub async fn connect(name: &str) -> Result<Self> {
-        let vfs_util = get_vfs_util().await?;
+    /// Connect to an unencrypted database, stored in `backend`, or IndexedDB by default
+    pub async fn connect(name: &str, backend: Option<Backend>) -> Result<Self> {
+        let vfs = Vfs::get(backend).await?;
 
-        if encryption::db_file_is_encrypted(name, &vfs_util)
+        if encryption::db_file_is_encrypted(name, vfs)
             .context("checking whether db file is encrypted")?
         {
-            return Err(anyhow!(
-                "database file is encrypted but no key was provided when connecting"
-            )
-            .into());
+            return Err(Error::new(
+                ErrorCode::EncryptedDbNoKey,
+                "database file is encrypted but no key was provided when connecting",
+            ));
         }
 
-        let connection =
-            rusqlite::Connection::open_with_flags_and_vfs(name, *RUSQLITE_FLAGS, VFS_NAME)
-                .context("opening database connection")?;
+        let connection = vfs.open(name)?;
         Ok(Self {
-            connection,
+            connection: Some(connection),
             name: name.to_string(),
-            vfs_util,
+            vfs,
+            key: None,
         })
     }
 
-    /// Connect to an encrypted database
-    pub async fn connect_with_key(name: &str, passphrase: &str) -> Result<Self> {
-        let vfs_util = get_vfs_util().await?;
+    /// Connect to an encrypted database.
+    ///
+    /// `config` is the cipher configuration the database was encrypted with. It only needs to be given once: it is
+    /// remembered when unlocking with it works, and the remembered one is used when it is left out.
+    ///
+    /// `backend` works as it does for `connect`.
+    pub async fn connect_with_key(
+        name: &str,
+        passphrase: &str,
+        config: Option<CipherConfig>,
+        backend: Option<Backend>,
+    ) -> Result<Self> {
+        let vfs = Vfs::get(backend).await?;
+        Self::connect_encrypted(vfs, name, Key::Passphrase(passphrase.to_owned()), config)
+    }
+
+    /// Connect to a database encrypted with a raw 256-bit key, given as 64 hex digits.
+    ///
+    /// `config` and `backend` work as they do for `connect_with_key`.
+    pub async fn connect_with_raw_key(
+        name: &str,
+        hex_key: &str,
+        config: Option<CipherConfig>,
+        backend: Option<Backend>,
+    ) -> Result<Self> {
+        let key = Key::from_hex(hex_key).context("parsing raw key")?;
+        let vfs = Vfs::get(backend).await?;
+        Self::connect_encrypted(vfs, name, key, config)
+    }
+
+    /// Get the database's name.
+    ///
+    /// This is equivalent to its path in its backend.
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// The backend the database is stored in
+    pub fn backend(&self) -> Backend {
+        self.vfs.backend()
+    }
+
+    /// Close the database, writing everything out to its backend.
+    ///
+    /// Anything done with it afterwards fails, saying it is closed. Closing it again does nothing.
+    pub fn close(&mut self) -> Result<()> {
+        let Some(connection) = self.connection.take() else {
+            return Ok(());
+        };
+        if let Err((connection, err)) = connection.close() {
+            self.connection = Some(connection);
+            return Err(anyhow::Error::from(err).context("closing database").into());
+        }
+        Ok(())
+    }
+
+    /// Whether `close` has been called
+    pub fn is_closed(&self) -> bool {
+        self.connection.is_none()
+    }
+
+    /// Export the database contents as raw bytes.
+    pub fn export(&self) -> Result<Vec<u8>> {
+        self.vfs.export_db(&self.name).context("exporting database")
+    }
+
+    /// Copy the database into `backend`, IndexedDB by default, as `name`, which must not be in use there yet.
+    ///
+    /// This is how a database in the memory backend is kept after all. The copy is encrypted just as this database
+    /// is, and its cipher configuration is remembered; this connection carries on using the original.
+    pub async fn persist(&self, name: &str, backend: Option<Backend>) -> Result<()> {
+        // the file may have been deleted or replaced since closing
+        let page_size = encryption::page_size(self.connection()?)?;
+        let vfs = Vfs::get(backend).await?;
+        manage::copy(self.vfs, &self.name, vfs, name, Some(page_size)).await
+    }
+
+    /// Import a database from raw bytes, as `export` or the CLI produce, under a name not yet in use.
+    ///
+    /// Unencrypted databases must be valid SQLite files; with `migrate`, their schema is also brought up to date.
+    /// Anything else is taken to be encrypted, and needs its `page_size`, as `PRAGMA page_size` reported it where it
+    /// came from: its header is encrypted, so it can't tell. It is migrated by `apply_schema` after connecting with
+    /// its key, and its cipher configuration, if not the default, is given then too.
+    ///
+    /// `backend` works as it does for `connect`.
+    pub async fn import(
+        name: &str,
+        bytes: &[u8],
+        migrate: bool,
+        backend: Option<Backend>,
+        page_size: Option<usize>,
+    ) -> Result<()> {
+        let vfs = Vfs::get(backend).await?;
+        let encrypted = manage::import_bytes(vfs, name, bytes, page_size).await?;
+        // a database which had this name before may have been encrypted differently
+        encryption::remember_config(vfs, name, None)
+            .context("forgetting the cipher configuration of an earlier database of this name")?;
+
+        if migrate && !encrypted {
+            let database = Self::connect(name, backend).await?;
+            todo_list::apply_schema(database.connection()?)
+                .await
+                .map_err(|err| {
+                    Error::from(err).with_code(ErrorCode::Schema, "migrating imported database")
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl Database {
+    /// The connection to the database, unless it has been closed
+    pub(crate) fn connection(&self) -> Result<&Connection> {
+        self.connection.as_ref().ok_or_else(|| closed(&self.name))
+    }
 
-        if !encryption::db_file_is_encrypted(name, &vfs_util)
+    fn connect_encrypted(
+        vfs: Vfs,
+        name: &str,
+        key: Key,
+        config: Option<CipherConfig>,
+    ) -> Result<Self> {
+        if !encryption::db_file_is_encrypted(name, vfs)
             .context("checking whether db file is encrypted")?
         {
-            return Err(anyhow!(
-                "database file is not encrypted but a key was provided when connecting"
-            )
-            .into());
+            return Err(Error::new(
+                ErrorCode::UnencryptedDbKey,
+                "database file is not encrypted but a key was provided when connecting",
+            ));
         }
 
-        let connection =
-            rusqlite::Connection::open_with_flags_and_vfs(name, *RUSQLITE_FLAGS, VFS_NAME)
-                .context("opening database connection")?;
+        let connection = vfs.open(name)?;
 
-        let database = Self {
-            connection,
+        let mut database = Self {
+            connection: Some(connection),
             name: name.to_string(),
-            vfs_util,
+            vfs,
+            key: None,
         };
 
+        let config = config
+            .map(todo_list::encryption::CipherConfig::try_from)
+            .transpose()?;
+        let remembered = encryption::remembered_config(vfs, name)
+            .context("looking up the database's cipher configuration")?;
         database
-            .decrypt(passphrase)
+            .decrypt(key, config.or(remembered))
             .context("decrypting database during initialization")?;
+        if config.is_some() {
+            encryption::remember_config(vfs, name, config)
+                .context("remembering the database's cipher configuration")?;
+        }
+        // copying the file needs it, and only an unlocked connection can tell
+        let page_size = encryption::page_size(database.connection()?)?;
+        encryption::remember_page_size(vfs, name, Some(page_size))
+            .context("remembering the database's page size")?;
 
         Ok(database)
     }
-
-    /// Get the database's name.
-    ///
-    /// This is equivalent to its path in IndexedDB.
-    pub fn name(&self) -> String {
-        self.name.clone()
-    }
-
-    /// Export the database contents as raw bytes.
-    pub fn export(&self) -> Result<Vec<u8>> {
-        self.vfs_util
-            .export_db(&self.name)
-            .map_err(|err| anyhow!("{err}"))
-            .context("exporting database from relaxed-idb")
-    }
 }
@@ -0,0 +1,139 @@
+//! Connecting to a database without knowing beforehand whether it is encrypted.
+
+use todo_list::encryption::Key;
+use wasm_bindgen::{JsCast as _, prelude::*};
+use wasm_bindgen_futures::JsFuture;
+
+use crate::{Context as _, Error, ErrorCode, Result};
+
+use super::{Backend, CipherConfig, Database, Vfs, encryption};
+
+/// Where `Database.open` finds a database, and how it unlocks one which turns out to be encrypted
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    passphrase: Option<String>,
+    config: Option<CipherConfig>,
+    on_passphrase: Option<js_sys::Function>,
+    backend: Option<Backend>,
+}
+
+#[wasm_bindgen]
+impl OpenOptions {
+    /// `passphrase` is tried first, if given. After that, until a passphrase works, `on_passphrase` is asked for one,
+    /// with the database's name and how many wrong passphrases have been tried so far. It returns a passphrase, or a
+    /// promise of one, or `null` to give up.
+    ///
+    /// `config` and `backend` work as they do for `Database.connect_with_key`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        passphrase: Option<String>,
+        config: Option<CipherConfig>,
+        #[wasm_bindgen(
+            unchecked_param_type = "(name: string, attempts: number) => string | null | Promise<string | null>"
+        )]
+        on_passphrase: Option<js_sys::Function>,
+        backend: Option<Backend>,
+    ) -> Self {
+        Self {
+            passphrase,
+            config,
+            on_passphrase,
+            backend,
+        }
+    }
+}
+
+impl OpenOptions {
+    /// The passphrase to try after `attempts` wrong ones, if there is one
+    async fn passphrase(&self, name: &str, attempts: u32) -> Result<Option<String>> {
+        if attempts == 0
+            && let Some(passphrase) = &self.passphrase
+        {
+            return Ok(Some(passphrase.clone()));
+        }
+        let Some(on_passphrase) = &self.on_passphrase else {
+            return Ok(None);
+        };
+
+        let mut passphrase = on_passphrase
+            .call2(&JsValue::NULL, &name.into(), &attempts.into())
+            .map_err(Error::from)
+            .context("asking for passphrase")?;
+        if let Some(promise) = passphrase.dyn_ref::<js_sys::Promise>() {
+            passphrase = JsFuture::from(promise.clone())
+                .await
+                .map_err(Error::from)
+                .context("waiting for passphrase")?;
+        }
+        Ok(passphrase.as_string())
+    }
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const OPEN_RESULT: &str = r#"
+/**
+ * What `Database.open` found: the connected database, or an encrypted one which no passphrase unlocked, because
+ * `on_passphrase` gave up after `attempts` wrong ones.
+ */
+export type OpenResult = { database: Database } | { needs_passphrase: true; attempts: number };
+"#;
+
+/// What [`Database::open`] found
+enum Opened {
+    Database(Database),
+    /// The database is encrypted, and the passphrase callback gave up after this many wrong passphrases
+    NeedsPassphrase {
+        attempts: u32,
+    },
+}
+
+impl Opened {
+    /// The `OpenResult` for JS
+    fn into_js(self) -> Result<JsValue> {
+        let fields = match self {
+            Self::Database(database) => vec![("database", JsValue::from(database))],
+            Self::NeedsPassphrase { attempts } => vec![
+                ("needs_passphrase", JsValue::TRUE),
+                ("attempts", JsValue::from(attempts)),
+            ],
+        };
+        let result = js_sys::Object::new();
+        for (field, value) in fields {
+            js_sys::Reflect::set(&result, &field.into(), &value)
+                .map_err(Error::from)
+                .context("building open result")?;
+        }
+        Ok(result.into())
+    }
+}
+
+#[wasm_bindgen]
+impl Database {
+    /// Connect to a database whether or not it is encrypted, unlocking it as `options` say if it is.
+    ///
+    /// Resolves to `{ database }` once connected. If the database is encrypted but no passphrase unlocked it, because
+    /// `on_passphrase` gave up, it resolves to `{ needs_passphrase: true, attempts }` instead, saying how many wrong
+    /// passphrases were tried. Anything else which goes wrong throws.
+    #[wasm_bindgen(unchecked_return_type = "OpenResult")]
+    pub async fn open(name: &str, options: Option<OpenOptions>) -> Result<JsValue> {
+        let options = options.unwrap_or_default();
+        let vfs = Vfs::get(options.backend).await?;
+        if !encryption::db_file_is_encrypted(name, vfs)
+            .context("checking whether db file is encrypted")?
+        {
+            let database = Self::connect(name, options.backend).await?;
+            return Opened::Database(database).into_js();
+        }
+
+        let mut attempts = 0;
+        while let Some(passphrase) = options.passphrase(name, attempts).await? {
+            match Self::connect_encrypted(vfs, name, Key::Passphrase(passphrase), options.config) {
+                Ok(database) => return Opened::Database(database).into_js(),
+                Err(err) if err.code() == ErrorCode::WrongKey => attempts += 1,
+                Err(err) => return Err(err),
+            }
+        }
+        Opened::NeedsPassphrase { attempts }.into_js()
+    }
+}
@@ -0,0 +1,367 @@
+//! The storage backends databases can be kept in, each installed as a SQLite VFS.
+//!
+//! sqlite3mc layers encryption over whichever VFS a connection names with its `multipleciphers-` prefix, so both
+//! backends encrypt the same way.
+//!
+//! The memory backend is the in-memory VFS sqlite-wasm-rs installs as SQLite's default. Its databases are gone once
+//! the page is, unless `Database.persist` copies them into another backend first.
+
+use std::{
+    cell::Cell,
+    ffi::{CStr, CString, c_int},
+    sync::LazyLock,
+    thread::LocalKey,
+};
+
+use anyhow::{Context as _, Result, anyhow, ensure};
+use rusqlite::{Connection, OpenFlags};
+use sqlite_wasm_rs::{
+    MemVfsUtil, SQLITE_OK, SQLITE_OPEN_MAIN_DB, SQLITE_OPEN_READONLY, WasmOsCallback, sqlite3_file,
+    sqlite3_int64, sqlite3_io_methods, sqlite3_vfs, sqlite3_vfs_find,
+};
+use sqlite_wasm_vfs::{
+    relaxed_idb::{self, RelaxedIdbCfg, RelaxedIdbUtil, WaitCommit},
+    sahpool::{self, OpfsSAHPoolCfg, OpfsSAHPoolUtil},
+};
+use wasm_bindgen::prelude::*;
+
+static RUSQLITE_FLAGS: LazyLock<OpenFlags> =
+    LazyLock::new(|| OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE);
+
+/// Files the OPFS pool keeps room for beyond those it holds, for journals, backups, and new databases
+const OPFS_HEADROOM: u32 = 6;
+
+thread_local! {
+    /// The relaxed-idb VFS utility, once the VFS is installed
+    static IDB_UTIL: Cell<Option<&'static RelaxedIdbUtil>> = const { Cell::new(None) };
+    /// The OPFS VFS utility, once the VFS is installed
+    static OPFS_UTIL: Cell<Option<&'static OpfsSAHPoolUtil>> = const { Cell::new(None) };
+    /// The in-memory VFS utility, once asked for
+    static MEMORY_UTIL: Cell<Option<&'static MemVfsUtil<WasmOsCallback>>> = const { Cell::new(None) };
+}
+
+/// Where databases are stored
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// IndexedDB, kept in memory and written back in the background; available everywhere, and the default
+    IndexedDb = "indexeddb",
+    /// The origin private file system, through synchronous access handles, which only dedicated workers have
+    Opfs = "opfs",
+    /// Memory alone, for demos, tests, and private sessions which should leave nothing behind
+    Memory = "memory",
+}
+
+/// An installed backend
+#[derive(Clone, Copy)]
+pub(super) enum Vfs {
+    Idb(&'static RelaxedIdbUtil),
+    Opfs(&'static OpfsSAHPoolUtil),
+    Memory(&'static MemVfsUtil<WasmOsCallback>),
+}
+
+/// Keep `util` for good, unless another connection kept one while this one was being installed
+fn keep<T>(cell: &'static LocalKey<Cell<Option<&'static T>>>, util: T) -> &'static T {
+    cell.get().unwrap_or_else(|| {
+        let util = &*Box::leak(Box::new(util));
+        cell.set(Some(util));
+        util
+    })
+}
+
+impl Vfs {
+    /// Get the backend's VFS, installing it the first time; without a backend, IndexedDB's
+    // note: the configs set values including the name, which gets used as the IDB database or OPFS directory name
+    pub(super) async fn get(backend: Option<Backend>) -> Result<Self> {
+        match backend.unwrap_or(Backend::IndexedDb) {
+            Backend::IndexedDb => {
+                if let Some(util) = IDB_UTIL.get() {
+                    return Ok(Self::Idb(util));
+                }
+                let util = relaxed_idb::install::<WasmOsCallback>(&RelaxedIdbCfg::default(), false)
+                    .await
+                    .map_err(|err| anyhow!("{err}"))
+                    .context("failed to install relaxed-idb vfs")?;
+                Ok(Self::Idb(keep(&IDB_UTIL, util)))
+            }
+            Backend::Opfs => {
+                let util = match OPFS_UTIL.get() {
+                    Some(util) => util,
+                    None => {
+                        let util = sahpool::install::<WasmOsCallback>(
+                            &OpfsSAHPoolCfg::default(),
+                            false,
+                        )
+                        .await
+                        .map_err(|err| anyhow!("{err}"))
+                        .context(
+                            "failed to install OPFS vfs; it only works in dedicated workers",
+                        )?;
+                        keep(&OPFS_UTIL, util)
+                    }
+                };
+                // every file takes a handle from the pool, which can only grow asynchronously
+                util.reserve_minimum_capacity(util.count() + OPFS_HEADROOM)
+                    .await
+                    .map_err(|err| anyhow!("{err}"))
+                    .context("reserving OPFS capacity")?;
+                Ok(Self::Opfs(util))
+            }
+            Backend::Memory => Ok(Self::Memory(
+                MEMORY_UTIL
+                    .get()
+                    .unwrap_or_else(|| keep(&MEMORY_UTIL, MemVfsUtil::new())),
+            )),
+            // wasm-bindgen's catch-all for strings from JS which aren't backends
+            Backend::__Invalid => Err(anyhow!("unknown backend")),
+        }
+    }
+
+    pub(super) fn backend(self) -> Backend {
+        match self {
+            Self::Idb(_) => Backend::IndexedDb,
+            Self::Opfs(_) => Backend::Opfs,
+            Self::Memory(_) => Backend::Memory,
+        }
+    }
+
+    /// The name of the VFS with sqlite3mc's encryption layered on top
+    fn name(self) -> &'static str {
+        match self {
+            Self::Idb(_) => "multipleciphers-relaxed-idb",
+            Self::Opfs(_) => "multipleciphers-opfs-sahpool",
+            Self::Memory(_) => "multipleciphers-memvfs",
+        }
+    }
+
+    /// The name of the VFS beneath sqlite3mc's encryption, whose files hold the bytes as stored
+    fn raw_name(self) -> &'static CStr {
+        match self {
+            Self::Idb(_) => c"relaxed-idb",
+            Self::Opfs(_) => c"opfs-sahpool",
+            Self::Memory(_) => c"memvfs",
+        }
+    }
+
+    /// Open a connection to the database `name`, creating it if necessary
+    pub(super) fn open(self, name: &str) -> Result<Connection> {
+        Connection::open_with_flags_and_vfs(name, *RUSQLITE_FLAGS, self.name())
+            .context("opening database connection")
+    }
+
+    pub(super) fn exists(self, name: &str) -> Result<bool> {
+        match self {
+            Self::Idb(util) => Ok(util.exists(name)),
+            Self::Opfs(util) => util.exists(name).map_err(|err| anyhow!("{err}")),
+            Self::Memory(util) => Ok(util.exists(name)),
+        }
+    }
+
+    /// The names of every file in the backend, databases or not
+    pub(super) fn list(self) -> Vec<String> {
+        match self {
+            Self::Idb(util) => util.list(),
+            Self::Opfs(util) => util.list(),
+            Self::Memory(util) => util.list(),
+        }
+    }
+
+    /// The length in bytes of the file `name`, which must exist
+    pub(super) fn file_size(self, name: &str) -> Result<usize> {
+        RawFile::open(self, name)?.size()
+    }
+
+    /// Up to the first `len` bytes of the file `name`, which must exist
+    pub(super) fn read_head(self, name: &str, len: usize) -> Result<Vec<u8>> {
+        let file = RawFile::open(self, name)?;
+        let mut head = vec![0; len.min(file.size()?)];
+        file.read(&mut head)?;
+        Ok(head)
+    }
+
+    pub(super) fn export_db(self, name: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::Idb(util) => util.export_db(name).map_err(|err| anyhow!("{err}")),
+            Self::Opfs(util) => util.export_db(name).map_err(|err| anyhow!("{err}")),
+            Self::Memory(util) => util.export_db(name).map_err(|err| anyhow!("{err}")),
+        }
+        .context("exporting database")
+    }
+
+    /// Write the database `name`, which must not exist yet.
+    ///
+    /// Unencrypted databases are checked on the way in. Encrypted ones can't be, and all but OPFS need to be told
+    /// their `page_size`; `None` means the database is unencrypted.
+    pub(super) fn import_db(
+        self,
+        name: &str,
+        bytes: &[u8],
+        page_size: Option<usize>,
+    ) -> Result<Commit> {
+        match (self, page_size) {
+            (Self::Idb(util), None) => util
+                .import_db(name, bytes)
+                .map(Commit::pending)
+                .map_err(|err| anyhow!("{err}")),
+            (Self::Idb(util), Some(page_size)) => util
+                .import_db_unchecked(name, bytes, page_size)
+                .map(Commit::pending)
+                .map_err(|err| anyhow!("{err}")),
+            (Self::Opfs(util), None) => util
+                .import_db(name, bytes)
+                .map(Commit::done)
+                .map_err(|err| anyhow!("{err}")),
+            (Self::Opfs(util), Some(_)) => util
+                .import_db_unchecked(name, bytes)
+                .map(Commit::done)
+                .map_err(|err| anyhow!("{err}")),
+            (Self::Memory(util), None) => util
+                .import_db(name, bytes)
+                .map(Commit::done)
+                .map_err(|err| anyhow!("{err}")),
+            (Self::Memory(util), Some(page_size)) => util
+                .import_db_unchecked(name, bytes, page_size)
+                .map(Commit::done)
+                .map_err(|err| anyhow!("{err}")),
+        }
+        .context("importing database")
+    }
+
+    /// Delete the database `name`, which nothing may have open
+    pub(super) fn delete_db(self, name: &str) -> Result<Commit> {
+        match self {
+            Self::Idb(util) => util
+                .delete_db(name)
+                .map(Commit::pending)
+                .map_err(|err| anyhow!("{err}")),
+            Self::Opfs(util) => util
+                .delete_db(name)
+                .map(Commit::done)
+                .map_err(|err| anyhow!("{err}")),
+            Self::Memory(util) => {
+                util.delete_db(name);
+                Ok(Commit::done(()))
+            }
+        }
+        .context("deleting database")
+    }
+}
+
+/// A file opened read-only through a backend's raw VFS, for looking at it without exporting it whole.
+///
+/// Closed again when dropped.
+struct RawFile {
+    file: *mut sqlite3_file,
+    /// The VFS's own file struct, which `file` points to the start of
+    _storage: Vec<u64>,
+    /// SQLite promises VFSes that a file's name outlives it
+    _name: CString,
+}
+
+impl RawFile {
+    fn open(vfs: Vfs, name: &str) -> Result<Self> {
+        // SAFETY: the name is a nul-terminated static string
+        let raw = unsafe { sqlite3_vfs_find(vfs.raw_name().as_ptr()) };
+        ensure!(
+            !raw.is_null(),
+            "RawFile::open: the {:?} vfs isn't installed",
+            vfs.raw_name()
+        );
+        // SAFETY: registered VFSes stay registered for as long as the page lives
+        let sqlite3_vfs {
+            szOsFile, xOpen, ..
+        } = unsafe { *raw };
+        let x_open = xOpen.context("RawFile::open: the vfs can't open files")?;
+
+        let name = CString::new(name).context("RawFile::open: name contains a nul byte")?;
+        let len = usize::try_from(szOsFile).context("RawFile::open: bad file struct size")?;
+        let mut file = Self {
+            file: std::ptr::null_mut(),
+            // 8-byte aligned, as SQLite allocates them
+            _storage: vec![0; len.div_ceil(8).max(1)],
+            _name: name,
+        };
+        file.file = file._storage.as_mut_ptr().cast();
+        let mut out_flags: c_int = 0;
+        // SAFETY: `file` points to `szOsFile` zeroed bytes, and the name lives as long as the file; on failure,
+        // dropping the file closes it only if the VFS set its methods, as SQLite itself does
+        let rc = unsafe {
+            x_open(
+                raw,
+                file._name.as_ptr(),
+                file.file,
+                SQLITE_OPEN_READONLY | SQLITE_OPEN_MAIN_DB,
+                &mut out_flags,
+            )
+        };
+        ensure!(
+            rc == SQLITE_OK,
+            "RawFile::open: opening the file failed with code {rc}"
+        );
+        Ok(file)
+    }
+
+    fn methods(&self) -> Option<&sqlite3_io_methods> {
+        // SAFETY: the VFS set the methods when it opened the file, if at all, and they outlive it
+        unsafe { (*self.file).pMethods.as_ref() }
+    }
+
+    fn size(&self) -> Result<usize> {
+        let x_file_size = self
+            .methods()
+            .and_then(|methods| methods.xFileSize)
+            .context("RawFile::size: the vfs can't tell file sizes")?;
+        let mut size: sqlite3_int64 = 0;
+        // SAFETY: the file is open
+        let rc = unsafe { x_file_size(self.file, &mut size) };
+        ensure!(rc == SQLITE_OK, "RawFile::size: failed with code {rc}");
+        usize::try_from(size).context("RawFile::size: size out of range")
+    }
+
+    /// Fill `buf` from the start of the file, which must be at least as long
+    fn read(&self, buf: &mut [u8]) -> Result<()> {
+        let x_read = self
+            .methods()
+            .and_then(|methods| methods.xRead)
+            .context("RawFile::read: the vfs can't read files")?;
+        let len = c_int::try_from(buf.len()).context("RawFile::read: too much to read at once")?;
+        // SAFETY: the file is open, and `buf` is `len` bytes long
+        let rc = unsafe { x_read(self.file, buf.as_mut_ptr().cast(), len, 0) };
+        ensure!(rc == SQLITE_OK, "RawFile::read: failed with code {rc}");
+        Ok(())
+    }
+}
+
+impl Drop for RawFile {
+    fn drop(&mut self) {
+        if let Some(x_close) = self.methods().and_then(|methods| methods.xClose) {
+            // SAFETY: the file is open, and is never used again
+            unsafe { x_close(self.file) };
+        }
+    }
+}
+
+/// A change to a backend, which relaxed-idb only writes to IndexedDB in the background.
+///
+/// The change is visible straight away either way; dropping this just doesn't wait for it to be stored.
+pub(super) struct Commit(Option<WaitCommit>);
+
+impl Commit {
+    fn pending(wait: WaitCommit) -> Self {
+        Self(Some(wait))
+    }
+
+    fn done<T>(_: T) -> Self {
+        Self(None)
+    }
+
+    /// Wait until the change is stored
+    pub(super) async fn stored(self) -> Result<()> {
+        if let Some(wait) = self.0 {
+            wait.await
+                .map_err(|err| anyhow!("{err}"))
+                .context("committing to IndexedDB")?;
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,95 @@
 use std::{convert::Infallible, fmt::Display};
 
 use anyhow::anyhow;
-use serde_json::json;
 use sqlite_wasm_vfs::relaxed_idb::RelaxedIdbError;
+use todo_list::encryption::WrongPassphrase;
 use wasm_bindgen::prelude::*;
 
 /// A convenience wrapper for results which defaults to [`Error`].
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-/// A Js-compatible error type
+/// What kind of failure an [`Error`] is, so that JS can tell them apart without reading messages
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The key doesn't decrypt the database, or it isn't a database at all
+    WrongKey = "WRONG_KEY",
+    /// No database, list, or item has that name or id
+    NotFound = "NOT_FOUND",
+    /// A database of that name exists already
+    AlreadyExists = "ALREADY_EXISTS",
+    /// The database is encrypted, but was connected to without a key
+    EncryptedDbNoKey = "ENCRYPTED_DB_NO_KEY",
+    /// The database isn't encrypted, but was connected to with a key
+    UnencryptedDbKey = "UNENCRYPTED_DB_KEY",
+    /// Migrating the database schema failed
+    Schema = "SCHEMA",
+    /// The database was used after being closed
+    Closed = "CLOSED",
+    /// Anything else
+    Other = "OTHER",
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const TODO_ERROR: &str = r#"
+/**
+ * What every function in this library throws.
+ *
+ * `message` says what failed, and each `cause` in turn says why.
+ */
+export interface TodoError extends Error {
+    name: "TodoError";
+    code: ErrorCode;
+    cause?: Error;
+}
+"#;
+
+/// A failure of a kind named by its code, raised in this crate
+#[derive(Debug, derive_more::Display)]
+#[display("{message}")]
+struct Coded {
+    code: ErrorCode,
+    message: String,
+}
+
+impl std::error::Error for Coded {}
+
+/// A Js-compatible error type.
+///
+/// It reaches JS as a `TodoError`: a JS `Error` with a `code`, and the errors which caused it chained through `cause`.
 #[derive(Debug, derive_more::Display, derive_more::From)]
-pub struct Error(anyhow::Error);
+pub struct Error(pub(crate) anyhow::Error);
+
+impl Error {
+    /// An error of the kind `code`
+    pub(crate) fn new(code: ErrorCode, message: impl Display) -> Self {
+        Self(anyhow::Error::new(Coded {
+            code,
+            message: message.to_string(),
+        }))
+    }
+
+    /// Wrap this error in `context`, making it an error of the kind `code`
+    pub(crate) fn with_code(self, code: ErrorCode, context: impl Display) -> Self {
+        Self(self.0.context(Coded {
+            code,
+            message: context.to_string(),
+        }))
+    }
+
+    /// What kind of failure this is: the outermost code given in this crate, or else what it was caused by
+    pub(crate) fn code(&self) -> ErrorCode {
+        if let Some(coded) = self.0.downcast_ref::<Coded>() {
+            coded.code
+        } else if self.0.is::<WrongPassphrase>() {
+            ErrorCode::WrongKey
+        } else if let Some(rusqlite::Error::QueryReturnedNoRows) = self.0.downcast_ref() {
+            ErrorCode::NotFound
+        } else {
+            ErrorCode::Other
+        }
+    }
+}
 
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
@@ -20,29 +99,20 @@ impl std::error::Error for Error {
 
 impl From<Error> for JsValue {
     fn from(value: Error) -> Self {
-        // construct the basic error
-        let mut json_value = json!({
-            "msg": value.to_string(),
-        });
+        let error = js_sys::Error::new(&value.to_string());
+        error.set_name("TodoError");
+        js_sys::Reflect::set(&error, &"code".into(), &value.code().into())
+            .expect("setting a property of a fresh error works");
 
         // fill in the source errors chain
-        let mut err: &dyn std::error::Error = &value;
-        let mut json_err = &mut json_value;
-        while let Some(inner_err) = err.source() {
-            json_err.as_object_mut().unwrap().insert(
-                "source".into(),
-                json!({
-                    "msg": inner_err.to_string(),
-                }),
-            );
-
-            err = inner_err;
-            json_err = json_err.as_object_mut().unwrap().get_mut("source").unwrap();
+        let mut outer = error.clone();
+        for source in value.0.chain().skip(1) {
+            let cause = js_sys::Error::new(&source.to_string());
+            outer.set_cause(&cause);
+            outer = cause;
         }
 
-        // convert to JsValue
-        serde_wasm_bindgen::to_value(&json_value)
-            .expect("converting a simple object tree to js works")
+        error.into()
     }
 }
 
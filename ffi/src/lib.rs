@@ -1,29 +1,15 @@
 mod database;
 mod error;
+mod logger;
+mod object;
 
 use wasm_bindgen::prelude::*;
 
-pub use database::Database;
-pub use error::{Context, Error, Result};
-
-macro_rules! console_log {
-    ($e:expr) => {{
-        let js_value = JsValue::from($e);
-        web_sys::console::log_1(&js_value);
-    }};
-    ($e:expr; $( $k:literal => $v:expr ),*) => {{
-        let json = serde_json::json!({
-            "msg": $e,
-            "context": {
-                $(
-                    $k: $v
-                ),*
-            }
-        });
-        let js_value = serde_wasm_bindgen::to_value(&json).unwrap_or_else(Into::into);
-        web_sys::console::table_1(&js_value);
-    }};
-}
+pub use database::{
+    Backend, Cipher, CipherConfig, Database, DatabaseInfo, OpenOptions, PassphraseStrength,
+};
+pub use error::{Context, Error, ErrorCode, Result};
+pub use logger::{LogLevel, set_log_level};
 
 fn elide_ok<T, E>(result: &Result<T, E>) -> String
 where
@@ -46,18 +32,26 @@ where
 /// `Ok(_)` are replaced with the string "Ok", and errors are printed.
 ///
 /// If `result_map` is omitted, the entire return value is emitted in debug form.
+///
+/// Calls are logged at debug level, so nothing is formatted unless that level is enabled.
 macro_rules! log_call {
     ($name:literal ($( $param:expr ),*) => $f:expr; $result_map:expr) => {{
-        let mut log_str = $name.to_string();
-        log_str.push('(');
-        $(
-            log_str.push_str(&format!("{:?}, ", $param));
-        )*
-        log_str.push(')');
+        let enabled = log::log_enabled!(log::Level::Debug);
+        let mut log_str = String::new();
+        if enabled {
+            log_str.push_str($name);
+            log_str.push('(');
+            $(
+                log_str.push_str(&format!("{:?}, ", $param));
+            )*
+            log_str.push(')');
+        }
 
         let result = $f;
-        log_str.push_str(&format!(" -> {:?}", $result_map(&result)));
-        console_log!(log_str);
+        if enabled {
+            log_str.push_str(&format!(" -> {:?}", $result_map(&result)));
+            log::debug!("{log_str}");
+        }
         result
     }};
     ($name:literal ($( $param:expr ),*) => $f:expr) => {
@@ -67,17 +61,17 @@ macro_rules! log_call {
     }
 }
 
+#[cfg(feature = "sync")]
+mod sync;
+
 #[wasm_bindgen]
 pub async fn apply_schema(database: &Database) -> Result<()> {
-    let mut result = todo_list::apply_schema(&database.connection).await;
-    if let Err(err) = &result
-        && err.to_string() == "applying schema"
-        && let Some(err) = err.source()
-        && err.to_string().ends_with("already exists")
-    {
-        result = Ok(());
-    }
-    log_call!("apply_schema"() => result.map_err(Into::into))
+    log_call!(
+        "apply_schema"() =>
+        todo_list::apply_schema(database.connection()?)
+            .await
+            .map_err(|err| Error::from(err).with_code(ErrorCode::Schema, "applying schema"))
+    )
 }
 
 #[wasm_bindgen]
@@ -89,6 +83,11 @@ impl Item {
         self.0.id().into()
     }
 
+    /// Globally unique id of this item, stable across databases
+    pub fn uuid(&self) -> String {
+        self.0.uuid().to_string()
+    }
+
     pub fn list_id(&self) -> u32 {
         self.0.list_id().into()
     }
@@ -98,7 +97,12 @@ impl Item {
     }
 
     pub fn is_completed(&self) -> bool {
-        log_call!("Item::is_completed"() => self.0.is_completed())
+        self.0.is_completed()
+    }
+
+    /// Where this item sorts within its list, lowest first
+    pub fn position(&self) -> i32 {
+        object::position(&self.0)
     }
 
     /// Unix timestamp of the creation time of this item
@@ -109,6 +113,18 @@ impl Item {
             .try_into()
             .unwrap_or_default()
     }
+
+    /// This item as a plain object
+    #[wasm_bindgen(unchecked_return_type = "ItemObject")]
+    pub fn to_object(&self) -> Result<JsValue> {
+        object::to_js(&object::ItemObject::from(&self.0))
+    }
+
+    /// This item as a plain object, for `JSON.stringify`
+    #[wasm_bindgen(js_name = toJSON, unchecked_return_type = "ItemObject")]
+    pub fn to_json(&self) -> Result<JsValue> {
+        self.to_object()
+    }
 }
 
 #[wasm_bindgen]
@@ -126,6 +142,11 @@ impl TodoList {
         self.0.id().into()
     }
 
+    /// Globally unique id of this list, stable across databases
+    pub fn uuid(&self) -> String {
+        self.0.uuid().to_string()
+    }
+
     pub fn title(&self) -> String {
         self.0.title().to_owned()
     }
@@ -153,6 +174,24 @@ impl TodoList {
         self.0.item(item_id.into()).cloned().map(Item)
     }
 
+    /// Get all items as plain objects, in the same order as `item_ids`
+    #[wasm_bindgen(unchecked_return_type = "ItemObject[]")]
+    pub fn items(&self) -> Result<JsValue> {
+        object::to_js(&object::items(&self.0))
+    }
+
+    /// This todo list and all its items as a plain object
+    #[wasm_bindgen(unchecked_return_type = "TodoListObject")]
+    pub fn to_object(&self) -> Result<JsValue> {
+        object::to_js(&object::TodoListObject::from(&self.0))
+    }
+
+    /// This todo list as a plain object, for `JSON.stringify`
+    #[wasm_bindgen(js_name = toJSON, unchecked_return_type = "TodoListObject")]
+    pub fn to_json(&self) -> Result<JsValue> {
+        self.to_object()
+    }
+
     /// Update an item's description.
     ///
     /// Returns `Some(dirty)` if the item was found, where `dirty` indicates whether or not item will update on the next save.
@@ -173,12 +212,22 @@ impl TodoList {
         Some(item.dirty())
     }
 
+    /// Move an item within its list.
+    ///
+    /// Returns `Some(dirty)` if the item was found, where `dirty` indicates whether or not the item will update on the next save.
+    /// Returns `None` if the item was not found.
+    pub fn set_item_position(&mut self, item_id: u32, position: i32) -> Option<bool> {
+        let item = self.item_mut(item_id)?;
+        item.set_position(position.into());
+        Some(item.dirty())
+    }
+
     /// Get all todo lists with their ids
     #[wasm_bindgen(unchecked_return_type = "[number, string][]")]
     pub async fn list_all(database: &Database) -> Result<JsValue> {
         let items = log_call!(
             "TodoList::list_all"() =>
-            todo_list::TodoList::list_all(&database.connection).await;
+            todo_list::TodoList::list_all(database.connection()?).await;
             elide_ok
         )?
         .into_iter()
@@ -192,7 +241,7 @@ impl TodoList {
     pub async fn new(database: &Database, title: String) -> Result<Self> {
         log_call!(
             "TodoList::new"(title) =>
-            todo_list::TodoList::new(&database.connection, title).await;
+            todo_list::TodoList::new(database.connection()?, title).await;
             elide_ok
         )
         .map(Self)
@@ -201,14 +250,26 @@ impl TodoList {
 
     /// Save a todo list and all its items
     pub async fn save(&mut self, database: &Database) -> Result<()> {
-        log_call!("TodoList::save"() => self.0.save(&database.connection).await.map_err(Into::into))
+        log_call!("TodoList::save"() => self.0.save(database.connection()?).await.map_err(Into::into))
     }
 
     /// Load a todo list by its id
     pub async fn load(database: &Database, id: u32) -> Result<Self> {
         log_call!(
             "TodoList::load"() =>
-            todo_list::TodoList::load(&database.connection, id.into()).await;
+            todo_list::TodoList::load(database.connection()?, id.into()).await;
+            elide_ok
+        )
+        .map(Self)
+        .map_err(Into::into)
+    }
+
+    /// Load a todo list by its globally unique id
+    pub async fn load_by_uuid(database: &Database, uuid: &str) -> Result<Self> {
+        let uuid = todo_list::Uuid::parse_str(uuid).context("parsing uuid")?;
+        log_call!(
+            "TodoList::load_by_uuid"(uuid) =>
+            todo_list::TodoList::load_by_uuid(database.connection()?, uuid).await;
             elide_ok
         )
         .map(Self)
@@ -219,7 +280,7 @@ impl TodoList {
     ///
     /// Returns `true` if a list existed for that id.
     pub async fn delete(database: &Database, id: u32) -> Result<bool> {
-        log_call!("TodoList::delete"(id) => todo_list::TodoList::delete(&database.connection, id.into()).await)
+        log_call!("TodoList::delete"(id) => todo_list::TodoList::delete(database.connection()?, id.into()).await)
             .map_err(Into::into)
     }
 
@@ -229,19 +290,41 @@ impl TodoList {
     pub async fn add_item(&mut self, database: &Database, description: String) -> Result<u32> {
         log_call!(
             "TodoList::add_item"(description) =>
-            self.0.add_item(&database.connection, description).await
+            self.0.add_item(database.connection()?, description).await
         )
         .map(Into::into)
         .map_err(Into::into)
     }
 
+    /// Write this todo list as a GitHub-flavoured markdown checklist
+    pub fn to_markdown(&self) -> String {
+        todo_list::markdown::from_list(&self.0)
+    }
+
+    /// Create a new todo list for each checklist in a markdown document
+    ///
+    /// Checklist items which come before any heading go in a list titled `default_title`.
+    pub async fn import_markdown(
+        database: &Database,
+        markdown: &str,
+        default_title: &str,
+    ) -> Result<Vec<TodoList>> {
+        log_call!(
+            "TodoList::import_markdown"(default_title) =>
+            todo_list::markdown::import(database.connection()?, markdown, default_title).await;
+            elide_ok
+        )
+        .map(|lists| lists.into_iter().map(Self).collect())
+        .map_err(Into::into)
+    }
+
     /// Remove an item from this todo list.
     ///
     /// Returns `true` if an item existed for that id.
     pub async fn remove_item(&mut self, database: &Database, item_id: u32) -> Result<bool> {
         log_call!(
             "TodoList::remove_item"(item_id) =>
-            self.0.remove_item(&database.connection, item_id.into()).await
+            self.0.remove_item(database.connection()?, item_id.into()).await
         )
         .map_err(Into::into)
     }
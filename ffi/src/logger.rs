@@ -0,0 +1,91 @@
+//! Logging to the browser console, through the `log` facade.
+//!
+//! This carries this crate's call logging and `todo-list`'s own `debug!`s alike. Only warnings and errors are shown
+//! until `set_log_level` says otherwise.
+
+use std::fmt::Write as _;
+
+use log::{
+    Level, LevelFilter, Log, Metadata, Record,
+    kv::{self, Key, Value, VisitSource},
+};
+use wasm_bindgen::prelude::*;
+
+/// How much is logged to the browser console
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Off = "off",
+    Error = "error",
+    Warn = "warn",
+    Info = "info",
+    Debug = "debug",
+    Trace = "trace",
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::Off | LogLevel::__Invalid => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Writes each record to the console method matching its level, so the browser can filter them too
+struct ConsoleLogger;
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+/// Appends ` key=value` for each key-value pair of a record
+struct Pairs<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for Pairs<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        write!(self.0, " {key}={value}").map_err(|_| kv::Error::msg("formatting key-value pair"))
+    }
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut line = format!("{}: {}", record.target(), record.args());
+        // best effort: the message is worth logging without them
+        let _ = record.key_values().visit(&mut Pairs(&mut line));
+
+        let line = JsValue::from(line);
+        match record.level() {
+            Level::Error => web_sys::console::error_1(&line),
+            Level::Warn => web_sys::console::warn_1(&line),
+            Level::Info => web_sys::console::info_1(&line),
+            Level::Debug => web_sys::console::log_1(&line),
+            Level::Trace => web_sys::console::debug_1(&line),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[wasm_bindgen(start)]
+fn init() {
+    // nothing else in this crate sets a logger, so this can't fail
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(LevelFilter::Warn);
+}
+
+/// Log everything at `level` and above to the browser console; `"off"` logs nothing
+#[wasm_bindgen]
+pub fn set_log_level(level: LogLevel) {
+    log::set_max_level(level.into());
+}
@@ -0,0 +1,106 @@
+//! Todo lists and items as plain JS objects, which cost one trip across the wasm boundary rather than one per field.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::{Context as _, Error, Result};
+
+#[wasm_bindgen(typescript_custom_section)]
+const OBJECT_TYPES: &str = r#"
+/**
+ * An item as a plain object; its fields match the getters of `Item`.
+ */
+export interface ItemObject {
+    id: number;
+    uuid: string;
+    list_id: number;
+    description: string;
+    is_completed: boolean;
+    position: number;
+    created_at: number;
+}
+
+/**
+ * A todo list and all its items as a plain object; its fields match the getters of `TodoList`.
+ */
+export interface TodoListObject {
+    id: number;
+    uuid: string;
+    title: string;
+    created_at: number;
+    items: ItemObject[];
+}
+"#;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ItemObject {
+    id: u32,
+    uuid: String,
+    list_id: u32,
+    description: String,
+    is_completed: bool,
+    position: i32,
+    created_at: u32,
+}
+
+impl From<&todo_list::Item> for ItemObject {
+    fn from(item: &todo_list::Item) -> Self {
+        Self {
+            id: item.id().into(),
+            uuid: item.uuid().to_string(),
+            list_id: item.list_id().into(),
+            description: item.description().to_owned(),
+            is_completed: item.is_completed(),
+            position: position(item),
+            created_at: item
+                .created_at()
+                .unix_timestamp()
+                .try_into()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TodoListObject {
+    id: u32,
+    uuid: String,
+    title: String,
+    created_at: u32,
+    items: Vec<ItemObject>,
+}
+
+impl From<&todo_list::TodoList> for TodoListObject {
+    fn from(list: &todo_list::TodoList) -> Self {
+        Self {
+            id: list.id().into(),
+            uuid: list.uuid().to_string(),
+            title: list.title().to_owned(),
+            created_at: list
+                .created_at()
+                .unix_timestamp()
+                .try_into()
+                .unwrap_or_default(),
+            items: items(list),
+        }
+    }
+}
+
+/// Where `item` sorts within its list, saturated to the range of an `i32` so JS holds it exactly
+pub(crate) fn position(item: &todo_list::Item) -> i32 {
+    let position = item.position();
+    i32::try_from(position).unwrap_or(if position < 0 { i32::MIN } else { i32::MAX })
+}
+
+/// The list's items, in the same order as `TodoList.item_ids`
+pub(crate) fn items(list: &todo_list::TodoList) -> Vec<ItemObject> {
+    list.items().values().map(ItemObject::from).collect()
+}
+
+/// Convert `value` to a plain JS object
+pub(crate) fn to_js(value: &impl Serialize) -> Result<JsValue> {
+    serde_wasm_bindgen::to_value(value)
+        .map_err(JsValue::from)
+        .map_err(Error::from)
+        .context("converting to a plain object")
+}
@@ -0,0 +1,163 @@
+use sync_service::{
+    client::{Client, Transport},
+    protocol::Cursor,
+};
+use todo_list::{Uuid, sync::ConflictPolicy};
+use wasm_bindgen::{JsCast as _, prelude::*};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, Response};
+
+use crate::{Context as _, Database, Error, Result, elide_ok};
+
+#[wasm_bindgen]
+extern "C" {
+    // bound directly rather than through `Window`, so that it also works in workers
+    #[wasm_bindgen(js_name = fetch)]
+    fn fetch_with_request(request: &Request) -> js_sys::Promise;
+}
+
+/// Carries sync requests to the server with `fetch`
+#[derive(Debug)]
+pub struct FetchTransport {
+    base_url: String,
+}
+
+impl FetchTransport {
+    async fn fetch(&self, method: &str, path: &str, body: Option<Vec<u8>>) -> Result<Vec<u8>> {
+        let init = RequestInit::new();
+        init.set_method(method);
+        if let Some(body) = body {
+            init.set_body(&js_sys::Uint8Array::from(body.as_slice()));
+        }
+        let request = Request::new_with_str_and_init(&format!("{}{path}", self.base_url), &init)
+            .map_err(Error::from)
+            .context("building request")?;
+        request
+            .headers()
+            .set("Content-Type", "application/octet-stream")
+            .map_err(Error::from)
+            .context("setting content type")?;
+
+        let response: Response = JsFuture::from(fetch_with_request(&request))
+            .await
+            .map_err(Error::from)
+            .context("sending request")?
+            .dyn_into()
+            .map_err(Error::from)
+            .context("fetch resolved to something other than a response")?;
+        let body = JsFuture::from(
+            response
+                .array_buffer()
+                .map_err(Error::from)
+                .context("reading response")?,
+        )
+        .await
+        .map_err(Error::from)
+        .context("reading response")?;
+        let body = js_sys::Uint8Array::new(&body).to_vec();
+
+        if !response.ok() {
+            return Err(anyhow::anyhow!(
+                "server responded {}: {}",
+                response.status(),
+                String::from_utf8_lossy(&body)
+            )
+            .into());
+        }
+        Ok(body)
+    }
+}
+
+impl Transport for FetchTransport {
+    async fn get(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        self.fetch("GET", path, None).await.map_err(|err| err.0)
+    }
+
+    async fn post(&self, path: &str, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        self.fetch("POST", path, Some(body))
+            .await
+            .map_err(|err| err.0)
+    }
+}
+
+/// What happened during a sync
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct SyncReport {
+    /// Number of batches of local changes pushed to the server
+    pub pushed: u32,
+    /// Number of batches of remote changes pulled from the server
+    pub pulled: u32,
+    /// Number of remote row changes applied locally
+    pub applied: u32,
+    /// Number of remote row changes which conflicted with local changes
+    pub conflicts: u32,
+    /// Number of remote row changes which referred to rows which no longer exist
+    pub skipped: u32,
+}
+
+impl From<sync_service::client::SyncReport> for SyncReport {
+    fn from(report: sync_service::client::SyncReport) -> Self {
+        let count = |n: usize| n.try_into().unwrap_or(u32::MAX);
+        Self {
+            pushed: count(report.pushed),
+            pulled: count(report.pulled),
+            applied: count(report.applied.applied),
+            conflicts: count(report.applied.conflicts),
+            skipped: count(report.applied.skipped),
+        }
+    }
+}
+
+/// A client of a sync server
+#[wasm_bindgen]
+pub struct SyncClient(Client<FetchTransport>);
+
+#[wasm_bindgen]
+impl SyncClient {
+    /// Create a client for the sync server at `url`.
+    ///
+    /// To resume where a previous client left off, pass its `id` and `cursor`.
+    /// Otherwise the client gets a fresh id and pulls everything on the server.
+    #[wasm_bindgen(constructor)]
+    pub fn new(url: &str, id: Option<String>, cursor: Option<u64>) -> Result<Self> {
+        let id = id
+            .map(|id| Uuid::parse_str(&id))
+            .transpose()
+            .context("parsing client id")?
+            .unwrap_or_else(Uuid::now_v7);
+        let transport = FetchTransport {
+            base_url: url.trim_end_matches('/').to_owned(),
+        };
+        Ok(Self(Client::new(
+            transport,
+            id,
+            Cursor::from(cursor.unwrap_or_default()),
+        )))
+    }
+
+    /// The id of this client; persist it along with the cursor to resume later
+    pub fn id(&self) -> String {
+        self.0.id().to_string()
+    }
+
+    /// The latest point in the server's history which has been applied locally
+    pub fn cursor(&self) -> u64 {
+        self.0.cursor().into()
+    }
+
+    /// Push this database to the server, then pull and apply changes from other clients.
+    ///
+    /// The whole database is pushed each time as a snapshot, deletions included, which replaces
+    /// whatever this client pushed before.
+    /// Conflicting edits are resolved in favour of the most recent.
+    pub async fn sync(&mut self, database: &Database) -> Result<SyncReport> {
+        log_call!(
+            "SyncClient::sync"() =>
+            self.0.sync_snapshot(database.connection()?, ConflictPolicy::LastWriteWins).await;
+            elide_ok
+        )
+        .map(Into::into)
+        .map_err(Into::into)
+    }
+}
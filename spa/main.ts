@@ -1,4 +1,23 @@
-import wasm_init, { Database, TodoList, apply_schema, db_is_encrypted } from "./ffi";
+import wasm_init, { Backend, Database, OpenOptions, PassphraseStrength, TodoList, apply_schema, passphrase_strength, set_log_level } from "./ffi";
+import type { ErrorCode, ItemObject, LogLevel, TodoError } from "./ffi";
+
+/**
+ * Whether `err` was thrown by the wasm library, and so has a `code` to branch on
+ */
+function isTodoError(err: unknown, code?: ErrorCode): err is TodoError {
+    return err instanceof Error && err.name === 'TodoError' && (code === undefined || (err as TodoError).code === code);
+}
+
+/**
+ * The messages of `err` and each error which caused it, outermost first
+ */
+function errorChain(err: Error): string[] {
+    const chain = [err.message];
+    for (let cause = err.cause; cause instanceof Error; cause = cause.cause) {
+        chain.push(cause.message);
+    }
+    return chain;
+}
 
 /**
  * Application state
@@ -96,36 +115,46 @@ class TodoApp {
         console.log('[INIT] Starting initialization');
         await wasm_init("./ffi_bg.wasm");
         console.log('[INIT] WASM initialized');
+        // `?log=debug` in the URL shows every call into the wasm library, and what the database does about it
+        const logLevel = new URLSearchParams(window.location.search).get('log');
+        if (logLevel) {
+            set_log_level(logLevel as LogLevel);
+        }
         this.setStatus('Connecting to database...');
 
         // Ensure modal is hidden on startup
         console.log('[INIT] Hiding modal on startup');
         this.hideModal();
 
-        // Check encryption before opening a connection — the key must be
-        // provided as the first operation on an encrypted connection.
-        console.log('[INIT] Checking if database is encrypted...');
-        const isEncrypted = await db_is_encrypted('todo_app');
-        console.log('[INIT] Database encrypted:', isEncrypted);
-
-        let db: Database;
-        if (isEncrypted) {
-            // Connect with key; modal stays open until the passphrase is correct or the user cancels.
-            console.log('[INIT] Connecting to encrypted database...');
-            const result = await this.promptAndConnectEncrypted('todo_app');
-            if (result === null) {
-                this.setStatus('Connection cancelled — reload the page to try again');
-                console.log('[INIT] Attaching event listeners (no db)');
-                this.attachEventListeners();
-                this.updateEncryptionStatus();
-                this.updateEncryptionButton();
-                return;
-            }
-            db = result;
-        } else {
-            db = await Database.connect('todo_app');
-            console.log('[INIT] Applying schema');
+        // An encrypted database asks for its passphrase through the modal until it is correct or the user cancels.
+        console.log('[INIT] Opening database...');
+        // With `?private` in the URL, the database lives in memory alone and is gone when the page is
+        const backend = new URLSearchParams(window.location.search).has('private') ? Backend.Memory : undefined;
+        const options = new OpenOptions(undefined, undefined, (name, attempts) => this.promptPassphrase(name, attempts), backend);
+        const opened = await Database.open('todo_app', options);
+        if (!('database' in opened)) {
+            // the passphrase prompt was cancelled, after any number of wrong passphrases
+            console.log('[INIT] Not unlocked after', opened.attempts, 'wrong passphrases');
+            this.setStatus('Connection cancelled — reload the page to try again');
+            console.log('[INIT] Attaching event listeners (no db)');
+            this.attachEventListeners();
+            this.updateEncryptionStatus();
+            this.updateEncryptionButton();
+            return;
+        }
+        const db = opened.database;
+        console.log('[INIT] Applying schema');
+        try {
             await apply_schema(db);
+        } catch (err) {
+            if (!isTodoError(err, 'SCHEMA')) throw err;
+            console.error(err);
+            db.close();
+            this.setStatus('The database could not be upgraded: ' + this.getErrorMessage(err));
+            this.attachEventListeners();
+            this.updateEncryptionStatus();
+            this.updateEncryptionButton();
+            return;
         }
 
         console.log('[INIT] Database connected');
@@ -168,6 +197,8 @@ class TodoApp {
             await this.handleEncryptionButtonClick();
         });
         // Note: Modal cancel button is handled by individual modal functions to avoid conflicts
+        // Close the connection so that everything is written out before the page goes away
+        window.addEventListener('pagehide', () => this.state.db?.close());
         console.log('[EVENTS] Event listeners attached');
     }
 
@@ -304,22 +335,13 @@ class TodoApp {
         this.dom.currentListTitle.textContent = this.state.currentList.title();
         this.dom.itemsControls.classList.remove('hidden');
 
-        const itemIds = this.state.currentList.item_ids();
-        for (let i = 0; i < itemIds.length; i++) {
-            const itemId = itemIds[i];
-            const itemEl = this.createItemElement(itemId);
-            if (itemEl) {
-                this.dom.itemsEl.appendChild(itemEl);
-            }
+        for (const item of this.state.currentList.items()) {
+            this.dom.itemsEl.appendChild(this.createItemElement(item));
         }
     }
 
-    private createItemElement(itemId: number): HTMLLIElement | null {
-        if (!this.state.currentList) return null;
-
-        const item = this.state.currentList.item(itemId);
-        if (!item) return null;
-
+    private createItemElement(item: ItemObject): HTMLLIElement {
+        const itemId = item.id;
         const li = document.createElement('li');
 
         // Left side: checkbox and description
@@ -328,15 +350,15 @@ class TodoApp {
 
         const checkbox = document.createElement('input');
         checkbox.type = 'checkbox';
-        checkbox.checked = item.is_completed();
+        checkbox.checked = item.is_completed;
         checkbox.onchange = () => this.handleToggleItem(itemId, checkbox.checked);
 
         const desc = document.createElement('span');
         desc.className = 'item-desc';
-        if (item.is_completed()) {
+        if (item.is_completed) {
             desc.classList.add('completed');
         }
-        desc.textContent = item.description();
+        desc.textContent = item.description;
 
         left.appendChild(checkbox);
         left.appendChild(desc);
@@ -459,12 +481,7 @@ class TodoApp {
 
     private getErrorMessage(err: unknown): string {
         if (err instanceof Error) {
-            // Check if it has an error chain from the proxy
-            if ((err as any).errorChain) {
-                const chain = (err as any).errorChain as string[];
-                return chain.join('\n  Caused by: ');
-            }
-            return err.message;
+            return errorChain(err).join('\n  Caused by: ');
         }
 
         // Handle structured error objects
@@ -525,32 +542,23 @@ class TodoApp {
         this.dom.modalError.classList.remove('hidden');
     }
 
-    /// Open a passphrase modal and attempt `Database.connect_with_key`.
-    /// Keeps the modal open on wrong passphrase; resolves with the connected
-    /// Database on success, or null if the user cancels.
-    private async promptAndConnectEncrypted(name: string): Promise<Database | null> {
-        console.log('[DECRYPT] Setting up decrypt modal');
+    /// Open a passphrase modal for `Database.open`, saying so if `attempts` passphrases were wrong already.
+    /// Resolves with the entered passphrase, or null if the user cancels.
+    private promptPassphrase(name: string, attempts: number): Promise<string | null> {
+        console.log('[DECRYPT] Setting up decrypt modal for', name, 'after attempts:', attempts);
         this.dom.modalTitle.textContent = 'Decrypt Database';
         this.dom.modalMessage.textContent = 'This database is encrypted. Enter the passphrase to unlock it.';
         this.dom.passphraseInput.required = true;
 
-        return new Promise<Database | null>((resolve) => {
-            const handleSubmit = async (e: Event) => {
+        return new Promise<string | null>((resolve) => {
+            const handleSubmit = (e: Event) => {
                 console.log('[DECRYPT] Form submitted');
                 e.preventDefault();
                 const passphrase = this.dom.passphraseInput.value;
-                console.log('[DECRYPT] Attempting connection with passphrase length:', passphrase.length);
-
-                try {
-                    const db = await Database.connect_with_key(name, passphrase);
-                    console.log('[DECRYPT] Connection successful');
-                    this.hideModal();
-                    cleanup();
-                    resolve(db);
-                } catch (err) {
-                    console.error('[DECRYPT] Connection failed:', err);
-                    this.showModalError('Incorrect passphrase. Please try again.');
-                }
+                console.log('[DECRYPT] Trying passphrase of length:', passphrase.length);
+                this.hideModal();
+                cleanup();
+                resolve(passphrase);
             };
 
             const handleCancel = (e: Event) => {
@@ -572,6 +580,9 @@ class TodoApp {
             this.dom.modalCancel.addEventListener('click', handleCancel);
 
             this.showModal();
+            if (attempts > 0) {
+                this.showModalError('Incorrect passphrase. Please try again.');
+            }
         });
     }
 
@@ -621,10 +632,25 @@ class TodoApp {
                     return;
                 }
 
+                if (passphrase === '' && !confirm('Remove encryption? Anyone with access to this browser will be able to read the database.')) {
+                    console.log('[SET_ENCRYPTION] Removing encryption not confirmed');
+                    return;
+                }
+
+                if (passphrase !== '' && passphrase_strength(passphrase) === PassphraseStrength.Weak) {
+                    console.log('[SET_ENCRYPTION] Weak passphrase rejected');
+                    this.showModalError('That passphrase is weak; try a longer one, or a few unrelated words.');
+                    return;
+                }
+
                 try {
                     console.log('[SET_ENCRYPTION] Setting encryption key...');
                     this.setStatus('Updating encryption...');
-                    this.state.db!.set_key(passphrase);
+                    if (passphrase === '') {
+                        this.state.db!.remove_key(true);
+                    } else {
+                        this.state.db!.set_key(passphrase);
+                    }
 
                     if (passphrase === '') {
                         console.log('[SET_ENCRYPTION] Encryption removed');
@@ -757,10 +783,9 @@ function formatError(err: unknown): { brief: string; detailed: string } {
     if (err instanceof Error) {
         const brief = err.message;
 
-        // Check if it has an error chain from the proxy
-        if ((err as any).errorChain) {
-            const chain = (err as any).errorChain as string[];
-            const detailed = chain.join('\n  Caused by: ');
+        // Errors from the wasm library say why they happened through their causes
+        if (isTodoError(err)) {
+            const detailed = errorChain(err).join('\n  Caused by: ');
             return { brief, detailed };
         }
 
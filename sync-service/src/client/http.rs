@@ -0,0 +1,148 @@
+//! A minimal HTTP/1.1 transport over plain TCP.
+//!
+//! It speaks just enough HTTP to talk to [`crate::server`]: one request per connection, bodies
+//! framed by content length, no TLS. That keeps native front-ends free of an HTTP client stack for
+//! what is meant to be a local service. The socket is blocking, so requests run on a thread pool.
+
+use std::{
+    io::{Read as _, Write as _},
+    net::TcpStream,
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+
+use super::Transport;
+
+/// How long to wait on the server before giving up
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Talks to a sync server over plain HTTP.
+///
+/// Requests run on a pool of threads set aside for blocking work, so awaiting one doesn't stall the
+/// executor, however long the server takes.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    /// `host:port`
+    authority: String,
+    /// Prefix for every request path, without a trailing slash
+    base_path: String,
+}
+
+impl HttpTransport {
+    /// Talk to the server at `url`, which must look like `http://host:port[/base/path]`.
+    pub fn new(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow!("HttpTransport::new: only http:// urls are supported"))?;
+        let (authority, base_path) = rest.split_once('/').unwrap_or((rest, ""));
+        ensure!(!authority.is_empty(), "HttpTransport::new: url has no host");
+        let authority = if authority.contains(':') {
+            authority.to_owned()
+        } else {
+            format!("{authority}:80")
+        };
+        let base_path = match base_path.trim_end_matches('/') {
+            "" => String::new(),
+            path => format!("/{path}"),
+        };
+        Ok(Self {
+            authority,
+            base_path,
+        })
+    }
+
+    /// Perform a request on the blocking thread pool
+    async fn send(&self, method: &'static str, path: &str, body: Vec<u8>) -> Result<Vec<u8>> {
+        let transport = self.clone();
+        let path = path.to_owned();
+        blocking::unblock(move || transport.request(method, &path, &body)).await
+    }
+
+    fn request(&self, method: &str, path: &str, body: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = TcpStream::connect(&self.authority)
+            .with_context(|| format!("connecting to {}", self.authority))?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+            .context("setting timeouts")?;
+
+        let head = format!(
+            "{method} {}{path} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.base_path,
+            self.authority,
+            body.len(),
+        );
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|()| stream.write_all(body))
+            .context("sending request")?;
+
+        let mut response = Vec::new();
+        stream
+            .read_to_end(&mut response)
+            .context("reading response")?;
+        parse_response(response)
+    }
+}
+
+impl Transport for HttpTransport {
+    async fn get(&self, path: &str) -> Result<Vec<u8>> {
+        self.send("GET", path, Vec::new())
+            .await
+            .with_context(|| format!("HttpTransport::get: GET {path}"))
+    }
+
+    async fn post(&self, path: &str, body: Vec<u8>) -> Result<Vec<u8>> {
+        self.send("POST", path, body)
+            .await
+            .with_context(|| format!("HttpTransport::post: POST {path}"))
+    }
+}
+
+/// Split a response into its head and body, failing unless it was successful
+fn parse_response(mut response: Vec<u8>) -> Result<Vec<u8>> {
+    let head_len = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("response has no end of headers"))?;
+    let head = std::str::from_utf8(&response[..head_len]).context("response head is not utf-8")?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("malformed status line"))?;
+
+    let mut content_length = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .context("bad content-length")?,
+            );
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            bail!("unsupported transfer encoding: {}", value.trim());
+        }
+    }
+
+    let mut body = response.split_off(head_len + 4);
+    if let Some(content_length) = content_length {
+        ensure!(body.len() >= content_length, "response body truncated");
+        body.truncate(content_length);
+    }
+
+    if !(200..300).contains(&status) {
+        bail!(
+            "server responded {status}: {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+    Ok(body)
+}
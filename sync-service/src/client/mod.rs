@@ -0,0 +1,187 @@
+//! Exchange a database's changes with a sync server.
+//!
+//! The client doesn't perform any I/O itself; a [`Transport`] carries its requests to the server.
+//! Natively, [`HttpTransport`] does that over a plain TCP connection. In the browser, the front-end
+//! supplies a transport built on `fetch`.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod http;
+
+use anyhow::{Context as _, Result};
+use log::debug;
+use rusqlite::Connection;
+use todo_list::sync::{self, ApplyReport, ConflictPolicy, Recorder};
+use uuid::Uuid;
+
+use crate::protocol::{Cursor, Pull, pull_path, push_path, snapshot_path};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::http::HttpTransport;
+
+/// Carries requests to the sync server.
+///
+/// Paths include the query string. Implementations must fail if the server responds with anything
+/// but success, and otherwise produce the response body.
+pub trait Transport {
+    /// Perform a `GET` request
+    fn get(&self, path: &str) -> impl Future<Output = Result<Vec<u8>>>;
+
+    /// Perform a `POST` request
+    fn post(&self, path: &str, body: Vec<u8>) -> impl Future<Output = Result<Vec<u8>>>;
+}
+
+/// What happened during a sync
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Number of batches of local changes pushed to the server
+    pub pushed: usize,
+    /// Number of batches of remote changes pulled from the server
+    pub pulled: usize,
+    /// The combined outcome of applying the pulled batches
+    pub applied: ApplyReport,
+}
+
+/// A client of the sync server.
+///
+/// Front-ends which want to resume where they left off across restarts should persist the
+/// [`Self::id`] and [`Self::cursor`] of their client and pass them back to [`Self::new`].
+#[derive(Debug)]
+pub struct Client<T> {
+    transport: T,
+    /// Identifies this client's pushes, so that the server doesn't send them back
+    id: Uuid,
+    /// The latest point in the server's history which has been applied locally
+    cursor: Cursor,
+    /// Local changes which have been taken from the recorder but not yet accepted by the server
+    pending: Vec<Vec<u8>>,
+    /// Whether the first pending batch is a snapshot
+    pending_snapshot: bool,
+}
+
+impl<T: Transport> Client<T> {
+    /// Create a client which has already applied everything up to `cursor`.
+    ///
+    /// A client which has never synced should start from the default cursor.
+    pub fn new(transport: T, id: Uuid, cursor: Cursor) -> Self {
+        Self {
+            transport,
+            id,
+            cursor,
+            pending: Vec::new(),
+            pending_snapshot: false,
+        }
+    }
+
+    /// Identifies this client's pushes
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The latest point in the server's history which has been applied locally
+    pub fn cursor(&self) -> Cursor {
+        self.cursor
+    }
+
+    /// Push the changes recorded since the last sync, then pull and apply changes from other clients.
+    pub async fn sync(
+        &mut self,
+        recorder: &mut Recorder<'_>,
+        policy: ConflictPolicy,
+    ) -> Result<SyncReport> {
+        if !recorder.is_empty() {
+            let changes = recorder
+                .take_changes()
+                .await
+                .context("Client::sync: taking recorded changes")?;
+            self.pending.push(changes);
+        }
+        let pushed = self.push_pending().await?;
+
+        let pull = self.pull().await?;
+        let mut applied = ApplyReport::default();
+        for batch in &pull.batches {
+            applied += recorder
+                .apply(batch, policy)
+                .await
+                .context("Client::sync: applying pulled changes")?;
+        }
+        Ok(self.finish(pushed, pull, applied))
+    }
+
+    /// Push the entire database, then pull and apply changes from other clients.
+    ///
+    /// This suits front-ends which can't keep a [`Recorder`] alive between syncs. The snapshot
+    /// carries local deletions, and replaces whatever this client pushed before on the server.
+    pub async fn sync_snapshot(
+        &mut self,
+        connection: &Connection,
+        policy: ConflictPolicy,
+    ) -> Result<SyncReport> {
+        // anything still pending is superseded by the snapshot
+        self.pending.clear();
+        let changes = sync::snapshot(connection)
+            .await
+            .context("Client::sync_snapshot: taking snapshot")?;
+        self.pending.push(changes);
+        self.pending_snapshot = true;
+        let pushed = self.push_pending().await?;
+
+        let pull = self.pull().await?;
+        let mut applied = ApplyReport::default();
+        for batch in &pull.batches {
+            applied += sync::apply(connection, batch, policy)
+                .await
+                .context("Client::sync_snapshot: applying pulled changes")?;
+        }
+        Ok(self.finish(pushed, pull, applied))
+    }
+
+    /// Push pending batches in order, keeping any which the server didn't accept for next time
+    async fn push_pending(&mut self) -> Result<usize> {
+        let mut pushed = 0;
+        while let Some(changes) = self.pending.first() {
+            let path = if self.pending_snapshot {
+                snapshot_path(self.id)
+            } else {
+                push_path(self.id)
+            };
+            let response = self
+                .transport
+                .post(&path, changes.clone())
+                .await
+                .context("Client::push_pending: pushing changes")?;
+            let cursor = String::from_utf8_lossy(&response);
+            let cursor = Cursor::parse(&cursor).context("Client::push_pending: reading cursor")?;
+            debug!("cursor" = u64::from(cursor), "len" = changes.len(); "pushed changes");
+            self.pending.remove(0);
+            self.pending_snapshot = false;
+            pushed += 1;
+        }
+        Ok(pushed)
+    }
+
+    /// Fetch everything other clients have pushed since our cursor
+    async fn pull(&self) -> Result<Pull> {
+        let response = self
+            .transport
+            .get(&pull_path(self.id, self.cursor))
+            .await
+            .context("Client::pull: pulling changes")?;
+        Pull::from_bytes(&response).context("Client::pull: decoding response")
+    }
+
+    /// Advance the cursor once a pull has been applied
+    fn finish(&mut self, pushed: usize, pull: Pull, applied: ApplyReport) -> SyncReport {
+        let report = SyncReport {
+            pushed,
+            pulled: pull.batches.len(),
+            applied,
+        };
+        debug!(
+            "from" = u64::from(self.cursor), "to" = u64::from(pull.cursor), report:debug;
+            "synced"
+        );
+        self.cursor = pull.cursor;
+        report
+    }
+}
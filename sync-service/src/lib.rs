@@ -0,0 +1,13 @@
+//! Synchronize todo-list databases through a central server.
+//!
+//! The [`server`] relays batches of changes between clients without interpreting them, so it needs
+//! no knowledge of the todo-list schema. The [`client`] records local changes, pushes them to the
+//! server, and applies whatever other clients have pushed in the meantime. Both speak the HTTP
+//! [`protocol`].
+
+pub mod protocol;
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod server;
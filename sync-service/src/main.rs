@@ -0,0 +1,31 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use anyhow::{Context as _, Result};
+use clap::Parser;
+use sync_service::server::Server;
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// Path to the server's database
+    #[arg(short = 'p', long, default_value = "sync.sqlite")]
+    db_path: PathBuf,
+
+    /// Address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:8417")]
+    listen: SocketAddr,
+
+    /// Origin of a web page whose browser client may sync, such as `https://todo.example.com`;
+    /// may be repeated. Without any, only native clients are served
+    #[arg(long = "allow-origin", value_name = "ORIGIN")]
+    allowed_origins: Vec<String>,
+}
+
+fn main() -> Result<()> {
+    // log at info by default, so the address the server is listening on is shown
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Args::parse();
+    let server = Server::open(args.listen, &args.db_path)
+        .context("starting server")?
+        .allow_origins(args.allowed_origins);
+    server.run()
+}
@@ -0,0 +1,126 @@
+//! The HTTP protocol spoken between sync clients and the server.
+//!
+//! The server keeps the batches of changes it is sent, in the order it received them, and numbers
+//! each with a [`Cursor`]. It never looks inside a batch. Clients push their local changes, and pull
+//! whatever other clients have pushed since the last cursor they saw. A client which pushes a
+//! snapshot of its whole database marks it as one, and the server drops every batch that client
+//! pushed before, since the snapshot supersedes them.
+//!
+//! Both endpoints identify the client by its uuid in the `client` query parameter:
+//!
+//! - `POST /v1/changes?client=<uuid>` stores the request body as a new batch, and responds with its
+//!   cursor as decimal text. With `&snapshot=1`, the client's earlier batches are dropped.
+//! - `GET /v1/changes?client=<uuid>&since=<cursor>` responds with every batch after `since` which
+//!   was pushed by some other client, encoded as a [`Pull`].
+
+use anyhow::{Context as _, Result, bail, ensure};
+use uuid::Uuid;
+
+/// Path of the changes endpoint
+pub const CHANGES_PATH: &str = "/v1/changes";
+
+/// The position of a batch of changes in the server's history.
+///
+/// Cursors increase monotonically; the default cursor precedes every batch.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    derive_more::Display,
+    derive_more::From,
+    derive_more::Into,
+)]
+pub struct Cursor(u64);
+
+impl Cursor {
+    /// Parse a cursor from its decimal representation
+    pub fn parse(s: &str) -> Result<Self> {
+        s.trim()
+            .parse()
+            .map(Self)
+            .with_context(|| format!("Cursor::parse: {s:?} is not a cursor"))
+    }
+}
+
+/// Path and query for pushing a batch of changes
+pub fn push_path(client: Uuid) -> String {
+    format!("{CHANGES_PATH}?client={client}")
+}
+
+/// Path and query for pushing a snapshot, which replaces every batch the client pushed before
+pub fn snapshot_path(client: Uuid) -> String {
+    format!("{CHANGES_PATH}?client={client}&snapshot=1")
+}
+
+/// Path and query for pulling the batches after `since`
+pub fn pull_path(client: Uuid, since: Cursor) -> String {
+    format!("{CHANGES_PATH}?client={client}&since={since}")
+}
+
+/// The response to a pull request.
+///
+/// Layout, all integers little-endian:
+///
+/// ```text
+/// cursor: u64 | count: u32 | batches: [len: u32, batch: [u8; len]; count]
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Pull {
+    /// The latest cursor on the server.
+    ///
+    /// This may be later than any batch in `batches`, if the client pushed those batches itself.
+    pub cursor: Cursor,
+    /// Batches of changes pushed by other clients, oldest first
+    pub batches: Vec<Vec<u8>>,
+}
+
+impl Pull {
+    /// Serialize this response
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = self
+            .batches
+            .iter()
+            .map(|batch| 4 + batch.len())
+            .sum::<usize>();
+        let mut out = Vec::with_capacity(12 + len);
+        out.extend_from_slice(&self.cursor.0.to_le_bytes());
+        out.extend_from_slice(&(self.batches.len() as u32).to_le_bytes());
+        for batch in &self.batches {
+            out.extend_from_slice(&(batch.len() as u32).to_le_bytes());
+            out.extend_from_slice(batch);
+        }
+        out
+    }
+
+    /// Deserialize a response produced by [`Self::to_bytes`]
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let mut take = |n: usize| {
+            if bytes.len() < n {
+                bail!("pull response truncated");
+            }
+            let (head, tail) = bytes.split_at(n);
+            bytes = tail;
+            Ok(head)
+        };
+
+        let cursor = Cursor(u64::from_le_bytes(
+            take(8)?.try_into().expect("took 8 bytes"),
+        ));
+        let count = u32::from_le_bytes(take(4)?.try_into().expect("took 4 bytes"));
+        let batches = (0..count)
+            .map(|_| {
+                let len = u32::from_le_bytes(take(4)?.try_into().expect("took 4 bytes"));
+                Ok(take(len as usize)?.to_vec())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        ensure!(bytes.is_empty(), "trailing bytes after pull response");
+        Ok(Self { cursor, batches })
+    }
+}
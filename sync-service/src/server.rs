@@ -0,0 +1,329 @@
+//! The sync server: an HTTP front-end to a store of change batches.
+
+use std::{
+    io::Read as _,
+    net::{SocketAddr, ToSocketAddrs},
+    path::Path,
+    sync::Arc,
+    thread,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use log::{debug, error, info};
+use rusqlite::Connection;
+use tiny_http::{Header, Method, Request, Response};
+use uuid::Uuid;
+
+use crate::protocol::{CHANGES_PATH, Cursor, Pull};
+
+/// Largest batch of changes the server accepts
+const MAX_BATCH_LEN: usize = 16 << 20;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS batches (
+    cursor INTEGER PRIMARY KEY AUTOINCREMENT,
+    client BLOB NOT NULL,
+    received_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    changes BLOB NOT NULL
+);
+";
+
+/// An error to report to the client, as opposed to a failure of the server itself
+#[derive(Debug, derive_more::Display)]
+#[display("{status}: {message}")]
+struct Rejection {
+    status: u16,
+    message: String,
+}
+
+impl Rejection {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: 400,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Rejection {
+    fn from(err: anyhow::Error) -> Self {
+        error!("err":% = format!("{err:#}"); "failed to handle request");
+        Self {
+            status: 500,
+            message: "internal server error".into(),
+        }
+    }
+}
+
+/// A sync server bound to a local address
+pub struct Server {
+    http: Arc<tiny_http::Server>,
+    store: Connection,
+    /// Origins of the web pages which may use this server
+    allowed_origins: Vec<String>,
+}
+
+impl Server {
+    /// Bind to `address` and store batches in the database at `path`.
+    pub fn open(address: impl ToSocketAddrs, path: impl AsRef<Path>) -> Result<Self> {
+        let store = Connection::open(path).context("Server::open: opening store")?;
+        Self::new(address, store)
+    }
+
+    /// Bind to `address` and store batches through an existing connection.
+    pub fn new(address: impl ToSocketAddrs, store: Connection) -> Result<Self> {
+        store
+            .execute_batch(SCHEMA)
+            .context("Server::new: applying store schema")?;
+        let http = tiny_http::Server::http(address)
+            .map_err(|err| anyhow!("{err}"))
+            .context("Server::new: binding")?;
+        Ok(Self {
+            http: Arc::new(http),
+            store,
+            allowed_origins: Vec::new(),
+        })
+    }
+
+    /// Serve browser clients on web pages from these origins, such as `https://todo.example.com`.
+    ///
+    /// Requests which carry any other `Origin` are refused. Requests without one, as native
+    /// clients send them, are always served.
+    pub fn allow_origins(mut self, origins: impl IntoIterator<Item = String>) -> Self {
+        self.allowed_origins.extend(origins);
+        self
+    }
+
+    /// The address the server is listening on.
+    ///
+    /// Useful after binding to port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.http
+            .server_addr()
+            .to_ip()
+            .context("Server::local_addr: not listening on an ip address")
+    }
+
+    /// Handle requests on the current thread until the server is shut down.
+    pub fn run(self) -> Result<()> {
+        info!("address":? = self.http.server_addr(); "sync server listening");
+        for mut request in self.http.incoming_requests() {
+            let origin = request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv("Origin"))
+                .map(|header| header.value.to_string());
+            let response = match self
+                .check_origin(origin.as_deref())
+                .and_then(|()| self.handle(&mut request))
+            {
+                Ok(body) => Response::from_data(body).with_status_code(200),
+                Err(rejection) => {
+                    debug!("url" = request.url(), "rejection":% = rejection; "rejected request");
+                    Response::from_data(rejection.message.into_bytes())
+                        .with_status_code(rejection.status)
+                }
+            }
+            // the client only understands content-length framing
+            .with_chunked_threshold(usize::MAX);
+            let response = match origin {
+                Some(origin) if self.allowed_origins.contains(&origin) => cors_headers(&origin)
+                    .into_iter()
+                    .fold(response, Response::with_header),
+                _ => response,
+            };
+            if let Err(err) = request.respond(response) {
+                debug!("err":% = err; "failed to send response");
+            }
+        }
+        info!("sync server stopped");
+        Ok(())
+    }
+
+    /// Handle requests on a background thread until the returned handle is dropped.
+    pub fn spawn(self) -> Result<ServerHandle> {
+        let address = self.local_addr()?;
+        let http = Arc::clone(&self.http);
+        let thread = thread::Builder::new()
+            .name("sync-server".into())
+            .spawn(move || self.run())
+            .context("Server::spawn: spawning server thread")?;
+        Ok(ServerHandle {
+            address,
+            http,
+            thread: Some(thread),
+        })
+    }
+
+    /// Refuse requests from web pages on origins which weren't allowed
+    fn check_origin(&self, origin: Option<&str>) -> Result<(), Rejection> {
+        match origin {
+            Some(origin) if !self.allowed_origins.iter().any(|allowed| allowed == origin) => {
+                Err(Rejection {
+                    status: 403,
+                    message: format!("origin not allowed: {origin}"),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn handle(&self, request: &mut Request) -> Result<Vec<u8>, Rejection> {
+        // cors preflight
+        if *request.method() == Method::Options {
+            return Ok(Vec::new());
+        }
+
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        if path != CHANGES_PATH {
+            return Err(Rejection {
+                status: 404,
+                message: format!("no such endpoint: {path}"),
+            });
+        }
+
+        let parameter = |name: &str| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find_map(|(key, value)| (key == name).then_some(value))
+        };
+        let client = parameter("client")
+            .ok_or_else(|| Rejection::bad_request("missing client parameter"))
+            .and_then(|client| {
+                Uuid::parse_str(client).map_err(|_| Rejection::bad_request("malformed client id"))
+            })?;
+        let since = parameter("since")
+            .map(|since| {
+                Cursor::parse(since).map_err(|err| Rejection::bad_request(err.to_string()))
+            })
+            .transpose()?;
+        let snapshot = parameter("snapshot") == Some("1");
+
+        match request.method().clone() {
+            Method::Post => {
+                let mut changes = Vec::new();
+                request
+                    .as_reader()
+                    .take(MAX_BATCH_LEN as u64 + 1)
+                    .read_to_end(&mut changes)
+                    .context("Server::handle: reading request body")?;
+                if changes.len() > MAX_BATCH_LEN {
+                    return Err(Rejection {
+                        status: 413,
+                        message: format!("batches are limited to {MAX_BATCH_LEN} bytes"),
+                    });
+                }
+                let cursor = self.push(client, &changes, snapshot)?;
+                Ok(cursor.to_string().into_bytes())
+            }
+            Method::Get => {
+                let pull = self.pull(client, since.unwrap_or_default())?;
+                Ok(pull.to_bytes())
+            }
+            method => Err(Rejection {
+                status: 405,
+                message: format!("method not allowed: {method}"),
+            }),
+        }
+    }
+
+    /// Store a batch of changes, returning its cursor.
+    ///
+    /// A snapshot replaces every batch the client pushed before.
+    fn push(&self, client: Uuid, changes: &[u8], snapshot: bool) -> Result<Cursor> {
+        let tx = self
+            .store
+            .unchecked_transaction()
+            .context("Server::push: starting transaction")?;
+        if snapshot {
+            let replaced = tx
+                .execute("DELETE FROM batches WHERE client = ?", [client.as_bytes()])
+                .context("Server::push: dropping superseded batches")?;
+            debug!("client":% = client, "count" = replaced; "dropped batches superseded by snapshot");
+        }
+        tx.execute(
+            "INSERT INTO batches (client, changes) VALUES (?, ?)",
+            (client.as_bytes(), changes),
+        )
+        .context("Server::push: storing batch")?;
+        let cursor = Cursor::from(tx.last_insert_rowid() as u64);
+        tx.commit().context("Server::push: committing")?;
+        debug!("client":% = client, "cursor" = u64::from(cursor), "len" = changes.len(); "stored batch");
+        Ok(cursor)
+    }
+
+    /// Get every batch after `since` which `client` didn't push itself
+    fn pull(&self, client: Uuid, since: Cursor) -> Result<Pull> {
+        let cursor = self
+            .store
+            .query_row("SELECT COALESCE(MAX(cursor), 0) FROM batches", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .context("Server::pull: getting latest cursor")?;
+        let batches = self
+            .store
+            .prepare_cached(
+                "SELECT changes FROM batches WHERE cursor > ? AND cursor <= ? AND client != ? ORDER BY cursor",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map(
+                    (u64::from(since) as i64, cursor, client.as_bytes()),
+                    |row| row.get::<_, Vec<u8>>(0),
+                )?
+                .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .context("Server::pull: selecting batches")?;
+        debug!("client":% = client, "since" = u64::from(since), "count" = batches.len(); "serving batches");
+        Ok(Pull {
+            cursor: Cursor::from(cursor as u64),
+            batches,
+        })
+    }
+}
+
+/// A server running on a background thread.
+///
+/// Dropping the handle stops the server.
+pub struct ServerHandle {
+    address: SocketAddr,
+    http: Arc<tiny_http::Server>,
+    thread: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl ServerHandle {
+    /// The address the server is listening on
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The base url clients should use to reach the server
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.http.unblock();
+        if let Some(thread) = self.thread.take()
+            && let Ok(Err(err)) = thread.join()
+        {
+            error!("err":% = format!("{err:#}"); "sync server failed");
+        }
+    }
+}
+
+/// Let a browser client on an allowed origin read the response
+fn cors_headers(origin: &str) -> [Header; 4] {
+    [
+        ("Access-Control-Allow-Origin", origin),
+        ("Access-Control-Allow-Methods", "GET, POST"),
+        ("Access-Control-Allow-Headers", "Content-Type"),
+        // the response differs between origins, so caches must not share it
+        ("Vary", "Origin"),
+    ]
+    .map(|(field, value)| {
+        Header::from_bytes(field, value).expect("origin came from a valid header")
+    })
+}
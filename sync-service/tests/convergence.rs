@@ -0,0 +1,234 @@
+//! Several in-process clients editing concurrently must converge through a localhost server.
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use rusqlite::Connection;
+use sync_service::{
+    client::{Client, HttpTransport},
+    protocol::Cursor,
+    server::{Server, ServerHandle},
+};
+use todo_list::{
+    TodoList, Uuid,
+    sync::{ConflictPolicy, Recorder},
+};
+
+/// A list's uuid, title, and items' uuid, description, and completion
+type Contents = Vec<(Uuid, String, Vec<(Uuid, String, bool)>)>;
+
+/// Everything synced, keyed by uuid rather than local id
+fn contents(connection: &Connection) -> Result<Contents> {
+    let mut lists = connection
+        .prepare("SELECT uuid, title FROM todo_lists ORDER BY uuid")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, Vec::new())))?
+        .collect::<rusqlite::Result<Vec<(Uuid, String, _)>>>()?;
+    for (uuid, _, items) in &mut lists {
+        *items = connection
+            .prepare(
+                "SELECT i.uuid, i.description, i.is_completed FROM todo_items i
+                 JOIN todo_lists l ON l.id = i.list_id WHERE l.uuid = ? ORDER BY i.uuid",
+            )?
+            .query_map([*uuid], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+    }
+    Ok(lists)
+}
+
+fn start_server() -> Result<ServerHandle> {
+    Server::new("127.0.0.1:0", Connection::open_in_memory()?)?.spawn()
+}
+
+fn open_replica() -> Result<Connection> {
+    let connection = Connection::open_in_memory()?;
+    block_on(todo_list::apply_schema(&connection))?;
+    Ok(connection)
+}
+
+fn client(server: &ServerHandle) -> Result<Client<HttpTransport>> {
+    Ok(Client::new(
+        HttpTransport::new(&server.url())?,
+        Uuid::now_v7(),
+        Cursor::default(),
+    ))
+}
+
+#[test]
+fn concurrent_edits_converge() -> Result<()> {
+    let server = start_server()?;
+    let connections = [open_replica()?, open_replica()?, open_replica()?];
+    let mut recorders = connections
+        .iter()
+        .map(Recorder::new)
+        .collect::<Result<Vec<_>>>()?;
+    let mut clients = (0..connections.len())
+        .map(|_| client(&server))
+        .collect::<Result<Vec<_>>>()?;
+
+    let sync_all = |clients: &mut [Client<HttpTransport>], recorders: &mut [Recorder<'_>]| {
+        // two rounds, so that changes pushed late in the first round reach the earlier clients
+        for _ in 0..2 {
+            for (client, recorder) in clients.iter_mut().zip(recorders.iter_mut()) {
+                block_on(client.sync(recorder, ConflictPolicy::LastWriteWins))?;
+            }
+        }
+        anyhow::Ok(())
+    };
+
+    block_on(async {
+        let mut list = TodoList::new(&connections[0], "groceries".into()).await?;
+        for description in ["milk", "eggs", "bread"] {
+            list.add_item(&connections[0], description.into()).await?;
+        }
+        anyhow::Ok(())
+    })?;
+    sync_all(&mut clients, &mut recorders)?;
+
+    let expected = contents(&connections[0])?;
+    assert_eq!(expected.len(), 1);
+    assert_eq!(expected[0].2.len(), 3);
+    for connection in &connections[1..] {
+        assert_eq!(contents(connection)?, expected);
+    }
+
+    // every replica edits its own copy before anyone syncs again
+    let list_uuid = expected[0].0;
+    let item_uuids = expected[0].2.iter().map(|item| item.0).collect::<Vec<_>>();
+    block_on(async {
+        let mut list = TodoList::load_by_uuid(&connections[0], list_uuid).await?;
+        list.set_title("shopping".into());
+        let milk = find_item(&list, item_uuids[0]);
+        list.item_mut(milk)
+            .unwrap()
+            .set_description("oat milk".into());
+        list.save(&connections[0]).await?;
+
+        let mut list = TodoList::load_by_uuid(&connections[1], list_uuid).await?;
+        let milk = find_item(&list, item_uuids[0]);
+        list.item_mut(milk).unwrap().set_is_completed(true);
+        let eggs = find_item(&list, item_uuids[1]);
+        list.remove_item(&connections[1], eggs).await?;
+        list.save(&connections[1]).await?;
+
+        let mut list = TodoList::load_by_uuid(&connections[2], list_uuid).await?;
+        list.add_item(&connections[2], "apples".into()).await?;
+        let bread = find_item(&list, item_uuids[2]);
+        list.item_mut(bread)
+            .unwrap()
+            .set_description("rye bread".into());
+        list.save(&connections[2]).await?;
+
+        anyhow::Ok(())
+    })?;
+    sync_all(&mut clients, &mut recorders)?;
+
+    let converged = contents(&connections[0])?;
+    for connection in &connections[1..] {
+        assert_eq!(contents(connection)?, converged);
+    }
+
+    let (_, title, items) = &converged[0];
+    assert_eq!(title, "shopping");
+    let mut descriptions = items
+        .iter()
+        .map(|(_, description, is_completed)| (description.as_str(), *is_completed))
+        .collect::<Vec<_>>();
+    descriptions.sort();
+    assert_eq!(
+        descriptions,
+        [("apples", false), ("oat milk", true), ("rye bread", false)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn snapshot_brings_late_joiner_up_to_date() -> Result<()> {
+    let server = start_server()?;
+    let early = open_replica()?;
+    let late = open_replica()?;
+
+    block_on(async {
+        let mut list = TodoList::new(&early, "chores".into()).await?;
+        list.add_item(&early, "laundry".into()).await?;
+        anyhow::Ok(())
+    })?;
+
+    // changes made before any recorder existed can only travel as a snapshot
+    let mut early_client = client(&server)?;
+    let report = block_on(early_client.sync_snapshot(&early, ConflictPolicy::LastWriteWins))?;
+    assert_eq!(report.pushed, 1);
+
+    let mut late_client = client(&server)?;
+    let mut recorder = Recorder::new(&late)?;
+    let report = block_on(late_client.sync(&mut recorder, ConflictPolicy::LastWriteWins))?;
+    assert_eq!(report.pulled, 1);
+    assert_eq!(contents(&late)?, contents(&early)?);
+
+    // nothing new to pull the second time around
+    let report = block_on(late_client.sync(&mut recorder, ConflictPolicy::LastWriteWins))?;
+    assert_eq!(report.pulled, 0);
+    assert_eq!(late_client.cursor(), early_client.cursor());
+
+    Ok(())
+}
+
+#[test]
+fn snapshot_syncs_carry_deletions() -> Result<()> {
+    let server = start_server()?;
+    let browser = open_replica()?;
+    let native = open_replica()?;
+
+    let list = block_on(async {
+        let mut list = TodoList::new(&browser, "chores".into()).await?;
+        list.add_item(&browser, "laundry".into()).await?;
+        anyhow::Ok(list)
+    })?;
+    let mut browser_client = client(&server)?;
+    block_on(browser_client.sync_snapshot(&browser, ConflictPolicy::LastWriteWins))?;
+    let mut native_client = client(&server)?;
+    let mut recorder = Recorder::new(&native)?;
+    block_on(native_client.sync(&mut recorder, ConflictPolicy::LastWriteWins))?;
+    assert_eq!(contents(&native)?, contents(&browser)?);
+
+    block_on(TodoList::delete(&browser, list.id()))?;
+    block_on(browser_client.sync_snapshot(&browser, ConflictPolicy::LastWriteWins))?;
+    block_on(native_client.sync(&mut recorder, ConflictPolicy::LastWriteWins))?;
+    assert!(contents(&browser)?.is_empty());
+    assert!(contents(&native)?.is_empty());
+
+    // the deleted rows don't come back with the next snapshot either
+    block_on(browser_client.sync_snapshot(&browser, ConflictPolicy::LastWriteWins))?;
+    assert!(contents(&browser)?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn snapshots_replace_the_clients_earlier_batches() -> Result<()> {
+    let server = start_server()?;
+    let browser = open_replica()?;
+    let mut browser_client = client(&server)?;
+    for title in ["chores", "groceries", "errands"] {
+        block_on(async {
+            TodoList::new(&browser, title.into()).await?;
+            browser_client
+                .sync_snapshot(&browser, ConflictPolicy::LastWriteWins)
+                .await
+        })?;
+    }
+
+    // a late joiner only pulls the latest snapshot, which holds everything
+    let late = open_replica()?;
+    let mut late_client = client(&server)?;
+    let report = block_on(late_client.sync_snapshot(&late, ConflictPolicy::LastWriteWins))?;
+    assert_eq!(report.pulled, 1);
+    assert_eq!(contents(&late)?, contents(&browser)?);
+    Ok(())
+}
+
+fn find_item(list: &TodoList, uuid: Uuid) -> todo_list::ItemId {
+    list.items()
+        .values()
+        .find(|item| item.uuid() == uuid)
+        .expect("item exists")
+        .id()
+}
@@ -0,0 +1,73 @@
+//! Browser requests are only served to the origins the operator allowed.
+
+use std::{
+    io::{Read as _, Write as _},
+    net::TcpStream,
+};
+
+use anyhow::Result;
+use rusqlite::Connection;
+use sync_service::{
+    protocol::{Cursor, pull_path},
+    server::{Server, ServerHandle},
+};
+use uuid::Uuid;
+
+fn start_server(origins: &[&str]) -> Result<ServerHandle> {
+    Server::new("127.0.0.1:0", Connection::open_in_memory()?)?
+        .allow_origins(origins.iter().map(|origin| origin.to_string()))
+        .spawn()
+}
+
+/// Pull from the server, returning the raw response
+fn pull(server: &ServerHandle, origin: Option<&str>) -> Result<String> {
+    let mut stream = TcpStream::connect(server.address())?;
+    let origin = origin
+        .map(|origin| format!("Origin: {origin}\r\n"))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\n{origin}Connection: close\r\n\r\n",
+        pull_path(Uuid::now_v7(), Cursor::default()),
+        server.address(),
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+#[test]
+fn only_allowed_origins_are_served() -> Result<()> {
+    let server = start_server(&["https://todo.example.com"])?;
+
+    let response = pull(&server, Some("https://todo.example.com"))?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(
+        response.contains("Access-Control-Allow-Origin: https://todo.example.com"),
+        "{response}"
+    );
+
+    let response = pull(&server, Some("https://elsewhere.example.com"))?;
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    assert!(
+        !response.contains("Access-Control-Allow-Origin"),
+        "{response}"
+    );
+
+    // native clients send no origin
+    let response = pull(&server, None)?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(
+        !response.contains("Access-Control-Allow-Origin"),
+        "{response}"
+    );
+    Ok(())
+}
+
+#[test]
+fn no_origins_are_allowed_by_default() -> Result<()> {
+    let server = start_server(&[])?;
+    let response = pull(&server, Some("http://localhost:8080"))?;
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    Ok(())
+}
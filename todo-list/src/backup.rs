@@ -0,0 +1,129 @@
+//! Copies of a database taken while it is in use, through SQLite's online backup API.
+//!
+//! Copying the file itself can catch it halfway through a write. The backup API copies it page by page instead,
+//! starting over whenever another connection changes it in the meantime, so the copy is always consistent.
+//!
+//! Unavailable in wasm, whose databases live in IndexedDB and are exported whole instead.
+
+use std::{
+    os::raw::c_int,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context as _, Result, bail, ensure};
+use log::debug;
+use rusqlite::{
+    Connection, OpenFlags,
+    backup::{Backup, StepResult},
+};
+
+use crate::encryption::{self, Key};
+
+/// Pages copied per step; other connections can use the database between steps
+const PAGES_PER_STEP: c_int = 256;
+
+/// How long to wait before retrying a step which found the database locked
+const LOCKED_PAUSE: Duration = Duration::from_millis(50);
+
+/// How long the database may stay locked without the copy making progress before giving up
+const LOCKED_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How far a backup or restore has got, in pages of the database being copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackupProgress {
+    pub copied: u32,
+    pub total: u32,
+}
+
+/// Copy the database behind `connection` to `dest_path`, replacing whatever database is there.
+///
+/// `key` is what the copy is encrypted with; it should be the key `connection` is unlocked with, if any, as SQLCipher
+/// can't copy between databases encrypted differently, and a copy without one would give away the encrypted data.
+///
+/// `progress` is called after every step of the copy. If the copy fails, a file it created is removed again.
+pub fn backup(
+    connection: &Connection,
+    dest_path: impl AsRef<Path>,
+    key: Option<&Key>,
+    progress: impl FnMut(BackupProgress),
+) -> Result<()> {
+    let dest_path = dest_path.as_ref();
+    let dest_exists = std::fs::exists(dest_path).context("backup: checking for destination")?;
+
+    let copied = (|| {
+        let mut dest = Connection::open(dest_path).context("backup: opening destination")?;
+        if let Some(key) = key {
+            encryption::unlock(&dest, key).context("backup: unlocking destination")?;
+        }
+        copy(connection, &mut dest, progress).context("backup: copying database")
+    })();
+    if copied.is_err() && !dest_exists {
+        // best effort
+        let _ = std::fs::remove_file(dest_path);
+    }
+    copied
+}
+
+/// Replace the database behind `connection` with the backup at `src_path`, which is encrypted with `key`, if any.
+///
+/// Other connections to the database see the restored contents once this returns. `progress` works as it does for
+/// [`backup`].
+pub fn restore(
+    connection: &mut Connection,
+    src_path: impl AsRef<Path>,
+    key: Option<&Key>,
+    progress: impl FnMut(BackupProgress),
+) -> Result<()> {
+    let src_path = src_path.as_ref();
+    ensure!(
+        std::fs::exists(src_path).context("restore: checking for backup")?,
+        "restore: {} does not exist",
+        src_path.display()
+    );
+
+    let src = Connection::open_with_flags(src_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .context("restore: opening backup")?;
+    if let Some(key) = key {
+        encryption::unlock(&src, key).context("restore: unlocking backup")?;
+    }
+    copy(&src, connection, progress).context("restore: copying backup")
+}
+
+fn copy(
+    from: &Connection,
+    to: &mut Connection,
+    mut progress: impl FnMut(BackupProgress),
+) -> Result<()> {
+    let backup = Backup::new(from, to).context("starting backup")?;
+    // when the current run of locked steps began
+    let mut locked_since = None;
+    loop {
+        let step = backup.step(PAGES_PER_STEP).context("copying pages")?;
+        let pages = backup.progress();
+        let total = u32::try_from(pages.pagecount).unwrap_or_default();
+        let remaining = u32::try_from(pages.remaining).unwrap_or_default();
+        progress(BackupProgress {
+            copied: total.saturating_sub(remaining),
+            total,
+        });
+
+        match step {
+            StepResult::Done => return Ok(()),
+            StepResult::More => locked_since = None,
+            StepResult::Busy | StepResult::Locked => {
+                let since = *locked_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= LOCKED_TIMEOUT {
+                    bail!(
+                        "the database stayed locked for {}s; try again once other connections are done with it",
+                        LOCKED_TIMEOUT.as_secs()
+                    );
+                }
+                debug!("remaining" = remaining; "database locked during backup; retrying");
+                thread::sleep(LOCKED_PAUSE);
+            }
+            step => bail!("unexpected backup step result: {step:?}"),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Hybrid logical clocks.
+//!
+//! A hybrid logical clock reads like a millisecond wall clock, but never runs backwards and always
+//! ticks past any timestamp it has observed from another replica. So a write made after seeing
+//! another replica's write is always stamped later, even if the two replicas' wall clocks disagree.
+//! The node id breaks ties, making timestamps from different replicas totally ordered.
+//!
+//! A replica whose wall clock is set far in the future would drag every replica it syncs with
+//! along, so timestamps from other replicas are only followed up to [`Hlc::MAX_DRIFT_MILLIS`] ahead
+//! of the local wall clock.
+
+use anyhow::{Context as _, Result, ensure};
+#[cfg(feature = "sync")]
+use log::warn;
+use rusqlite::{
+    Connection, ToSql,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef},
+};
+use uuid::Uuid;
+
+/// A hybrid logical timestamp.
+///
+/// Timestamps order by wall clock, then by counter, then by node.
+/// They are stored as big-endian blobs so that sqlite compares them the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, accessory::Accessors)]
+#[access(get, defaults(all(cp)))]
+pub struct Hlc {
+    /// Milliseconds since the unix epoch
+    millis: u64,
+    /// Distinguishes timestamps issued within the same millisecond
+    counter: u32,
+    /// The replica which issued this timestamp
+    node: Uuid,
+}
+
+impl Hlc {
+    /// Length of the serialized form
+    pub const LEN: usize = 28;
+
+    /// How far a timestamp from another replica may run ahead of the local wall clock
+    pub const MAX_DRIFT_MILLIS: u64 = 5 * 60 * 1000;
+
+    /// Construct a timestamp from its parts
+    pub fn new(millis: u64, counter: u32, node: Uuid) -> Self {
+        Self {
+            millis,
+            counter,
+            node,
+        }
+    }
+
+    /// The next timestamp a replica issues, given the latest one it has issued or observed.
+    ///
+    /// The result is always later than `latest`. Should the counter run out within a millisecond,
+    /// the clock moves on to the next one.
+    pub fn next(latest: Self, now_millis: u64, node: Uuid) -> Self {
+        if now_millis > latest.millis {
+            return Self::new(now_millis, 0, node);
+        }
+        match latest.counter.checked_add(1) {
+            Some(counter) => Self::new(latest.millis, counter, node),
+            None => Self::new(latest.millis + 1, 0, node),
+        }
+    }
+
+    /// This timestamp, pulled back to [`Self::MAX_DRIFT_MILLIS`] ahead of `now_millis` if it is
+    /// further ahead than that
+    pub fn clamp_to(self, now_millis: u64) -> Self {
+        let limit = now_millis.saturating_add(Self::MAX_DRIFT_MILLIS);
+        if self.millis > limit {
+            Self::new(limit, 0, self.node)
+        } else {
+            self
+        }
+    }
+
+    /// Serialize this timestamp
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let mut out = [0; Self::LEN];
+        out[..8].copy_from_slice(&self.millis.to_be_bytes());
+        out[8..12].copy_from_slice(&self.counter.to_be_bytes());
+        out[12..].copy_from_slice(self.node.as_bytes());
+        out
+    }
+
+    /// Deserialize a timestamp produced by [`Self::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() == Self::LEN,
+            "Hlc::from_bytes: expected {} bytes but got {}",
+            Self::LEN,
+            bytes.len()
+        );
+        Ok(Self {
+            millis: u64::from_be_bytes(bytes[..8].try_into().expect("took 8 bytes")),
+            counter: u32::from_be_bytes(bytes[8..12].try_into().expect("took 4 bytes")),
+            node: Uuid::from_slice(&bytes[12..]).expect("took 16 bytes"),
+        })
+    }
+}
+
+impl ToSql for Hlc {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_bytes().to_vec()))
+    }
+}
+
+impl FromSql for Hlc {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Self::from_bytes(value.as_blob()?).map_err(|err| FromSqlError::Other(err.into()))
+    }
+}
+
+/// The current wall clock time in milliseconds since the unix epoch, as sqlite sees it
+const NOW_MILLIS: &str = "CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)";
+
+/// Issue a fresh timestamp from this database's clock.
+pub(crate) fn tick(connection: &Connection) -> Result<Hlc> {
+    let (node, latest, now_millis) = connection
+        .prepare_cached(&format!("SELECT node, clock, {NOW_MILLIS} FROM replica"))
+        .context("tick: preparing statement")?
+        .query_row([], |row| {
+            Ok((
+                row.get::<_, Uuid>(0)?,
+                row.get::<_, Hlc>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })
+        .context("tick: reading clock")?;
+    let hlc = Hlc::next(latest, now_millis.max(0) as u64, node);
+    store(connection, hlc).context("tick: storing clock")?;
+    Ok(hlc)
+}
+
+/// Advance this database's clock past a timestamp from another replica, if it isn't already.
+///
+/// Timestamps too far ahead of the local wall clock are clamped to [`Hlc::MAX_DRIFT_MILLIS`] ahead.
+#[cfg(feature = "sync")]
+pub(crate) fn observe(connection: &Connection, remote: Hlc) -> Result<()> {
+    let now_millis = connection
+        .prepare_cached(&format!("SELECT {NOW_MILLIS}"))
+        .context("observe: preparing clock read")?
+        .query_row([], |row| row.get::<_, i64>(0))
+        .context("observe: reading wall clock")?;
+    let clamped = remote.clamp_to(now_millis.max(0) as u64);
+    if clamped != remote {
+        warn!(
+            "remote_millis" = remote.millis, "now_millis" = now_millis, "node":% = remote.node;
+            "remote clock is too far ahead; only following it part of the way"
+        );
+    }
+    connection
+        .prepare_cached("UPDATE replica SET clock = ?1 WHERE clock < ?1")
+        .context("observe: preparing statement")?
+        .execute([clamped])
+        .context("observe: storing clock")?;
+    Ok(())
+}
+
+fn store(connection: &Connection, hlc: Hlc) -> Result<()> {
+    connection
+        .prepare_cached("UPDATE replica SET clock = ?")
+        .context("store: preparing statement")?
+        .execute([hlc])?;
+    Ok(())
+}
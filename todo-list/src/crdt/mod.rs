@@ -0,0 +1,57 @@
+//! Conflict-free replicated data types describing how concurrent edits merge.
+//!
+//! Each mergeable field of a row is a [`LwwRegister`] stamped with a hybrid logical clock ([`Hlc`]),
+//! and the items of a list form an [`OrSet`] tagged by item uuid. Merging is commutative,
+//! associative, and idempotent, so replicas which have seen the same edits agree, no matter the
+//! order in which they saw them.
+//!
+//! In the database, a register is a column plus a `<column>_clock` column holding its timestamp,
+//! and the removed tags of every set live in the `tombstones` table. The types here are the model
+//! which those tables implement; [`ListState`] and [`ItemState`] spell it out for a whole list.
+
+mod hlc;
+mod or_set;
+mod register;
+
+#[cfg(feature = "sync")]
+pub(crate) use self::hlc::observe;
+pub(crate) use self::hlc::tick;
+pub use self::{hlc::Hlc, or_set::OrSet, register::LwwRegister};
+
+/// State which can absorb another replica's version of itself
+pub trait Merge {
+    /// Merge `other` into `self`.
+    ///
+    /// Must be commutative, associative, and idempotent.
+    fn merge(&mut self, other: &Self);
+}
+
+/// The mergeable state of an item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemState {
+    pub description: LwwRegister<String>,
+    pub is_completed: LwwRegister<bool>,
+    pub position: LwwRegister<i64>,
+}
+
+impl Merge for ItemState {
+    fn merge(&mut self, other: &Self) {
+        self.description.merge(&other.description);
+        self.is_completed.merge(&other.is_completed);
+        self.position.merge(&other.position);
+    }
+}
+
+/// The mergeable state of a list and its items
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListState {
+    pub title: LwwRegister<String>,
+    pub items: OrSet<ItemState>,
+}
+
+impl Merge for ListState {
+    fn merge(&mut self, other: &Self) {
+        self.title.merge(&other.title);
+        self.items.merge(&other.items);
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::{BTreeMap, BTreeSet, btree_map::Entry};
+
+use uuid::Uuid;
+
+use super::Merge;
+
+/// An observed-remove set.
+///
+/// Every addition is tagged with a fresh uuid, and a removal only removes the tags its replica had
+/// observed. So an element added concurrently with a removal survives the merge, and an element
+/// which has been removed never reappears, however late a replica learns of its addition.
+///
+/// Elements are themselves mergeable: two replicas which both hold the element for a tag merge their
+/// versions of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrSet<T> {
+    elements: BTreeMap<Uuid, T>,
+    /// Tags which have been removed; kept so that their elements are never re-added
+    tombstones: BTreeSet<Uuid>,
+}
+
+impl<T> Default for OrSet<T> {
+    fn default() -> Self {
+        Self {
+            elements: BTreeMap::new(),
+            tombstones: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T> OrSet<T> {
+    /// Add an element under a fresh tag.
+    ///
+    /// Does nothing if the tag has already been removed.
+    pub fn add(&mut self, tag: Uuid, element: T) {
+        if !self.tombstones.contains(&tag) {
+            self.elements.entry(tag).or_insert(element);
+        }
+    }
+
+    /// Remove the element with this tag.
+    ///
+    /// Returns the element, if it was present.
+    pub fn remove(&mut self, tag: Uuid) -> Option<T> {
+        self.tombstones.insert(tag);
+        self.elements.remove(&tag)
+    }
+
+    /// Get the element with this tag
+    pub fn get(&self, tag: Uuid) -> Option<&T> {
+        self.elements.get(&tag)
+    }
+
+    /// Get the element with this tag mutably
+    pub fn get_mut(&mut self, tag: Uuid) -> Option<&mut T> {
+        self.elements.get_mut(&tag)
+    }
+
+    /// `true` if the tag has been removed
+    pub fn is_removed(&self, tag: Uuid) -> bool {
+        self.tombstones.contains(&tag)
+    }
+
+    /// Iterate over the elements and their tags, in tag order
+    pub fn iter(&self) -> impl Iterator<Item = (Uuid, &T)> {
+        self.elements.iter().map(|(&tag, element)| (tag, element))
+    }
+
+    /// Number of elements in the set
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// `true` if the set has no elements
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+impl<T: Clone + Merge> Merge for OrSet<T> {
+    fn merge(&mut self, other: &Self) {
+        self.tombstones.extend(&other.tombstones);
+        self.elements
+            .retain(|tag, _| !other.tombstones.contains(tag));
+        for (&tag, element) in &other.elements {
+            if self.tombstones.contains(&tag) {
+                continue;
+            }
+            match self.elements.entry(tag) {
+                Entry::Vacant(entry) => {
+                    entry.insert(element.clone());
+                }
+                Entry::Occupied(mut entry) => entry.get_mut().merge(element),
+            }
+        }
+    }
+}
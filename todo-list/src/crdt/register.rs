@@ -0,0 +1,46 @@
+use super::{Hlc, Merge};
+
+/// A last-write-wins register: a value which remembers when it was written.
+///
+/// Merging keeps whichever write has the later timestamp. Since timestamps are totally ordered,
+/// replicas which have seen the same writes hold the same value, whatever order they saw them in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LwwRegister<T> {
+    value: T,
+    stamp: Hlc,
+}
+
+impl<T> LwwRegister<T> {
+    /// A register holding `value`, written at `stamp`
+    pub fn new(value: T, stamp: Hlc) -> Self {
+        Self { value, stamp }
+    }
+
+    /// The current value
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// When the current value was written
+    pub fn stamp(&self) -> Hlc {
+        self.stamp
+    }
+
+    /// Write a new value, unless the register already holds a later write.
+    ///
+    /// Returns `true` if the value was written.
+    pub fn set(&mut self, value: T, stamp: Hlc) -> bool {
+        let newer = stamp > self.stamp;
+        if newer {
+            self.value = value;
+            self.stamp = stamp;
+        }
+        newer
+    }
+}
+
+impl<T: Clone> Merge for LwwRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        self.set(other.value.clone(), other.stamp);
+    }
+}
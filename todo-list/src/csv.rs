@@ -0,0 +1,430 @@
+//! Comma-separated values, one row per item.
+//!
+//! Exported files have a header row naming each [`Field`], and look like this:
+//!
+//! ```text
+//! list,list_uuid,uuid,description,completed,position,created_at,completed_at
+//! Groceries,0192f5c4-9b0e-7c3a-8d1e-5a4b3c2d1e0f,0192f5c4-a1b2-7c3d-8e4f-5a6b7c8d9e0f,Milk,true,1,2025-01-01T12:01:00Z,2025-01-02T09:30:00Z
+//! ```
+//!
+//! Spreadsheets rarely come from this program, so importing reads the header row through a
+//! [`ColumnMapping`], which says which field each column holds. Columns it doesn't know are
+//! skipped. Only the description is required; see [`to_document`] for how the rest is filled in.
+//!
+//! A row which can't be read, such as one whose `completed` column says `maybe`, is reported as a
+//! [`RowError`] and skipped, and the other rows are imported regardless. Lists without items have no
+//! rows, so they don't survive a round trip.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use rusqlite::Connection;
+use time::{
+    Date, Time, UtcDateTime,
+    format_description::{StaticFormatDescription, well_known::Rfc3339},
+    macros::format_description,
+};
+use uuid::Uuid;
+
+use crate::{
+    DOCUMENT_VERSION, Document, ImportMode, ImportReport, ItemDocument, ListDocument,
+    todo_txt::DEFAULT_LIST,
+};
+
+static DATE_FORMAT: StaticFormatDescription = format_description!("[year]-[month]-[day]");
+
+/// Something a column can hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    /// Title of the item's list
+    List,
+    /// Uuid of the item's list
+    ListUuid,
+    /// Uuid of the item
+    Uuid,
+    Description,
+    /// `true` or `false`; imports also accept `yes`, `no`, `1`, `0`, `x`, and blank
+    Completed,
+    /// Where the item sorts within its list
+    Position,
+    /// RFC 3339 timestamp; imports also accept a bare `YYYY-MM-DD` date
+    CreatedAt,
+    /// RFC 3339 timestamp of when the item was last marked completed. The database dates that by
+    /// its own clock, so it is only ever exported.
+    CompletedAt,
+}
+
+impl Field {
+    /// Every field, in the order export writes them
+    pub const ALL: [Self; 8] = [
+        Self::List,
+        Self::ListUuid,
+        Self::Uuid,
+        Self::Description,
+        Self::Completed,
+        Self::Position,
+        Self::CreatedAt,
+        Self::CompletedAt,
+    ];
+
+    /// The field's name, which is also its column's header in exported files
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::List => "list",
+            Self::ListUuid => "list_uuid",
+            Self::Uuid => "uuid",
+            Self::Description => "description",
+            Self::Completed => "completed",
+            Self::Position => "position",
+            Self::CreatedAt => "created_at",
+            Self::CompletedAt => "completed_at",
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|field| field.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| anyhow!("Field::from_str: unknown field {name:?}"))
+    }
+}
+
+/// Which field each column holds, by header.
+///
+/// Headers are matched case-insensitively, ignoring surrounding whitespace. The default mapping
+/// reads the headers of exported files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping(HashMap<String, Field>);
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Field::ALL
+            .into_iter()
+            .fold(Self::empty(), |mapping, field| {
+                mapping.map(field.name(), field)
+            })
+    }
+}
+
+impl ColumnMapping {
+    /// A mapping which knows no headers
+    pub fn empty() -> Self {
+        Self(HashMap::new())
+    }
+
+    /// Read the column with this header as `field`, replacing whatever it was read as before
+    pub fn map(mut self, header: &str, field: Field) -> Self {
+        self.0.insert(normalize(header), field);
+        self
+    }
+
+    /// The field the column with this header holds, if any
+    pub fn field(&self, header: &str) -> Option<Field> {
+        self.0.get(&normalize(header)).copied()
+    }
+}
+
+fn normalize(header: &str) -> String {
+    header.trim().to_lowercase()
+}
+
+/// A row which was skipped when importing
+#[derive(Debug, Clone, PartialEq, Eq, derive_more::Display)]
+#[display("line {line}: {message}")]
+pub struct RowError {
+    /// Line of the file the row starts on, counting from 1
+    pub line: u64,
+    pub message: String,
+}
+
+/// What happened when importing a CSV file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CsvImport {
+    pub report: ImportReport,
+    /// Rows which were skipped, in file order
+    pub errors: Vec<RowError>,
+}
+
+/// Write every item of a document as a row, list by list
+pub fn from_document(document: &Document) -> Result<String> {
+    let mut writer = ::csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(Field::ALL.map(Field::name))
+        .context("from_document: writing header")?;
+    for list in &document.lists {
+        for item in &list.items {
+            let completed_at = item
+                .completed_at
+                .map(|completed_at| completed_at.format(&Rfc3339))
+                .transpose()
+                .context("from_document: formatting completed_at")?;
+            writer
+                .write_record([
+                    list.title.clone(),
+                    list.uuid.to_string(),
+                    item.uuid.to_string(),
+                    item.description.clone(),
+                    item.is_completed.to_string(),
+                    item.position.to_string(),
+                    item.created_at
+                        .format(&Rfc3339)
+                        .context("from_document: formatting created_at")?,
+                    completed_at.unwrap_or_default(),
+                ])
+                .context("from_document: writing row")?;
+        }
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|err| anyhow!("from_document: flushing: {}", err.error()))?;
+    String::from_utf8(bytes).context("from_document: decoding")
+}
+
+/// The fields of one row, before they are matched up with existing data
+#[derive(Debug, Default)]
+struct Row {
+    list: Option<String>,
+    list_uuid: Option<Uuid>,
+    uuid: Option<Uuid>,
+    description: String,
+    is_completed: bool,
+    position: Option<i64>,
+    created_at: Option<UtcDateTime>,
+}
+
+impl Row {
+    fn parse(columns: &[Option<Field>], record: &::csv::StringRecord) -> Result<Self> {
+        let mut row = Self::default();
+        for (field, value) in columns.iter().zip(record) {
+            let Some(field) = field else { continue };
+            if value.is_empty() {
+                continue;
+            }
+            let in_column = || format!("reading {field} {value:?}");
+            match field {
+                Field::List => row.list = Some(value.to_owned()),
+                Field::ListUuid => {
+                    row.list_uuid = Some(Uuid::parse_str(value).with_context(in_column)?);
+                }
+                Field::Uuid => row.uuid = Some(Uuid::parse_str(value).with_context(in_column)?),
+                Field::Description => row.description = value.to_owned(),
+                Field::Completed => {
+                    row.is_completed = match value.to_lowercase().as_str() {
+                        "true" | "yes" | "1" | "x" => true,
+                        "false" | "no" | "0" => false,
+                        _ => bail!("{}: expected true or false", in_column()),
+                    };
+                }
+                Field::Position => row.position = Some(value.parse().with_context(in_column)?),
+                Field::CreatedAt => {
+                    let created_at = UtcDateTime::parse(value, &Rfc3339)
+                        .or_else(|_| {
+                            Date::parse(value, DATE_FORMAT)
+                                .map(|date| UtcDateTime::new(date, Time::MIDNIGHT))
+                        })
+                        .with_context(in_column)?;
+                    row.created_at = Some(created_at);
+                }
+                Field::CompletedAt => {}
+            }
+        }
+        ensure!(!row.description.is_empty(), "no description");
+        Ok(row)
+    }
+}
+
+/// Read rows into a document, skipping rows which can't be read.
+///
+/// `existing` is the current contents of the database the document is destined for. A row without
+/// a list uuid belongs to the list named in its `list` column, which may be an existing one, or to
+/// [`DEFAULT_LIST`] if it names none. A row without a uuid is matched to an existing item of its
+/// list by description, if possible. Everything else is new. Rows without a position follow the
+/// row before them in their list.
+///
+/// Fails outright if the header row can't be read, or if no column holds the description.
+pub fn to_document(
+    text: &str,
+    mapping: &ColumnMapping,
+    existing: &Document,
+) -> Result<(Document, Vec<RowError>)> {
+    let mut reader = ::csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(::csv::Trim::All)
+        .from_reader(text.as_bytes());
+    let headers = reader
+        .headers()
+        .context("to_document: reading header row")?
+        .clone();
+    let columns = headers
+        .iter()
+        .map(|header| mapping.field(header))
+        .collect::<Vec<_>>();
+    for field in Field::ALL {
+        let mut headers = headers
+            .iter()
+            .zip(&columns)
+            .filter_map(|(header, column)| (*column == Some(field)).then_some(header));
+        if let (Some(first), Some(second)) = (headers.next(), headers.next()) {
+            bail!("to_document: columns {first:?} and {second:?} both hold {field}");
+        }
+    }
+    ensure!(
+        columns.contains(&Some(Field::Description)),
+        "to_document: no column holds the description"
+    );
+
+    let existing_lists = existing
+        .lists
+        .iter()
+        .map(|list| (list.title.as_str(), list))
+        .collect::<HashMap<_, _>>();
+    let existing_lists_by_uuid = existing
+        .lists
+        .iter()
+        .map(|list| (list.uuid, list))
+        .collect::<HashMap<_, _>>();
+    let existing_items = existing
+        .lists
+        .iter()
+        .flat_map(|list| &list.items)
+        .map(|item| (item.uuid, item))
+        .collect::<HashMap<_, _>>();
+    let now = UtcDateTime::now();
+
+    let mut lists: Vec<ListDocument> = Vec::new();
+    let mut list_indices = HashMap::new();
+    let mut errors = Vec::new();
+    for record in reader.records() {
+        let (line, row) = match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, ::csv::Position::line);
+                (line, Row::parse(&columns, &record))
+            }
+            Err(err) => (
+                err.position().map_or(0, ::csv::Position::line),
+                Err(err.into()),
+            ),
+        };
+        let row = match row {
+            Ok(row) => row,
+            Err(err) => {
+                errors.push(RowError {
+                    line,
+                    message: format!("{err:#}"),
+                });
+                continue;
+            }
+        };
+
+        let existing_list = match row.list_uuid {
+            Some(uuid) => existing_lists_by_uuid.get(&uuid),
+            None => existing_lists.get(row.list.as_deref().unwrap_or(DEFAULT_LIST)),
+        };
+        let title = row
+            .list
+            .clone()
+            .or(existing_list.map(|list| list.title.clone()))
+            .unwrap_or_else(|| DEFAULT_LIST.to_owned());
+        let list_uuid = row
+            .list_uuid
+            .or(existing_list.map(|list| list.uuid))
+            .unwrap_or_else(Uuid::now_v7);
+        // rows naming the same list by title alone belong together
+        let key = row
+            .list_uuid
+            .map_or_else(|| title.clone(), |uuid| uuid.to_string());
+        let list_index = *list_indices.entry(key).or_insert_with(|| {
+            lists.push(ListDocument {
+                uuid: list_uuid,
+                title,
+                created_at: existing_list.map_or(now, |list| list.created_at),
+                items: Vec::new(),
+            });
+            lists.len() - 1
+        });
+        let list = &mut lists[list_index];
+
+        let existing_item = match row.uuid {
+            Some(uuid) => existing_items.get(&uuid).copied(),
+            None => existing_list.and_then(|existing| {
+                existing.items.iter().find(|item| {
+                    item.description == row.description
+                        && !list.items.iter().any(|taken| taken.uuid == item.uuid)
+                })
+            }),
+        };
+        let position = row.position.unwrap_or_else(|| {
+            list.items
+                .last()
+                .map_or(1, |previous| previous.position + 1)
+        });
+        list.items.push(ItemDocument {
+            uuid: row
+                .uuid
+                .or(existing_item.map(|item| item.uuid))
+                .unwrap_or_else(Uuid::now_v7),
+            description: row.description,
+            is_completed: row.is_completed,
+            position,
+            created_at: row
+                .created_at
+                .or(existing_item.map(|item| item.created_at))
+                .unwrap_or(now),
+            completed_at: None,
+        });
+    }
+
+    let document = Document {
+        version: DOCUMENT_VERSION,
+        lists,
+    };
+    Ok((document, errors))
+}
+
+/// Export every item in the database as CSV
+pub async fn export(connection: &Connection) -> Result<String> {
+    let document = Document::export(connection)
+        .await
+        .context("csv::export: exporting document")?;
+    from_document(&document).context("csv::export: writing rows")
+}
+
+/// Import CSV into the database; see [`to_document`] for how rows match existing data.
+///
+/// Replacing deletes everything which isn't in the file, including whatever the rows which
+/// couldn't be read stood for, so it only goes ahead if every row could be read.
+pub async fn import(
+    connection: &Connection,
+    text: &str,
+    mapping: &ColumnMapping,
+    mode: ImportMode,
+) -> Result<CsvImport> {
+    let existing = Document::export(connection)
+        .await
+        .context("csv::import: exporting existing data")?;
+    let (document, errors) =
+        to_document(text, mapping, &existing).context("csv::import: reading rows")?;
+    if mode == ImportMode::Replace
+        && let Some(error) = errors.first()
+    {
+        bail!(
+            "csv::import: not replacing, since {} rows could not be read; the first was {error}",
+            errors.len()
+        );
+    }
+    let report = document
+        .import(connection, mode)
+        .await
+        .context("csv::import: importing document")?;
+    Ok(CsvImport { report, errors })
+}
@@ -0,0 +1,374 @@
+//! A database's contents in a form which doesn't depend on any one database.
+//!
+//! Rows are identified by their uuid, never by their local id, so a [`Document`] exported from one
+//! database can be imported into any other. Each interchange format converts to and from a
+//! document, and leaves matching it up with existing data to [`Document::import`].
+//!
+//! Lists appear in the order they were created, and items in the order of their position.
+
+use std::collections::HashSet;
+
+use anyhow::{Context as _, Result, ensure};
+use log::debug;
+use rusqlite::{Connection, OptionalExtension as _, ToSql, named_params};
+use time::UtcDateTime;
+use uuid::Uuid;
+
+use crate::{
+    Item, ItemId, TodoList, TodoListId,
+    crdt::{self, Hlc},
+};
+
+/// Version of the document format written by [`Document::export`]
+pub const DOCUMENT_VERSION: u32 = 1;
+
+/// Every list and item in a database
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Document {
+    /// Version of the document format
+    pub version: u32,
+    pub lists: Vec<ListDocument>,
+}
+
+/// A list and its items
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ListDocument {
+    pub uuid: Uuid,
+    pub title: String,
+    #[cfg_attr(feature = "serde", serde(with = "crate::model::rfc3339"))]
+    pub created_at: UtcDateTime,
+    pub items: Vec<ItemDocument>,
+}
+
+/// An item, which belongs to the list it appears in
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ItemDocument {
+    pub uuid: Uuid,
+    pub description: String,
+    pub is_completed: bool,
+    pub position: i64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::model::rfc3339"))]
+    pub created_at: UtcDateTime,
+    /// When a completed item was last marked completed.
+    ///
+    /// Ignored on import: the database dates completion by its own clock.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "crate::model::rfc3339::option"
+        )
+    )]
+    pub completed_at: Option<UtcDateTime>,
+}
+
+/// What to do with existing data when importing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImportMode {
+    /// Add lists and items which don't exist yet and update those which do, keeping everything else
+    #[default]
+    Merge,
+    /// Make the database contain exactly what the document contains, deleting everything else
+    Replace,
+}
+
+/// What happened when importing a document
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Number of lists and items which didn't exist yet
+    pub created: usize,
+    /// Number of existing lists and items which differed from the document
+    pub updated: usize,
+    /// Number of lists and items which were absent from the document, when replacing
+    pub deleted: usize,
+    /// Number of lists and items in the document which this database has deleted, and so left out
+    pub skipped: usize,
+}
+
+impl Document {
+    /// Gather every list and item in the database
+    pub async fn export(connection: &Connection) -> Result<Self> {
+        let mut lists = Vec::new();
+        for (id, _) in TodoList::list_all(connection)
+            .await
+            .context("Document::export: listing lists")?
+        {
+            let list = TodoList::load(connection, id)
+                .await
+                .context("Document::export: loading list")?;
+            let mut items = list.items().values().collect::<Vec<_>>();
+            items.sort_by_key(|item| (item.position(), item.id()));
+            let items = items
+                .into_iter()
+                .map(|item| {
+                    Ok(ItemDocument {
+                        uuid: item.uuid(),
+                        description: item.description().to_owned(),
+                        is_completed: item.is_completed(),
+                        position: item.position(),
+                        created_at: item.created_at(),
+                        completed_at: completed_at(connection, item)?,
+                    })
+                })
+                .collect::<Result<_>>()
+                .context("Document::export: dating completions")?;
+            lists.push(ListDocument {
+                uuid: list.uuid(),
+                title: list.title().to_owned(),
+                created_at: list.created_at(),
+                items,
+            });
+        }
+
+        debug!("lists" = lists.len(); "exported document");
+        Ok(Self {
+            version: DOCUMENT_VERSION,
+            lists,
+        })
+    }
+
+    /// Write this document's contents into the database.
+    ///
+    /// Lists and items are matched to existing rows by uuid. Moving an item to a different list in
+    /// the document moves it in the database too. Rows which this database has deleted stay
+    /// deleted: they are counted as skipped, along with the items of a deleted list, so importing
+    /// the same file again never brings them back.
+    ///
+    /// Everything happens in a single transaction; if anything fails, nothing changes.
+    pub async fn import(&self, connection: &Connection, mode: ImportMode) -> Result<ImportReport> {
+        ensure!(
+            (1..=DOCUMENT_VERSION).contains(&self.version),
+            "Document::import: unsupported document version {}; expected at most {DOCUMENT_VERSION}",
+            self.version
+        );
+
+        let tx = connection
+            .unchecked_transaction()
+            .context("Document::import: starting transaction")?;
+        let mut report = ImportReport::default();
+        let mut kept_lists = HashSet::new();
+        let mut kept_items = HashSet::new();
+
+        for list in &self.lists {
+            let Some(list_id) = import_list(&tx, list, &mut report)
+                .with_context(|| format!("Document::import: importing list {}", list.uuid))?
+            else {
+                report.skipped += 1 + list.items.len();
+                continue;
+            };
+            kept_lists.insert(list_id);
+            for item in &list.items {
+                match import_item(&tx, list_id, item, &mut report)
+                    .with_context(|| format!("Document::import: importing item {}", item.uuid))?
+                {
+                    Some(item_id) => {
+                        kept_items.insert(item_id);
+                    }
+                    None => report.skipped += 1,
+                }
+            }
+        }
+
+        if mode == ImportMode::Replace {
+            // items first, so that deleting a list only ever deletes an empty list
+            for item_id in all_ids(&tx, "todo_items")? {
+                if !kept_items.contains(&ItemId::from(item_id)) {
+                    Item::delete(&tx, item_id.into())
+                        .await
+                        .context("Document::import: deleting item")?;
+                    report.deleted += 1;
+                }
+            }
+            for list_id in all_ids(&tx, "todo_lists")? {
+                if !kept_lists.contains(&TodoListId::from(list_id)) {
+                    TodoList::delete(&tx, list_id.into())
+                        .await
+                        .context("Document::import: deleting list")?;
+                    report.deleted += 1;
+                }
+            }
+        }
+
+        tx.commit().context("Document::import: committing")?;
+        debug!(report:debug, mode:debug; "imported document");
+        Ok(report)
+    }
+}
+
+/// When an item was last marked completed, if it is completed.
+///
+/// That's the time of the last write to its completion register.
+fn completed_at(connection: &Connection, item: &Item) -> Result<Option<UtcDateTime>> {
+    if !item.is_completed() {
+        return Ok(None);
+    }
+    let clock = connection
+        .prepare_cached("SELECT is_completed_clock FROM todo_items WHERE id = ?")
+        .context("preparing clock lookup")?
+        .query_row([item.id()], |row| row.get::<_, Hlc>(0))
+        .context("looking up clock")?;
+    let completed_at =
+        UtcDateTime::from_unix_timestamp_nanos(i128::from(clock.millis()) * 1_000_000)
+            .context("converting clock to a timestamp")?;
+    Ok(Some(completed_at))
+}
+
+/// Insert or update a list, returning its local id, or `None` if this database has deleted it
+fn import_list(
+    connection: &Connection,
+    list: &ListDocument,
+    report: &mut ImportReport,
+) -> Result<Option<TodoListId>> {
+    let existing = connection
+        .prepare_cached("SELECT id FROM todo_lists WHERE uuid = ?")
+        .context("preparing list lookup")?
+        .query_row([list.uuid], |row| row.get::<_, u32>(0))
+        .optional()
+        .context("looking up list")?;
+
+    let clock = crdt::tick(connection).context("getting timestamp")?;
+    if let Some(id) = existing {
+        let affected_rows = connection
+            .prepare_cached(
+                "UPDATE todo_lists
+                SET title = :title, title_clock = :clock,
+                    updated_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+                WHERE id = :id AND title IS NOT :title",
+            )
+            .context("preparing list update")?
+            .execute(named_params! {
+                ":title": list.title,
+                ":clock": clock,
+                ":id": id,
+            })
+            .context("updating list")?;
+        report.updated += affected_rows;
+        return Ok(Some(id.into()));
+    }
+    if is_deleted(connection, list.uuid)? {
+        return Ok(None);
+    }
+
+    let id = connection
+        .prepare_cached(
+            "INSERT INTO todo_lists (uuid, title, created_at, title_clock, updated_at)
+            VALUES (
+                :uuid, :title, datetime(:created_at, 'unixepoch'), :clock,
+                strftime('%Y-%m-%d %H:%M:%f', 'now')
+            )
+            RETURNING id",
+        )
+        .context("preparing list insert")?
+        .query_row(
+            named_params! {
+                ":uuid": list.uuid,
+                ":title": list.title,
+                ":created_at": list.created_at.unix_timestamp(),
+                ":clock": clock,
+            },
+            |row| row.get::<_, u32>(0),
+        )
+        .context("inserting list")?;
+    report.created += 1;
+    Ok(Some(id.into()))
+}
+
+/// Insert or update an item, returning its local id, or `None` if this database has deleted it
+fn import_item(
+    connection: &Connection,
+    list_id: TodoListId,
+    item: &ItemDocument,
+    report: &mut ImportReport,
+) -> Result<Option<ItemId>> {
+    let existing = connection
+        .prepare_cached("SELECT id FROM todo_items WHERE uuid = ?")
+        .context("preparing item lookup")?
+        .query_row([item.uuid], |row| row.get::<_, u32>(0))
+        .optional()
+        .context("looking up item")?;
+
+    let clock = crdt::tick(connection).context("getting timestamp")?;
+    let mut params: Vec<(&str, &dyn ToSql)> = vec![
+        (":list_id", &list_id),
+        (":description", &item.description),
+        (":is_completed", &item.is_completed),
+        (":position", &item.position),
+        (":clock", &clock),
+    ];
+    if let Some(id) = &existing {
+        params.push((":id", id));
+        // only fields which actually change get a new timestamp
+        let affected_rows = connection
+            .prepare_cached(
+                "UPDATE todo_items
+                SET description_clock = CASE WHEN description IS :description
+                        THEN description_clock ELSE :clock END,
+                    is_completed_clock = CASE WHEN is_completed IS :is_completed
+                        THEN is_completed_clock ELSE :clock END,
+                    position_clock = CASE WHEN position IS :position
+                        THEN position_clock ELSE :clock END,
+                    list_id = :list_id, description = :description,
+                    is_completed = :is_completed, position = :position,
+                    updated_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+                WHERE id = :id AND (
+                    list_id IS NOT :list_id OR description IS NOT :description
+                    OR is_completed IS NOT :is_completed OR position IS NOT :position
+                )",
+            )
+            .context("preparing item update")?
+            .execute(&params[..])
+            .context("updating item")?;
+        report.updated += affected_rows;
+        return Ok(Some(ItemId::from(*id)));
+    }
+    if is_deleted(connection, item.uuid)? {
+        return Ok(None);
+    }
+
+    let created_at = item.created_at.unix_timestamp();
+    params.extend([
+        (":uuid", &item.uuid as &dyn ToSql),
+        (":created_at", &created_at),
+    ]);
+    let id = connection
+        .prepare_cached(
+            "INSERT INTO todo_items (
+                uuid, list_id, description, is_completed, position, created_at, updated_at,
+                description_clock, is_completed_clock, position_clock
+            )
+            VALUES (
+                :uuid, :list_id, :description, :is_completed, :position,
+                datetime(:created_at, 'unixepoch'), strftime('%Y-%m-%d %H:%M:%f', 'now'),
+                :clock, :clock, :clock
+            )
+            RETURNING id",
+        )
+        .context("preparing item insert")?
+        .query_row(&params[..], |row| row.get::<_, u32>(0))
+        .context("inserting item")?;
+    report.created += 1;
+    Ok(Some(id.into()))
+}
+
+/// Whether this database has deleted a row by that uuid
+fn is_deleted(connection: &Connection, uuid: Uuid) -> Result<bool> {
+    connection
+        .prepare_cached("SELECT EXISTS (SELECT 1 FROM tombstones WHERE uuid = ?)")
+        .context("preparing tombstone lookup")?
+        .query_row([uuid], |row| row.get::<_, bool>(0))
+        .context("looking up tombstone")
+}
+
+fn all_ids(connection: &Connection, table: &str) -> Result<Vec<u32>> {
+    connection
+        .prepare(&format!("SELECT id FROM {table}"))
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .with_context(|| format!("all_ids: listing {table}"))
+}
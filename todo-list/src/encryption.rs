@@ -0,0 +1,659 @@
+//! Encryption of database files at rest.
+//!
+//! Which library does the encrypting depends on how SQLite was built: natively, rusqlite bundles
+//! SQLCipher, and in wasm, SQLite3 Multiple Ciphers (sqlite3mc) does the job. The two don't share a
+//! file format, but both are keyed with a passphrase or a raw [`Key`] through the same pragmas, and
+//! this module papers over where they differ, so every front-end gets the same behaviour from the
+//! same calls.
+//!
+//! sqlite3mc can also choose between several ciphers through a [`CipherConfig`]; SQLCipher only has
+//! its own.
+//!
+//! Encryption is invisible once a connection is unlocked: everything else in this crate works the
+//! same either way.
+
+use std::{
+    fmt,
+    io::Read as _,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use log::warn;
+use rusqlite::{Connection, ErrorCode, OptionalExtension as _};
+
+/// The first 16 bytes of every unencrypted database file
+pub const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// What a database is encrypted with
+#[derive(Clone, PartialEq, Eq)]
+pub enum Key {
+    /// A passphrase, from which the cipher derives the actual key
+    Passphrase(String),
+    /// A 256-bit key, used as it is.
+    ///
+    /// It is handed over in SQLCipher's raw key syntax, which sqlite3mc's SQLCipher cipher
+    /// understands as well.
+    Raw([u8; 32]),
+}
+
+impl Key {
+    /// A raw key written as 64 hex digits
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let hex = hex.trim();
+        ensure!(
+            hex.len() == 64 && hex.is_ascii(),
+            "Key::from_hex: a raw key is 64 hex digits"
+        );
+        let mut key = [0; 32];
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).expect("ascii is utf-8");
+            *byte = u8::from_str_radix(digits, 16)
+                .map_err(|_| anyhow!("Key::from_hex: {digits:?} is not a hex byte"))?;
+        }
+        Ok(Self::Raw(key))
+    }
+
+    /// Read a raw key from a key file, which holds either the 32 bytes of the key or 64 hex digits
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = std::fs::read(path).context("Key::from_file: reading key file")?;
+        if let Ok(key) = <[u8; 32]>::try_from(contents.as_slice()) {
+            return Ok(Self::Raw(key));
+        }
+        let hex = std::str::from_utf8(&contents).map_err(|_| {
+            anyhow!("Key::from_file: key file holds neither 32 bytes nor hex digits")
+        })?;
+        Self::from_hex(hex).context("Key::from_file")
+    }
+
+    /// The key as `PRAGMA key` takes it
+    fn keyspec(&self) -> String {
+        match self {
+            Self::Passphrase(passphrase) => passphrase.clone(),
+            Self::Raw(key) => {
+                let hex = key
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<String>();
+                format!("x'{hex}'")
+            }
+        }
+    }
+}
+
+impl From<&str> for Key {
+    fn from(passphrase: &str) -> Self {
+        Self::Passphrase(passphrase.to_owned())
+    }
+}
+
+/// Keys never show up in logs
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passphrase(_) => f.write_str("Passphrase(..)"),
+            Self::Raw(_) => f.write_str("Raw(..)"),
+        }
+    }
+}
+
+/// How hard a passphrase is to guess
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, derive_more::Display)]
+pub enum Strength {
+    /// Guessable in a practical amount of time; not worth encrypting with
+    #[display("weak")]
+    Weak,
+    /// Out of reach of casual attacks, but not of determined ones
+    #[display("fair")]
+    Fair,
+    #[display("strong")]
+    Strong,
+}
+
+impl Strength {
+    /// Estimate how hard a passphrase is to guess, from its length and the kinds of characters in
+    /// it.
+    ///
+    /// This is a rough guess, not a guarantee: it takes a quote or a keyboard walk for as many
+    /// random characters. It does catch short passphrases, small alphabets, and runs of one
+    /// character.
+    pub fn of(passphrase: &str) -> Self {
+        let bits = entropy_bits(passphrase);
+        if bits < 50.0 {
+            Self::Weak
+        } else if bits < 80.0 {
+            Self::Fair
+        } else {
+            Self::Strong
+        }
+    }
+}
+
+/// Bits of entropy in a passphrase, were each character picked at random from the kinds of
+/// characters it uses
+fn entropy_bits(passphrase: &str) -> f64 {
+    let has = |kind: fn(&char) -> bool| passphrase.chars().any(|c| kind(&c));
+    let alphabet = [
+        (has(char::is_ascii_lowercase), 26),
+        (has(char::is_ascii_uppercase), 26),
+        (has(char::is_ascii_digit), 10),
+        (has(|c| c.is_ascii_punctuation() || *c == ' '), 33),
+        (has(|c| !c.is_ascii()), 100),
+    ]
+    .into_iter()
+    .filter_map(|(used, size)| used.then_some(size))
+    .sum::<u32>();
+    // repeating the character before adds nothing
+    let mut previous = None;
+    let length = passphrase
+        .chars()
+        .filter(|&c| previous.replace(c) != Some(c))
+        .count();
+    length as f64 * f64::from(alphabet.max(1)).log2()
+}
+
+/// The key doesn't decrypt the database.
+///
+/// SQLite can't tell a wrong key apart from a file which isn't a database at all, so this stands
+/// for both.
+#[derive(Debug, derive_more::Display)]
+#[display("wrong passphrase, or not a database")]
+pub struct WrongPassphrase;
+
+impl std::error::Error for WrongPassphrase {}
+
+/// The encryption library SQLite was built with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Library {
+    SqlCipher,
+    MultipleCiphers,
+}
+
+impl Library {
+    /// Find out which encryption library the connection's SQLite was built with, if any
+    fn of(connection: &Connection) -> Result<Option<Self>> {
+        // each library answers only to its own pragma; plain SQLite ignores both
+        let answers = |pragma: &str| {
+            connection
+                .query_row(pragma, (), |_| Ok(()))
+                .optional()
+                .map(|row| row.is_some())
+        };
+        if answers("PRAGMA cipher_version").context("Library::of: querying SQLCipher version")? {
+            Ok(Some(Self::SqlCipher))
+        } else if answers("PRAGMA cipher").context("Library::of: querying sqlite3mc cipher")? {
+            Ok(Some(Self::MultipleCiphers))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Like [`Self::of`], but an error if SQLite can't encrypt at all
+    fn require(connection: &Connection) -> Result<Self> {
+        Self::of(connection)?
+            .ok_or_else(|| anyhow!("this build of SQLite does not support encryption"))
+    }
+}
+
+/// A cipher scheme sqlite3mc can encrypt a database with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Cipher {
+    /// AES with a 128 bit key in CBC mode, without authentication
+    Aes128Cbc,
+    /// AES with a 256 bit key in CBC mode, without authentication
+    Aes256Cbc,
+    /// ChaCha20-Poly1305, sqlite3mc's default
+    #[default]
+    ChaCha20,
+    /// AES with a 256 bit key in CBC mode, authenticated with an HMAC, as SQLCipher does it
+    SqlCipher,
+    /// RC4, which is insecure and only there to read old System.Data.SQLite databases
+    Rc4,
+    /// Ascon-128
+    Ascon128,
+    /// AEGIS, keyed through Argon2
+    Aegis,
+}
+
+impl Cipher {
+    /// Every cipher
+    pub const ALL: [Self; 7] = [
+        Self::Aes128Cbc,
+        Self::Aes256Cbc,
+        Self::ChaCha20,
+        Self::SqlCipher,
+        Self::Rc4,
+        Self::Ascon128,
+        Self::Aegis,
+    ];
+
+    /// The cipher's name, as sqlite3mc's `cipher` pragma knows it
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Aes128Cbc => "aes128cbc",
+            Self::Aes256Cbc => "aes256cbc",
+            Self::ChaCha20 => "chacha20",
+            Self::SqlCipher => "sqlcipher",
+            Self::Rc4 => "rc4",
+            Self::Ascon128 => "ascon128",
+            Self::Aegis => "aegis",
+        }
+    }
+
+    /// Whether the cipher derives its key with an iterated KDF whose iterations can be set
+    pub fn has_kdf_iter(self) -> bool {
+        matches!(
+            self,
+            Self::Aes256Cbc | Self::ChaCha20 | Self::SqlCipher | Self::Ascon128
+        )
+    }
+
+    /// Whether the cipher has a legacy mode, to read databases written by older versions of its
+    /// library
+    pub fn has_legacy(self) -> bool {
+        matches!(
+            self,
+            Self::Aes128Cbc | Self::Aes256Cbc | Self::ChaCha20 | Self::SqlCipher | Self::Rc4
+        )
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|cipher| cipher.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| anyhow!("Cipher::from_str: unknown cipher {name:?}"))
+    }
+}
+
+/// How sqlite3mc encrypts a database.
+///
+/// A database can only be unlocked with the configuration it was encrypted with, so whoever
+/// encrypts it with anything but the default has to remember the configuration somewhere outside
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CipherConfig {
+    pub cipher: Cipher,
+    /// Iterations of the cipher's key derivation function, if not its default
+    pub kdf_iter: Option<u32>,
+    /// Which older version of the cipher's library to be compatible with, if any; `0` means the
+    /// current one.
+    ///
+    /// For [`Cipher::SqlCipher`], this is SQLCipher's major version, and sets all its other
+    /// parameters to match.
+    pub legacy: Option<u32>,
+}
+
+impl CipherConfig {
+    /// Configure the connection to encrypt with this configuration.
+    ///
+    /// This must come before [`unlock`] or [`rekey`]; rekeying after configuring a different cipher
+    /// re-encrypts the database with it.
+    ///
+    /// Only sqlite3mc can be configured; SQLCipher has only the one cipher, so this is an error
+    /// there.
+    pub fn apply(&self, connection: &Connection) -> Result<()> {
+        if Library::require(connection).context("CipherConfig::apply")? != Library::MultipleCiphers
+        {
+            bail!("CipherConfig::apply: only sqlite3mc can choose a cipher");
+        }
+        ensure!(
+            self.kdf_iter.is_none() || self.cipher.has_kdf_iter(),
+            "{} has no KDF iterations to set",
+            self.cipher
+        );
+        ensure!(
+            self.legacy.is_none() || self.cipher.has_legacy(),
+            "{} has no legacy mode",
+            self.cipher
+        );
+
+        connection
+            .pragma_update(None, "cipher", self.cipher.name())
+            .context("CipherConfig::apply: setting cipher")?;
+        // legacy modes set the other parameters, so explicit ones come after
+        if let Some(legacy) = self.legacy {
+            connection
+                .pragma_update(None, "legacy", legacy)
+                .context("CipherConfig::apply: setting legacy mode")?;
+        }
+        if let Some(kdf_iter) = self.kdf_iter {
+            connection
+                .pragma_update(None, "kdf_iter", kdf_iter)
+                .context("CipherConfig::apply: setting KDF iterations")?;
+        }
+        Ok(())
+    }
+}
+
+/// Check whether a database is encrypted, given the start of its file.
+///
+/// At least the first 16 bytes are needed. An empty file is a new database, which isn't encrypted
+/// yet.
+pub fn is_encrypted(bytes: &[u8]) -> Result<bool> {
+    if bytes.is_empty() {
+        return Ok(false);
+    }
+    if bytes.len() < SQLITE_MAGIC.len() {
+        bail!(
+            "database file is {} bytes long, shorter than its header",
+            bytes.len()
+        );
+    }
+    Ok(!bytes.starts_with(SQLITE_MAGIC))
+}
+
+/// Check whether the database file at `path` is encrypted, without opening a connection to it.
+///
+/// A file which doesn't exist yet isn't encrypted.
+pub fn file_is_encrypted(path: impl AsRef<Path>) -> Result<bool> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err).context("file_is_encrypted: opening database file"),
+    };
+    let mut header = Vec::with_capacity(SQLITE_MAGIC.len());
+    file.take(SQLITE_MAGIC.len() as u64)
+        .read_to_end(&mut header)
+        .context("file_is_encrypted: reading database header")?;
+    is_encrypted(&header)
+}
+
+/// Unlock an encrypted database with its key.
+///
+/// **IMPORTANT** This must be the first operation performed on a newly opened connection. Unlocking
+/// a database which doesn't exist yet means it is created encrypted.
+///
+/// This doesn't change the stored data; it just lets SQLite encrypt and decrypt pages transparently
+/// on their way through this connection. A passphrase is not the actual encryption key, which the
+/// cipher derives from it.
+///
+/// Returns [`WrongPassphrase`] if the key doesn't decrypt the database.
+pub fn unlock(connection: &Connection, key: &Key) -> Result<()> {
+    if Library::require(connection).context("unlock")? == Library::SqlCipher {
+        // SQLCipher also logs wrong keys to stderr, which garbles terminals; the error says enough
+        connection
+            .pragma_update(None, "cipher_log_level", "NONE")
+            .context("unlock: silencing SQLCipher logs")?;
+    }
+    connection
+        .pragma_update(None, "key", key.keyspec())
+        .context("unlock: setting key")?;
+    // keying never fails by itself; the first read of the file shows whether the key fits
+    match connection.query_row("SELECT count(*) FROM sqlite_master", (), |_| Ok(())) {
+        Ok(()) => Ok(()),
+        Err(rusqlite::Error::SqliteFailure(err, _)) if err.code == ErrorCode::NotADatabase => {
+            Err(WrongPassphrase.into())
+        }
+        Err(err) => Err(err).context("unlock: reading database"),
+    }
+}
+
+/// Which side of a key change a connection is opened for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// The database as it was, encrypted with the current key if any
+    Before,
+    /// The database as it is meant to be, encrypted with the new key if any
+    After,
+}
+
+/// Where a database's file is kept.
+///
+/// Changing a database's key rewrites the whole file, so [`rekey`] backs it up first, and checks
+/// that the new key opens it afterwards. That takes a few things only whoever stores the file can
+/// do.
+pub trait DatabaseFile {
+    /// Open a new connection to the database, configured for the cipher it is encrypted with at
+    /// that stage, if that isn't the default, but not unlocked yet
+    fn open(&self, stage: Stage) -> Result<Connection>;
+
+    /// Copy the file aside, replacing any earlier backup
+    fn back_up(&self) -> Result<()>;
+
+    /// Replace the file with its backup, which is used up by this. Nothing has the file open.
+    fn restore(&self) -> Result<()>;
+
+    /// Delete the backup
+    fn remove_backup(&self) -> Result<()>;
+}
+
+/// A database file on disk, backed up beside itself with a `.pre-rekey` suffix
+impl DatabaseFile for Path {
+    fn open(&self, _stage: Stage) -> Result<Connection> {
+        Connection::open(self).context("Path::open: opening database")
+    }
+
+    fn back_up(&self) -> Result<()> {
+        std::fs::copy(self, backup_path(self)).context("Path::back_up: copying database")?;
+        Ok(())
+    }
+
+    fn restore(&self) -> Result<()> {
+        std::fs::rename(backup_path(self), self).context("Path::restore: replacing database")
+    }
+
+    fn remove_backup(&self) -> Result<()> {
+        std::fs::remove_file(backup_path(self)).context("Path::remove_backup: deleting backup")
+    }
+}
+
+fn backup_path(db_path: &Path) -> PathBuf {
+    let mut backup_path = db_path.to_owned().into_os_string();
+    backup_path.push(".pre-rekey");
+    backup_path.into()
+}
+
+/// Encrypt the database behind `connection` with `new`, or re-encrypt it if it is encrypted
+/// already.
+///
+/// `current` is the key the database is unlocked with now, if any, and `file` is where it is kept.
+///
+/// The database is backed up before it changes, and only counts as re-encrypted once a new
+/// connection opens it with the new key. If anything goes wrong on the way, the backup is put back
+/// and `connection` reopened with `current`, so the database stays as it was. The backup is deleted
+/// once the new key works: it would give away what encrypting the database was meant to hide.
+///
+/// sqlite3mc changes the key in place, encrypting with whichever [`CipherConfig`] was applied last.
+/// SQLCipher can only change the key of a database which is already encrypted; to encrypt or
+/// decrypt one, it copies the database into a new file, which then replaces the original, and
+/// `connection` is replaced by one to the copy.
+///
+/// Empty and [`Strength::Weak`] passphrases are refused before anything changes; to decrypt the
+/// database, use [`remove_encryption`].
+pub fn rekey(
+    connection: &mut Connection,
+    file: &(impl DatabaseFile + ?Sized),
+    current: Option<&Key>,
+    new: &Key,
+) -> Result<()> {
+    if let Key::Passphrase(passphrase) = new {
+        ensure!(
+            !passphrase.is_empty(),
+            "rekey: empty passphrase; use `remove_encryption` to decrypt"
+        );
+        ensure!(
+            Strength::of(passphrase) != Strength::Weak,
+            "rekey: passphrase is weak; try a longer one, or a few unrelated words"
+        );
+    }
+    change_key(connection, file, current, Some(new))
+}
+
+/// Decrypt the database behind `connection`, leaving it readable by anyone who gets hold of its
+/// file.
+///
+/// As that can't be undone for copies made in the meantime, it only happens if `confirmed`.
+/// Otherwise this works like [`rekey`].
+pub fn remove_encryption(
+    connection: &mut Connection,
+    file: &(impl DatabaseFile + ?Sized),
+    current: &Key,
+    confirmed: bool,
+) -> Result<()> {
+    ensure!(
+        confirmed,
+        "remove_encryption: decrypting leaves the database readable by anyone with its file, so it must be confirmed"
+    );
+    change_key(connection, file, Some(current), None)
+}
+
+fn change_key(
+    connection: &mut Connection,
+    file: &(impl DatabaseFile + ?Sized),
+    current: Option<&Key>,
+    new: Option<&Key>,
+) -> Result<()> {
+    if current.is_none() && new.is_none() {
+        return Ok(());
+    }
+    file.back_up().context("rekey: backing up database")?;
+
+    let changed = swap_key(connection, file, current, new).and_then(|()| {
+        // a connection of our own proves little; the next one to open the file has to get in with
+        // the new key
+        let check = file
+            .open(Stage::After)
+            .context("rekey: reopening database")?;
+        match new {
+            Some(new) => unlock(&check, new),
+            None => check
+                .query_row("SELECT count(*) FROM sqlite_master", (), |_| Ok(()))
+                .map_err(Into::into),
+        }
+        .context("rekey: checking the new key")
+    });
+
+    match changed {
+        Ok(()) => {
+            if let Err(err) = file.remove_backup() {
+                warn!(err:% = format!("{err:#}"); "failed to delete the backup made before changing the key");
+            }
+            Ok(())
+        }
+        Err(err) => match restore(connection, file, current) {
+            Ok(()) => Err(err.context("rekey: changing key failed, so the backup was restored")),
+            Err(restore_err) => Err(restore_err.context(format!(
+                "rekey: changing key failed ({err:#}), and so did restoring the backup, which is kept"
+            ))),
+        },
+    }
+}
+
+/// Put the backup back, and reopen `connection` to it
+fn restore(
+    connection: &mut Connection,
+    file: &(impl DatabaseFile + ?Sized),
+    current: Option<&Key>,
+) -> Result<()> {
+    close(connection)?;
+    file.restore().context("rekey: restoring backup")?;
+    *connection = file
+        .open(Stage::Before)
+        .context("rekey: reopening restored database")?;
+    if let Some(current) = current {
+        unlock(connection, current).context("rekey: unlocking restored database")?;
+    }
+    Ok(())
+}
+
+/// Close `connection`, leaving an in-memory placeholder in its place until it is reopened.
+///
+/// If it can't be closed, it is left as it was.
+fn close(connection: &mut Connection) -> Result<()> {
+    let original = std::mem::replace(
+        connection,
+        Connection::open_in_memory().context("rekey: opening placeholder connection")?,
+    );
+    if let Err((original, err)) = original.close() {
+        *connection = original;
+        return Err(err).context("rekey: closing database");
+    }
+    Ok(())
+}
+
+/// Change the key of the database behind `connection`, without any safeguards
+fn swap_key(
+    connection: &mut Connection,
+    file: &(impl DatabaseFile + ?Sized),
+    current: Option<&Key>,
+    new: Option<&Key>,
+) -> Result<()> {
+    match Library::require(connection).context("rekey")? {
+        Library::MultipleCiphers => connection
+            // an empty key removes encryption
+            .pragma_update(None, "rekey", new.map(Key::keyspec).unwrap_or_default())
+            .context("rekey: changing key"),
+        Library::SqlCipher if current.is_some() && new.is_some() => connection
+            .pragma_update(None, "rekey", new.map(Key::keyspec))
+            .context("rekey: changing key"),
+        Library::SqlCipher => export(connection, file, current, new),
+    }
+}
+
+/// Re-encrypt a database by copying it into a new file which replaces the original, for SQLCipher
+fn export(
+    connection: &mut Connection,
+    file: &(impl DatabaseFile + ?Sized),
+    current: Option<&Key>,
+    new: Option<&Key>,
+) -> Result<()> {
+    let db_path = connection
+        .path()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("only databases in files can be encrypted or decrypted"))?;
+    let mut copy_path = db_path.clone().into_os_string();
+    copy_path.push(".rekey");
+    let copy_path = PathBuf::from(copy_path);
+    let _ = std::fs::remove_file(&copy_path);
+
+    // an empty key attaches a plaintext database
+    connection
+        .execute(
+            "ATTACH DATABASE ?1 AS rekeyed KEY ?2",
+            (
+                copy_path.to_string_lossy(),
+                new.map(Key::keyspec).unwrap_or_default(),
+            ),
+        )
+        .context("rekey: attaching copy")?;
+    let exported = connection
+        .query_row("SELECT sqlcipher_export('rekeyed')", (), |_| Ok(()))
+        .context("rekey: copying database");
+    connection
+        .execute("DETACH DATABASE rekeyed", ())
+        .context("rekey: detaching copy")?;
+    exported?;
+    // sqlcipher_export copies the schema and data, but not the header
+    let version = crate::schema_version(connection).context("rekey: reading schema version")?;
+
+    // the copy can only replace the original once nothing has the original open
+    close(connection)?;
+    let replaced =
+        std::fs::rename(&copy_path, &db_path).context("rekey: replacing database with copy");
+    let (stage, key) = if replaced.is_ok() {
+        (Stage::After, new)
+    } else {
+        (Stage::Before, current)
+    };
+    *connection = file.open(stage).context("rekey: reopening database")?;
+    if let Some(key) = key {
+        unlock(connection, key).context("rekey: unlocking reopened database")?;
+    }
+    replaced?;
+
+    connection
+        .pragma_update(None, "user_version", version)
+        .context("rekey: restoring schema version")
+}
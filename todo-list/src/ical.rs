@@ -0,0 +1,417 @@
+//! iCalendar ([RFC 5545](https://www.rfc-editor.org/rfc/rfc5545)) to-dos.
+//!
+//! Each list is a `VCALENDAR`, and each of its items a `VTODO` within it:
+//!
+//! ```text
+//! BEGIN:VCALENDAR
+//! VERSION:2.0
+//! PRODID:-//todo-list//todo-list//EN
+//! X-WR-CALNAME:Groceries
+//! X-WR-RELCALID:0192f5c4-9b0e-7c3a-8d1e-5a4b3c2d1e0f
+//! BEGIN:VTODO
+//! UID:0192f5c4-a1b2-7c3d-8e4f-5a6b7c8d9e0f
+//! DTSTAMP:20250103T120000Z
+//! SUMMARY:Milk
+//! STATUS:COMPLETED
+//! CREATED:20250101T120100Z
+//! COMPLETED:20250102T093000Z
+//! X-APPLE-SORT-ORDER:1
+//! END:VTODO
+//! END:VCALENDAR
+//! ```
+//!
+//! Properties map onto lists and items like so:
+//!
+//! - `X-WR-CALNAME` is the list's title, and `X-WR-RELCALID` its uuid. A calendar without a uuid
+//!   belongs to the existing list with its name, if any.
+//! - `UID` is the item's uuid. Other clients' `UID`s needn't be uuids; those are hashed into one,
+//!   so that re-importing the same to-do updates the same item.
+//! - `SUMMARY` is the description.
+//! - `STATUS:COMPLETED`, or a `COMPLETED` time, marks a completed item.
+//! - `CREATED` is the item's creation time. `COMPLETED` is when the item was last marked completed;
+//!   the database dates that by its own clock, so it is only ever exported.
+//! - `X-APPLE-SORT-ORDER` is the item's position. To-dos without one follow the one before.
+//!
+//! Everything else, including other components such as `VEVENT`s, is skipped when reading.
+
+use std::collections::HashMap;
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use rusqlite::Connection;
+use time::{
+    PrimitiveDateTime, Time, UtcDateTime, format_description::StaticFormatDescription,
+    macros::format_description,
+};
+use uuid::Uuid;
+
+use crate::{DOCUMENT_VERSION, Document, ImportMode, ImportReport, ItemDocument, ListDocument};
+
+const PRODUCT_ID: &str = "-//todo-list//todo-list//EN";
+
+/// Namespace for the uuids of to-dos whose `UID` is not a uuid
+const UID_NAMESPACE: Uuid = Uuid::from_u128(0x0199_f8a2_6c1d_7e40_9b3a_5d2e_8f41_c7a6);
+
+/// Longest a content line may be, in octets, excluding its line break
+const MAX_LINE_LENGTH: usize = 75;
+
+static DATE_TIME_FORMAT: StaticFormatDescription =
+    format_description!("[year][month][day]T[hour][minute][second]");
+static DATE_FORMAT: StaticFormatDescription = format_description!("[year][month][day]");
+
+/// Write every list of a document as a calendar of to-dos, one calendar after the other
+pub fn from_document(document: &Document) -> String {
+    let stamp = format_date_time(UtcDateTime::now());
+    let mut out = String::new();
+    for list in &document.lists {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_owned(),
+            "VERSION:2.0".to_owned(),
+            format!("PRODID:{PRODUCT_ID}"),
+            format!("X-WR-CALNAME:{}", escape(&list.title)),
+            format!("X-WR-RELCALID:{}", list.uuid),
+        ];
+        for item in &list.items {
+            lines.extend([
+                "BEGIN:VTODO".to_owned(),
+                format!("UID:{}", item.uuid),
+                format!("DTSTAMP:{stamp}"),
+                format!("SUMMARY:{}", escape(&item.description)),
+            ]);
+            if item.is_completed {
+                lines.push("STATUS:COMPLETED".to_owned());
+            } else {
+                lines.push("STATUS:NEEDS-ACTION".to_owned());
+            }
+            lines.push(format!("CREATED:{}", format_date_time(item.created_at)));
+            if let Some(completed_at) = item.completed_at {
+                lines.push(format!("COMPLETED:{}", format_date_time(completed_at)));
+            }
+            lines.extend([
+                format!("X-APPLE-SORT-ORDER:{}", item.position),
+                "END:VTODO".to_owned(),
+            ]);
+        }
+        lines.push("END:VCALENDAR".to_owned());
+
+        for line in lines {
+            fold(&line, &mut out);
+        }
+    }
+    out
+}
+
+fn format_date_time(date_time: UtcDateTime) -> String {
+    let formatted = date_time
+        .format(DATE_TIME_FORMAT)
+        .expect("every date time can be formatted as a basic ISO 8601 date time");
+    format!("{formatted}Z")
+}
+
+/// Escape a `TEXT` value
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Write a content line, folding it onto continuation lines as needed
+fn fold(line: &str, out: &mut String) {
+    let mut rest = line;
+    let mut limit = MAX_LINE_LENGTH;
+    loop {
+        let mut end = rest.len().min(limit);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        out.push_str(&rest[..end]);
+        out.push_str("\r\n");
+        rest = &rest[end..];
+        if rest.is_empty() {
+            break;
+        }
+        // continuation lines start with a space, which counts towards their length
+        out.push(' ');
+        limit = MAX_LINE_LENGTH - 1;
+    }
+}
+
+/// A content line: `NAME;PARAM=value:value`
+#[derive(Debug)]
+struct Property<'a> {
+    name: String,
+    /// Parameter names, uppercased, and their values
+    params: Vec<(String, &'a str)>,
+    value: &'a str,
+}
+
+impl<'a> Property<'a> {
+    fn parse(line: &'a str) -> Result<Self> {
+        let name_end = line
+            .find([';', ':'])
+            .ok_or_else(|| anyhow!("no value in {line:?}"))?;
+        let name = line[..name_end].to_ascii_uppercase();
+        let mut rest = &line[name_end..];
+        let mut params = Vec::new();
+        while let Some(param) = rest.strip_prefix(';') {
+            let (param_name, mut values) = param
+                .split_once('=')
+                .ok_or_else(|| anyhow!("parameter without a value in {line:?}"))?;
+            // values may be quoted, in which case they can contain `;` and `:`
+            let mut end = 0;
+            let mut quoted = false;
+            for (index, c) in values.char_indices() {
+                match c {
+                    '"' => quoted = !quoted,
+                    ';' | ':' if !quoted => break,
+                    _ => {}
+                }
+                end = index + c.len_utf8();
+            }
+            (values, rest) = values.split_at(end);
+            params.push((param_name.to_ascii_uppercase(), values.trim_matches('"')));
+        }
+        let value = rest
+            .strip_prefix(':')
+            .ok_or_else(|| anyhow!("no value in {line:?}"))?;
+        Ok(Self {
+            name,
+            params,
+            value,
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find_map(|(param, value)| (param == name).then_some(*value))
+    }
+
+    /// Read a `TEXT` value
+    fn text(&self) -> String {
+        let mut out = String::with_capacity(self.value.len());
+        let mut chars = self.value.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n' | 'N') => out.push('\n'),
+                Some(escaped) => out.push(escaped),
+                None => out.push('\\'),
+            }
+        }
+        out
+    }
+
+    /// Read a `DATE-TIME` or `DATE` value.
+    ///
+    /// Times in a named time zone are read as UTC, since resolving the zone would need its
+    /// `VTIMEZONE`; being off by some hours doesn't matter for the dates of to-dos.
+    fn date_time(&self) -> Result<UtcDateTime> {
+        let value = self.value.strip_suffix(['Z', 'z']).unwrap_or(self.value);
+        let date_time = if self
+            .param("VALUE")
+            .is_some_and(|kind| kind.eq_ignore_ascii_case("DATE"))
+            || !value.contains(['T', 't'])
+        {
+            time::Date::parse(value, DATE_FORMAT).map(|date| date.with_time(Time::MIDNIGHT))
+        } else {
+            PrimitiveDateTime::parse(&value.to_ascii_uppercase(), DATE_TIME_FORMAT)
+        }
+        .with_context(|| format!("parsing {} {:?}", self.name, self.value))?;
+        Ok(date_time.as_utc())
+    }
+}
+
+/// Undo line folding, yielding each content line and the number of the line it starts on
+fn unfold(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some((_, last))) => last.push_str(continuation),
+            _ if line.is_empty() => {}
+            _ => lines.push((index + 1, line.to_owned())),
+        }
+    }
+    lines
+}
+
+/// The parts of a calendar which matter to a list
+#[derive(Debug, Default)]
+struct Calendar {
+    name: Option<String>,
+    uuid: Option<Uuid>,
+    todos: Vec<Todo>,
+}
+
+/// The parts of a `VTODO` which matter to an item
+#[derive(Debug, Default)]
+struct Todo {
+    uid: Option<String>,
+    summary: Option<String>,
+    status: Option<String>,
+    created: Option<UtcDateTime>,
+    completed: Option<UtcDateTime>,
+    sort_order: Option<i64>,
+}
+
+/// Read the calendars in a file, keeping only what lists and items need
+fn parse(text: &str) -> Result<Vec<Calendar>> {
+    let mut calendars = Vec::new();
+    // names of the components which are open, outermost first
+    let mut components: Vec<String> = Vec::new();
+    let mut calendar = Calendar::default();
+    let mut todo = Todo::default();
+
+    for (line_number, line) in unfold(text) {
+        let property = Property::parse(&line)
+            .with_context(|| format!("ical::parse: reading line {line_number}"))?;
+        let in_line = |err: anyhow::Error| err.context(format!("ical::parse: line {line_number}"));
+        match property.name.as_str() {
+            "BEGIN" => {
+                let component = property.value.to_ascii_uppercase();
+                if components.is_empty() {
+                    ensure!(
+                        component == "VCALENDAR",
+                        "ical::parse: line {line_number}: expected a VCALENDAR, found {component}"
+                    );
+                    calendar = Calendar::default();
+                } else if component == "VTODO" && components.len() == 1 {
+                    todo = Todo::default();
+                }
+                components.push(component);
+                continue;
+            }
+            "END" => {
+                let component = property.value.to_ascii_uppercase();
+                let Some(open) = components.pop() else {
+                    bail!("ical::parse: line {line_number}: END:{component} without a BEGIN");
+                };
+                ensure!(
+                    open == component,
+                    "ical::parse: line {line_number}: END:{component} inside {open}"
+                );
+                match components.len() {
+                    0 => calendars.push(std::mem::take(&mut calendar)),
+                    1 if component == "VTODO" => calendar.todos.push(std::mem::take(&mut todo)),
+                    _ => {}
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        match components.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["VCALENDAR"] => match property.name.as_str() {
+                "X-WR-CALNAME" => calendar.name = Some(property.text()),
+                "X-WR-RELCALID" => calendar.uuid = Uuid::parse_str(property.value.trim()).ok(),
+                _ => {}
+            },
+            ["VCALENDAR", "VTODO"] => match property.name.as_str() {
+                "UID" => todo.uid = Some(property.text()),
+                "SUMMARY" => todo.summary = Some(property.text()),
+                "STATUS" => todo.status = Some(property.value.trim().to_ascii_uppercase()),
+                "CREATED" => todo.created = Some(property.date_time().map_err(in_line)?),
+                "COMPLETED" => todo.completed = Some(property.date_time().map_err(in_line)?),
+                "X-APPLE-SORT-ORDER" => todo.sort_order = property.value.trim().parse().ok(),
+                _ => {}
+            },
+            // anything outside a calendar, or within other components
+            _ => {}
+        }
+    }
+
+    if let Some(open) = components.last() {
+        bail!("ical::parse: {open} is never ended");
+    }
+    Ok(calendars)
+}
+
+/// Read calendars into a document.
+///
+/// `existing` is the current contents of the database the document is destined for. A calendar
+/// without a uuid belongs to the existing list of the same name, if any. Everything else is new.
+pub fn to_document(text: &str, existing: &Document) -> Result<Document> {
+    let existing_lists = existing
+        .lists
+        .iter()
+        .map(|list| (list.title.as_str(), list))
+        .collect::<HashMap<_, _>>();
+    let now = UtcDateTime::now();
+
+    let lists = parse(text)
+        .context("ical::to_document: reading calendars")?
+        .into_iter()
+        .map(|calendar| {
+            let title = calendar.name.unwrap_or_default();
+            let existing_list = existing_lists.get(title.as_str());
+            let mut position = 0;
+            let items = calendar
+                .todos
+                .into_iter()
+                .map(|todo| {
+                    position = todo.sort_order.unwrap_or(position + 1);
+                    let uuid = match todo.uid {
+                        Some(uid) => Uuid::parse_str(&uid)
+                            .unwrap_or_else(|_| Uuid::new_v5(&UID_NAMESPACE, uid.as_bytes())),
+                        None => Uuid::now_v7(),
+                    };
+                    ItemDocument {
+                        uuid,
+                        description: todo.summary.unwrap_or_default(),
+                        is_completed: todo.status.as_deref() == Some("COMPLETED")
+                            || todo.completed.is_some(),
+                        position,
+                        created_at: todo.created.unwrap_or(now),
+                        completed_at: todo.completed,
+                    }
+                })
+                .collect();
+            ListDocument {
+                uuid: calendar
+                    .uuid
+                    .or(existing_list.map(|list| list.uuid))
+                    .unwrap_or_else(Uuid::now_v7),
+                created_at: existing_list.map_or(now, |list| list.created_at),
+                title,
+                items,
+            }
+        })
+        .collect();
+
+    Ok(Document {
+        version: DOCUMENT_VERSION,
+        lists,
+    })
+}
+
+/// Export the whole database as iCalendar
+pub async fn export(connection: &Connection) -> Result<String> {
+    let document = Document::export(connection)
+        .await
+        .context("ical::export: exporting document")?;
+    Ok(from_document(&document))
+}
+
+/// Import iCalendar into the database; see [`to_document`] for how calendars match existing data
+pub async fn import(connection: &Connection, text: &str, mode: ImportMode) -> Result<ImportReport> {
+    let existing = Document::export(connection)
+        .await
+        .context("ical::import: exporting existing data")?;
+    let document = to_document(text, &existing).context("ical::import: reading calendars")?;
+    document
+        .import(connection, mode)
+        .await
+        .context("ical::import: importing document")
+}
@@ -0,0 +1,56 @@
+//! Export and import the whole database as JSON.
+
+use anyhow::{Context as _, Result};
+use rusqlite::Connection;
+
+use crate::{Document, ImportMode, ImportReport};
+
+/// Export the whole database as a JSON document.
+///
+/// The JSON is a serialized [`Document`], and looks like this:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "lists": [
+///     {
+///       "uuid": "0192f5c4-9b0e-7c3a-8d1e-5a4b3c2d1e0f",
+///       "title": "Groceries",
+///       "created_at": "2025-01-01T12:00:00Z",
+///       "items": [
+///         {
+///           "uuid": "0192f5c4-a1b2-7c3d-8e4f-5a6b7c8d9e0f",
+///           "description": "Milk",
+///           "is_completed": true,
+///           "position": 1,
+///           "created_at": "2025-01-01T12:01:00Z",
+///           "completed_at": "2025-01-02T09:30:00Z"
+///         }
+///       ]
+///     }
+///   ]
+/// }
+/// ```
+///
+/// Timestamps are RFC 3339. `version` is [`DOCUMENT_VERSION`](crate::DOCUMENT_VERSION) at the time
+/// of export; documents from a later version are rejected rather than misread.
+pub async fn export_json(connection: &Connection) -> Result<String> {
+    let document = Document::export(connection)
+        .await
+        .context("export_json: exporting document")?;
+    serde_json::to_string_pretty(&document).context("export_json: serializing document")
+}
+
+/// Import a JSON document produced by [`export_json`]
+pub async fn import_json(
+    connection: &Connection,
+    json: &str,
+    mode: ImportMode,
+) -> Result<ImportReport> {
+    let document =
+        serde_json::from_str::<Document>(json).context("import_json: parsing document")?;
+    document
+        .import(connection, mode)
+        .await
+        .context("import_json: importing document")
+}
@@ -1,5 +1,28 @@
+#[cfg(not(target_family = "wasm"))]
+mod backup;
+pub mod crdt;
+pub mod csv;
+mod document;
+pub mod encryption;
+pub mod ical;
+#[cfg(feature = "serde")]
+mod json;
+pub mod markdown;
 mod model;
 mod schema;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "threaded")]
+pub mod threaded;
+pub mod todo_txt;
 
+#[cfg(not(target_family = "wasm"))]
+pub use backup::{BackupProgress, backup, restore};
+pub use document::{
+    DOCUMENT_VERSION, Document, ImportMode, ImportReport, ItemDocument, ListDocument,
+};
+#[cfg(feature = "serde")]
+pub use json::{export_json, import_json};
 pub use model::{Item, ItemId, TodoList, TodoListId};
-pub use schema::apply_schema;
+pub use schema::{SCHEMA_VERSION, apply_schema, schema_version};
+pub use uuid::Uuid;
@@ -0,0 +1,194 @@
+//! GitHub-flavoured markdown checklists.
+//!
+//! A list is written as a heading followed by one task list item per item, in list order:
+//!
+//! ```markdown
+//! # Groceries
+//!
+//! - [x] Milk
+//! - [ ] Eggs
+//! ```
+//!
+//! Items have no hierarchy, so exported checklists are flat. When reading, nested task list items
+//! are flattened into their list in document order, and anything other than headings and task list
+//! items is skipped. Each heading starts a new list; task list items before the first heading
+//! belong to a list with a title chosen by the caller. Headings without task list items are
+//! skipped too, so empty lists don't survive a round trip.
+
+use anyhow::{Context as _, Result, bail};
+use rusqlite::Connection;
+
+use crate::{TodoList, TodoListId};
+
+/// A list as read from markdown, before it exists in the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checklist {
+    /// The text of the heading which introduced this checklist, if any
+    pub title: Option<String>,
+    pub items: Vec<ChecklistItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecklistItem {
+    pub is_completed: bool,
+    pub description: String,
+}
+
+/// Write a list as a markdown checklist
+pub fn from_list(list: &TodoList) -> String {
+    let mut items = list.items().values().collect::<Vec<_>>();
+    items.sort_by_key(|item| (item.position(), item.id()));
+
+    let mut out = format!("# {}\n\n", single_line(list.title()));
+    for item in items {
+        let check = if item.is_completed() { 'x' } else { ' ' };
+        out.push_str(&format!(
+            "- [{check}] {}\n",
+            single_line(item.description())
+        ));
+    }
+    out
+}
+
+/// Markdown ends a list item at a line break, so descriptions must fit on one line
+fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Read the checklists in a markdown document
+pub fn parse(text: &str) -> Vec<Checklist> {
+    let mut checklists = Vec::new();
+    let mut current = Checklist {
+        title: None,
+        items: Vec::new(),
+    };
+    for line in text.lines() {
+        if let Some(title) = heading(line) {
+            if !current.items.is_empty() {
+                checklists.push(current);
+            }
+            current = Checklist {
+                title: Some(title.to_owned()),
+                items: Vec::new(),
+            };
+        } else if let Some(item) = task_list_item(line) {
+            current.items.push(item);
+        }
+    }
+    if !current.items.is_empty() {
+        checklists.push(current);
+    }
+    checklists
+}
+
+/// The text of an ATX heading such as `## Groceries`
+fn heading(line: &str) -> Option<&str> {
+    let line = line.trim();
+    let text = line.trim_start_matches('#');
+    let level = line.len() - text.len();
+    if !(1..=6).contains(&level) || !(text.is_empty() || text.starts_with([' ', '\t'])) {
+        return None;
+    }
+    // an optional closing sequence of `#`s is not part of the heading
+    let text = text.trim();
+    let text = match text.trim_end_matches('#') {
+        trimmed if trimmed.is_empty() || trimmed.ends_with([' ', '\t']) => trimmed.trim_end(),
+        _ => text,
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+/// A line such as `  - [x] Milk`, at any depth of nesting
+fn task_list_item(line: &str) -> Option<ChecklistItem> {
+    let rest = line.trim_start();
+    let rest = rest
+        .strip_prefix(['-', '*', '+'])
+        .or_else(|| {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            (digits > 0)
+                .then(|| rest[digits..].strip_prefix(['.', ')']))
+                .flatten()
+        })?
+        .strip_prefix([' ', '\t'])?
+        .trim_start();
+    let is_completed = match rest.get(..3)? {
+        "[ ]" => false,
+        "[x]" | "[X]" => true,
+        _ => return None,
+    };
+    let description = &rest[3..];
+    if !(description.is_empty() || description.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some(ChecklistItem {
+        is_completed,
+        description: description.trim().to_owned(),
+    })
+}
+
+/// Export a list from the database as a markdown checklist
+pub async fn export(connection: &Connection, list: TodoListId) -> Result<String> {
+    let list = TodoList::load(connection, list)
+        .await
+        .context("markdown::export: loading list")?;
+    Ok(from_list(&list))
+}
+
+/// Export every list in the database, one after the other
+pub async fn export_all(connection: &Connection) -> Result<String> {
+    let mut out = Vec::new();
+    for (id, _) in TodoList::list_all(connection)
+        .await
+        .context("markdown::export_all: listing lists")?
+    {
+        out.push(
+            export(connection, id)
+                .await
+                .context("markdown::export_all: exporting list")?,
+        );
+    }
+    Ok(out.join("\n"))
+}
+
+/// Create a new list for each checklist in a markdown document.
+///
+/// Checklist items which come before any heading go in a list titled `default_title`.
+pub async fn import(
+    connection: &Connection,
+    text: &str,
+    default_title: &str,
+) -> Result<Vec<TodoList>> {
+    let checklists = parse(text);
+    if checklists.is_empty() {
+        bail!("markdown::import: no checklist items found");
+    }
+
+    let transaction = connection
+        .unchecked_transaction()
+        .context("markdown::import: starting transaction")?;
+    let mut lists = Vec::with_capacity(checklists.len());
+    for checklist in checklists {
+        let title = checklist.title.unwrap_or_else(|| default_title.to_owned());
+        let mut list = TodoList::new(&transaction, title)
+            .await
+            .context("markdown::import: creating list")?;
+        for item in checklist.items {
+            let id = list
+                .add_item(&transaction, item.description)
+                .await
+                .context("markdown::import: adding item")?;
+            list.item_mut(id)
+                .expect("an item which was just added exists")
+                .set_is_completed(item.is_completed);
+        }
+        list.save(&transaction)
+            .await
+            .context("markdown::import: saving list")?;
+        lists.push(list);
+    }
+    transaction
+        .commit()
+        .context("markdown::import: committing transaction")?;
+
+    Ok(lists)
+}
@@ -4,12 +4,15 @@ use anyhow::{Context as _, Result};
 use log::debug;
 use rusqlite::{Connection, ToSql, named_params, types::ToSqlOutput};
 use time::UtcDateTime;
+use uuid::Uuid;
 
-use crate::TodoListId;
+use crate::{TodoListId, crdt};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, derive_more::From, derive_more::Into,
 )]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct ItemId(u32);
 
 impl ToSql for ItemId {
@@ -27,14 +30,21 @@ impl log::kv::ToValue for ItemId {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, accessory::Accessors)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[access(get, defaults(all(cp)))]
 pub struct Item {
     id: ItemId,
+    /// Globally unique id, stable across databases
+    uuid: Uuid,
     list_id: TodoListId,
     #[access(get(cp = false))]
     description: String,
     is_completed: bool,
+    /// Where this item sorts within its list
+    position: i64,
+    #[cfg_attr(feature = "serde", serde(with = "super::rfc3339"))]
     created_at: UtcDateTime,
+    #[cfg_attr(feature = "serde", serde(skip))]
     dirty: bool,
 }
 
@@ -51,6 +61,12 @@ impl Item {
         self.dirty |= is_completed != self.is_completed;
         self.is_completed = is_completed;
     }
+
+    /// Set the position within the list
+    pub fn set_position(&mut self, position: i64) {
+        self.dirty |= position != self.position;
+        self.position = position;
+    }
 }
 
 // db impls
@@ -61,20 +77,36 @@ impl Item {
         list_id: TodoListId,
         description: String,
     ) -> Result<Self> {
+        let clock = crdt::tick(connection).context("Item::new: getting timestamp")?;
         let mut stmt = connection
             .prepare_cached(
-                "INSERT INTO todo_items (list_id, description)
-                VALUES (:list_id, :description)
-                RETURNING id, created_at",
+                "INSERT INTO todo_items (
+                    uuid, list_id, description, position, updated_at,
+                    description_clock, is_completed_clock, position_clock
+                )
+                VALUES (
+                    :uuid, :list_id, :description,
+                    (SELECT COALESCE(MAX(position), 0) + 1 FROM todo_items WHERE list_id = :list_id),
+                    strftime('%Y-%m-%d %H:%M:%f', 'now'),
+                    :clock, :clock, :clock
+                )
+                RETURNING id, position, created_at",
             )
             .context("Item::new: preparing statement")?;
-        let (id, created_at) = stmt
+        let uuid = Uuid::now_v7();
+        let (id, position, created_at) = stmt
             .query_row(
-                named_params! {":list_id": list_id, ":description": description.as_str()},
+                named_params! {
+                    ":uuid": uuid,
+                    ":list_id": list_id,
+                    ":description": description.as_str(),
+                    ":clock": clock,
+                },
                 |row| {
                     let id = row.get("id")?;
+                    let position = row.get("position")?;
                     let created_at = row.get::<_, String>("created_at")?;
-                    Ok((id, created_at))
+                    Ok((id, position, created_at))
                 },
             )
             .context("Item::new: inserting row")?;
@@ -83,14 +115,16 @@ impl Item {
         let created_at =
             super::parse_date(&created_at).context("TodoList::new: getting created_at")?;
 
-        debug!(id, list_id, created_at:debug; "inserted new Item into the db");
+        debug!(id, uuid:display, list_id, created_at:debug; "inserted new Item into the db");
 
         Ok(Self {
             id,
+            uuid,
             list_id,
             description,
             created_at,
             is_completed: false,
+            position,
             dirty: false,
         })
     }
@@ -102,10 +136,19 @@ impl Item {
             return Ok(());
         }
 
+        let clock = crdt::tick(connection).context("Item::save: getting timestamp")?;
+        // only fields which actually change get a new timestamp
         let mut stmt = connection
             .prepare_cached(
                 "UPDATE todo_items
-                SET description = :description, is_completed = :is_completed
+                SET description_clock = CASE WHEN description IS :description
+                        THEN description_clock ELSE :clock END,
+                    is_completed_clock = CASE WHEN is_completed IS :is_completed
+                        THEN is_completed_clock ELSE :clock END,
+                    position_clock = CASE WHEN position IS :position
+                        THEN position_clock ELSE :clock END,
+                    description = :description, is_completed = :is_completed, position = :position,
+                    updated_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
                 WHERE id = :id",
             )
             .context("Item::save: prepare statement")?;
@@ -113,6 +156,8 @@ impl Item {
             .execute(named_params! {
                 ":description": self.description.as_str(),
                 ":is_completed": self.is_completed,
+                ":position": self.position,
+                ":clock": clock,
                 ":id": self.id,
             })
             .context("Item::save: execute query")?;
@@ -128,17 +173,26 @@ impl Item {
     pub async fn load(connection: &Connection, id: ItemId) -> Result<Self> {
         let mut stmt = connection
             .prepare_cached(
-                "SELECT list_id, description, is_completed, created_at
+                "SELECT uuid, list_id, description, is_completed, position, created_at
                 FROM todo_items WHERE id = ?",
             )
             .context("Item::load: preparing statement")?;
-        let (list_id, description, is_completed, created_at) = stmt
+        let (uuid, list_id, description, is_completed, position, created_at) = stmt
             .query_row([id], |row| {
+                let uuid = row.get("uuid")?;
                 let list_id = row.get::<_, u32>("list_id")?;
                 let description = row.get("description")?;
                 let is_completed = row.get("is_completed")?;
+                let position = row.get("position")?;
                 let created_at = row.get::<_, String>("created_at")?;
-                Ok((list_id, description, is_completed, created_at))
+                Ok((
+                    uuid,
+                    list_id,
+                    description,
+                    is_completed,
+                    position,
+                    created_at,
+                ))
             })
             .context("Item::load: loading row")?;
 
@@ -150,14 +204,28 @@ impl Item {
 
         Ok(Self {
             id,
+            uuid,
             list_id,
             description,
             is_completed,
+            position,
             created_at,
             dirty: false,
         })
     }
 
+    /// Load an Item by its globally unique id
+    pub async fn load_by_uuid(connection: &Connection, uuid: Uuid) -> Result<Self> {
+        let id = connection
+            .prepare_cached("SELECT id FROM todo_items WHERE uuid = ?")
+            .context("Item::load_by_uuid: preparing statement")?
+            .query_row([uuid], |row| row.get::<_, u32>(0))
+            .context("Item::load_by_uuid: looking up id")?;
+        Self::load(connection, ItemId(id))
+            .await
+            .context("Item::load_by_uuid: loading item")
+    }
+
     /// Load all items by todo list id
     ///
     /// Not for public use; end-users should use the `TodoList` interface instead.
@@ -168,7 +236,7 @@ impl Item {
     ) -> Result<BTreeMap<ItemId, Self>> {
         let mut stmt = connection
             .prepare_cached(
-                "SELECT id, description, is_completed, created_at
+                "SELECT id, description, is_completed, created_at, uuid, position
                 FROM todo_items WHERE list_id = ?",
             )
             .context("Item::load_for_list: preparing statement")?;
@@ -189,14 +257,18 @@ impl Item {
                     .context("Item::load: getting created_at")?,
             )
             .context("Item::load: parsing created_at")?;
+            let uuid = row.get(4).context("Item::load: getting uuid")?;
+            let position = row.get(5).context("Item::load: getting position")?;
 
             let ejected = out.insert(
                 id,
                 Self {
                     id,
+                    uuid,
                     list_id,
                     description,
                     is_completed,
+                    position,
                     created_at,
                     dirty: false,
                 },
@@ -216,12 +288,21 @@ impl Item {
     ///
     /// Returns true if deleting removed an actual item.
     pub(crate) async fn delete(connection: &Connection, id: ItemId) -> Result<bool> {
-        let mut stmt = connection
-            .prepare_cached("DELETE FROM todo_items WHERE id = ?")
-            .context("Item::delete: preparing statement")?;
-        let affected_rows = stmt
-            .execute([id])
-            .context("Item::delete: executing delete")?;
+        let affected_rows = super::atomically(connection, || {
+            // remember the deletion, so that sync can't bring the item back
+            connection
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO tombstones (uuid) SELECT uuid FROM todo_items WHERE id = ?",
+                )
+                .context("Item::delete: preparing tombstone")?
+                .execute([id])
+                .context("Item::delete: recording tombstone")?;
+            connection
+                .prepare_cached("DELETE FROM todo_items WHERE id = ?")
+                .context("Item::delete: preparing statement")?
+                .execute([id])
+                .context("Item::delete: executing delete")
+        })?;
 
         debug!(id, "was_present" = affected_rows > 0; "deleted an item by its id");
 
@@ -5,6 +5,7 @@ pub use item::{Item, ItemId};
 pub use todo_list::{TodoList, TodoListId};
 
 use anyhow::{Context as _, Result};
+use rusqlite::Connection;
 use time::{UtcDateTime, format_description::StaticFormatDescription, macros::format_description};
 
 static SQLITE_TIMESTAMP_FORMAT: StaticFormatDescription =
@@ -14,3 +15,82 @@ fn parse_date(sql_date: &str) -> Result<UtcDateTime> {
     UtcDateTime::parse(sql_date, SQLITE_TIMESTAMP_FORMAT)
         .context(format!("parse_date: parsing the date ({sql_date:?})"))
 }
+
+/// Run `f` so that either all of its writes happen or none do.
+///
+/// Outside a transaction, this is a transaction of its own; inside one, such as an import, a savepoint.
+fn atomically<T>(connection: &Connection, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    if connection.is_autocommit() {
+        let tx = connection
+            .unchecked_transaction()
+            .context("atomically: starting transaction")?;
+        let out = f()?;
+        tx.commit().context("atomically: committing")?;
+        return Ok(out);
+    }
+
+    connection
+        .execute_batch("SAVEPOINT atomically")
+        .context("atomically: starting savepoint")?;
+    match f() {
+        Ok(out) => {
+            connection
+                .execute_batch("RELEASE atomically")
+                .context("atomically: releasing savepoint")?;
+            Ok(out)
+        }
+        Err(err) => {
+            // the enclosing transaction carries on without anything `f` wrote
+            let _ = connection.execute_batch("ROLLBACK TO atomically; RELEASE atomically");
+            Err(err)
+        }
+    }
+}
+
+/// Serialize timestamps as RFC 3339 strings
+#[cfg(feature = "serde")]
+pub(crate) mod rfc3339 {
+    use serde::{Deserialize as _, Deserializer, Serializer, de::Error as _, ser::Error as _};
+    use time::{UtcDateTime, format_description::well_known::Rfc3339};
+
+    pub(crate) fn serialize<S: Serializer>(
+        timestamp: &UtcDateTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let formatted = timestamp.format(&Rfc3339).map_err(S::Error::custom)?;
+        serializer.serialize_str(&formatted)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<UtcDateTime, D::Error> {
+        let formatted = String::deserialize(deserializer)?;
+        UtcDateTime::parse(&formatted, &Rfc3339).map_err(D::Error::custom)
+    }
+
+    /// Serialize optional timestamps as RFC 3339 strings or null
+    pub(crate) mod option {
+        use serde::{Deserialize as _, Deserializer, Serializer};
+        use time::UtcDateTime;
+
+        pub(crate) fn serialize<S: Serializer>(
+            timestamp: &Option<UtcDateTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match timestamp {
+                Some(timestamp) => super::serialize(timestamp, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<UtcDateTime>, D::Error> {
+            #[derive(serde::Deserialize)]
+            struct Timestamp(#[serde(with = "super")] UtcDateTime);
+
+            let timestamp = Option::<Timestamp>::deserialize(deserializer)?;
+            Ok(timestamp.map(|Timestamp(timestamp)| timestamp))
+        }
+    }
+}
@@ -4,12 +4,15 @@ use anyhow::{Context as _, Result};
 use log::debug;
 use rusqlite::{Connection, ToSql, named_params, types::ToSqlOutput};
 use time::UtcDateTime;
+use uuid::Uuid;
 
-use crate::{Item, ItemId};
+use crate::{Item, ItemId, crdt};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, derive_more::From, derive_more::Into,
 )]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct TodoListId(u32);
 
 impl ToSql for TodoListId {
@@ -27,19 +30,24 @@ impl log::kv::ToValue for TodoListId {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, accessory::Accessors)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[access(get, defaults(all(cp)))]
 pub struct TodoList {
     /// ID of this list
     id: TodoListId,
+    /// Globally unique ID of this list, stable across databases
+    uuid: Uuid,
     /// List title
     #[access(get(cp = false))]
     title: String,
     /// When this list was created
+    #[cfg_attr(feature = "serde", serde(with = "super::rfc3339"))]
     created_at: UtcDateTime,
     /// Todo list items
     #[access(get(cp = false))]
     items: BTreeMap<ItemId, Item>,
     /// Whether the list has been modified since being successfully saved
+    #[cfg_attr(feature = "serde", serde(skip))]
     dirty: bool,
 }
 
@@ -88,11 +96,17 @@ impl TodoList {
 
     /// Create a todo list
     pub async fn new(connection: &Connection, title: String) -> Result<Self> {
+        let clock = crdt::tick(connection).context("TodoList::new: getting timestamp")?;
         let mut stmt = connection
-            .prepare_cached("INSERT INTO todo_lists (title) VALUES (?) RETURNING id, created_at")
+            .prepare_cached(
+                "INSERT INTO todo_lists (uuid, title, title_clock, updated_at)
+                VALUES (?, ?, ?, strftime('%Y-%m-%d %H:%M:%f', 'now'))
+                RETURNING id, created_at",
+            )
             .context("TodoList::new: preparing statement")?;
+        let uuid = Uuid::now_v7();
         let (id, created_at) = stmt
-            .query_row([title.as_str()], |row| {
+            .query_row((uuid, title.as_str(), clock), |row| {
                 Ok((row.get("id")?, row.get::<_, String>("created_at")?))
             })
             .context("TodoList::new: getting insertion result row")?;
@@ -100,10 +114,11 @@ impl TodoList {
         let created_at =
             super::parse_date(&created_at).context("TodoList::new: getting created_at")?;
 
-        debug!(id, created_at:debug; "created a new todo list");
+        debug!(id, uuid:display, created_at:debug; "created a new todo list");
 
         Ok(Self {
             id,
+            uuid,
             title,
             created_at,
             items: BTreeMap::new(),
@@ -113,11 +128,21 @@ impl TodoList {
 
     /// Save this list, and only this list, regardless of whether it thinks it's dirty
     async fn save_inner(&mut self, connection: &Connection) -> Result<()> {
+        let clock = crdt::tick(connection).context("TodoList::save_inner: getting timestamp")?;
         let mut stmt = connection
-            .prepare_cached("UPDATE todo_lists SET title = :title WHERE id = :id")
+            .prepare_cached(
+                "UPDATE todo_lists
+                SET title_clock = CASE WHEN title IS :title THEN title_clock ELSE :clock END,
+                    title = :title, updated_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+                WHERE id = :id",
+            )
             .context("TodoList::save_inner: preparing statement")?;
         let affected_rows = stmt
-            .execute(named_params! {":title": self.title.as_str(), ":id": self.id})
+            .execute(named_params! {
+                ":title": self.title.as_str(),
+                ":clock": clock,
+                ":id": self.id,
+            })
             .context("TodoList::save_inner: executing query")?;
 
         debug!("list_id" = self.id; "saved todo list");
@@ -150,11 +175,15 @@ impl TodoList {
     /// Retrieve a todo list by its id
     pub async fn load(connection: &Connection, id: TodoListId) -> Result<Self> {
         let mut stmt = connection
-            .prepare_cached("SELECT title, created_at FROM todo_lists WHERE id = ?")
+            .prepare_cached("SELECT title, created_at, uuid FROM todo_lists WHERE id = ?")
             .context("TodoList::load: preparing statement")?;
-        let (title, created_at) = stmt
+        let (title, created_at, uuid) = stmt
             .query_row([id], |row| {
-                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                ))
             })
             .context("TodoList::load: querying row")?;
 
@@ -169,6 +198,7 @@ impl TodoList {
 
         Ok(Self {
             id,
+            uuid,
             title,
             created_at,
             items,
@@ -176,16 +206,48 @@ impl TodoList {
         })
     }
 
+    /// Retrieve a todo list by its globally unique id
+    pub async fn load_by_uuid(connection: &Connection, uuid: Uuid) -> Result<Self> {
+        let id = connection
+            .prepare_cached("SELECT id FROM todo_lists WHERE uuid = ?")
+            .context("TodoList::load_by_uuid: preparing statement")?
+            .query_row([uuid], |row| row.get::<_, u32>(0))
+            .context("TodoList::load_by_uuid: looking up id")?;
+        Self::load(connection, TodoListId(id))
+            .await
+            .context("TodoList::load_by_uuid: loading list")
+    }
+
     /// Delete a todo list by its id
     ///
     /// Returns `true` if this existed or `false` if the id had already been deleted.
     ///
-    /// Automatically removes related list items due to `ON DELETE CASCADE` in the schema.
+    /// Also removes the list's items. The schema's `ON DELETE CASCADE` only applies when foreign keys
+    /// are enforced, so the items are deleted explicitly. The list, its items, and their tombstones
+    /// go together or not at all.
     pub async fn delete(connection: &Connection, id: TodoListId) -> Result<bool> {
-        let mut stmt = connection
-            .prepare_cached("DELETE FROM todo_lists WHERE id = ?")
-            .context("TodoList::delete: preparing statement")?;
-        let affected_rows = stmt.execute([id]).context("TodoList::delete: deleting")?;
+        let affected_rows = super::atomically(connection, || {
+            // remember the deletion of the list and its items, so that sync can't bring them back
+            connection
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO tombstones (uuid)
+                    SELECT uuid FROM todo_lists WHERE id = ?1
+                    UNION ALL SELECT uuid FROM todo_items WHERE list_id = ?1",
+                )
+                .context("TodoList::delete: preparing tombstones")?
+                .execute([id])
+                .context("TodoList::delete: recording tombstones")?;
+            connection
+                .prepare_cached("DELETE FROM todo_items WHERE list_id = ?")
+                .context("TodoList::delete: preparing item delete")?
+                .execute([id])
+                .context("TodoList::delete: deleting items")?;
+            connection
+                .prepare_cached("DELETE FROM todo_lists WHERE id = ?")
+                .context("TodoList::delete: preparing statement")?
+                .execute([id])
+                .context("TodoList::delete: deleting")
+        })?;
 
         debug!("list_id" = id, "was_present" = affected_rows > 0; "deleted todo list by id");
 
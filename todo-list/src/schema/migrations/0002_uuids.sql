@@ -0,0 +1,39 @@
+-- Globally unique ids so that data can move between databases without clashing.
+--
+-- `ALTER TABLE` can't add a column with a non-constant default, so existing rows are backfilled
+-- by the migration and the application supplies new values on insert.
+ALTER TABLE todo_lists ADD COLUMN uuid BLOB;
+ALTER TABLE todo_items ADD COLUMN uuid BLOB;
+
+CREATE UNIQUE INDEX todo_lists_by_uuid ON todo_lists (uuid);
+CREATE UNIQUE INDEX todo_items_by_uuid ON todo_items (uuid);
+
+-- Every list and item must have a uuid, but `ALTER TABLE` can only add the columns as nullable.
+--
+-- Rebuilding the tables with `uuid BLOB NOT NULL` would mean dropping `todo_lists`, which deletes
+-- every item through `ON DELETE CASCADE` if the connection enforces foreign keys, and that can't
+-- be switched off inside the migration's transaction. These triggers enforce the same instead;
+-- they only reject nulls, so the backfill can still fill in the existing rows.
+CREATE TRIGGER todo_lists_uuid_insert BEFORE INSERT ON todo_lists
+WHEN NEW.uuid IS NULL
+BEGIN
+    SELECT RAISE(ABORT, 'NOT NULL constraint failed: todo_lists.uuid');
+END;
+
+CREATE TRIGGER todo_lists_uuid_update BEFORE UPDATE OF uuid ON todo_lists
+WHEN NEW.uuid IS NULL
+BEGIN
+    SELECT RAISE(ABORT, 'NOT NULL constraint failed: todo_lists.uuid');
+END;
+
+CREATE TRIGGER todo_items_uuid_insert BEFORE INSERT ON todo_items
+WHEN NEW.uuid IS NULL
+BEGIN
+    SELECT RAISE(ABORT, 'NOT NULL constraint failed: todo_items.uuid');
+END;
+
+CREATE TRIGGER todo_items_uuid_update BEFORE UPDATE OF uuid ON todo_items
+WHEN NEW.uuid IS NULL
+BEGIN
+    SELECT RAISE(ABORT, 'NOT NULL constraint failed: todo_items.uuid');
+END;
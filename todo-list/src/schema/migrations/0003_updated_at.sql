@@ -0,0 +1,10 @@
+-- Track when each row last changed, so that sync can resolve conflicting edits.
+--
+-- The application stamps these with millisecond precision whenever it writes a row.
+-- They compare correctly as strings against the second-precision `created_at` values used to
+-- backfill existing rows.
+ALTER TABLE todo_lists ADD COLUMN updated_at DATETIME;
+ALTER TABLE todo_items ADD COLUMN updated_at DATETIME;
+
+UPDATE todo_lists SET updated_at = created_at;
+UPDATE todo_items SET updated_at = created_at;
@@ -0,0 +1,25 @@
+-- Merge concurrent edits field by field; see `crate::crdt`.
+--
+-- Each mergeable field gets a `<field>_clock` column holding the hybrid logical timestamp of its
+-- latest write. Existing rows are stamped by the migration, from their `updated_at`.
+ALTER TABLE todo_items ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+UPDATE todo_items SET position = id;
+
+ALTER TABLE todo_lists ADD COLUMN title_clock BLOB;
+ALTER TABLE todo_items ADD COLUMN description_clock BLOB;
+ALTER TABLE todo_items ADD COLUMN is_completed_clock BLOB;
+ALTER TABLE todo_items ADD COLUMN position_clock BLOB;
+
+-- This database's identity as a replica, and the latest timestamp it has issued or observed.
+-- There is only ever one row.
+CREATE TABLE replica (
+    only INTEGER PRIMARY KEY CHECK (only = 1),
+    node BLOB NOT NULL,
+    clock BLOB NOT NULL
+);
+
+-- Uuids of deleted lists and items, so that late-arriving changes can't resurrect them.
+CREATE TABLE tombstones (
+    uuid BLOB PRIMARY KEY,
+    deleted_at DATETIME DEFAULT CURRENT_TIMESTAMP
+) WITHOUT ROWID;
@@ -1,14 +1,165 @@
 use anyhow::{Context as _, Result};
+use log::debug;
 use rusqlite::Connection;
+use uuid::Uuid;
 
-const SCHEMA: &str = include_str!("schema.sql");
+use crate::crdt::Hlc;
 
-/// Apply the schema to the database.
+/// A single step in the evolution of the schema.
 ///
-/// Must be called once on a new database before the database can be used.
-/// Must not be called repeatedly on the same database.
+/// The database's `user_version` pragma records how many migrations have been applied.
+struct Migration {
+    name: &'static str,
+    sql: &'static str,
+    /// Data fixups which can't be expressed in SQL, run after `sql`
+    fixup: Option<fn(&Connection) -> Result<()>>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "initial",
+        sql: include_str!("migrations/0001_initial.sql"),
+        fixup: None,
+    },
+    Migration {
+        name: "uuids",
+        sql: include_str!("migrations/0002_uuids.sql"),
+        fixup: Some(backfill_uuids),
+    },
+    Migration {
+        name: "updated_at",
+        sql: include_str!("migrations/0003_updated_at.sql"),
+        fixup: None,
+    },
+    Migration {
+        name: "crdt",
+        sql: include_str!("migrations/0004_crdt.sql"),
+        fixup: Some(initialize_clocks),
+    },
+];
+
+/// The schema version which this build of the library expects.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Apply the schema to the database, migrating it from whatever version it was at.
 ///
-/// Really we want a proper migration format, but that's too much to build right now for this demo.
+/// Must be called on every database before it is used.
+/// This is idempotent: migrations which have already been applied are skipped.
 pub async fn apply_schema(connection: &Connection) -> Result<()> {
-    connection.execute_batch(SCHEMA).context("applying schema")
+    let mut version = schema_version(connection).context("apply_schema: getting version")?;
+
+    for (migration, target_version) in MIGRATIONS.iter().zip(1..).skip(version as usize) {
+        let tx = connection
+            .unchecked_transaction()
+            .context("apply_schema: starting transaction")?;
+        tx.execute_batch(migration.sql)
+            .with_context(|| format!("applying migration {target_version} ({})", migration.name))?;
+        if let Some(fixup) = migration.fixup {
+            fixup(&tx).with_context(|| {
+                format!("fixing up migration {target_version} ({})", migration.name)
+            })?;
+        }
+        tx.pragma_update(None, "user_version", target_version)
+            .context("apply_schema: updating user_version")?;
+        tx.commit().context("apply_schema: committing migration")?;
+
+        debug!("version" = target_version, "name" = migration.name; "applied migration");
+        version = target_version;
+    }
+
+    debug_assert_eq!(version, SCHEMA_VERSION);
+    Ok(())
+}
+
+/// Get the number of migrations which have been applied to the database.
+pub fn schema_version(connection: &Connection) -> Result<u32> {
+    let version = connection
+        .pragma_query_value(None, "user_version", |row| row.get::<_, u32>(0))
+        .context("schema_version: querying user_version")?;
+    if version > 0 {
+        return Ok(version);
+    }
+
+    // databases created before migrations existed have the initial schema but no recorded version
+    let has_tables = connection
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'todo_lists')",
+            [],
+            |row| row.get::<_, bool>(0),
+        )
+        .context("schema_version: checking for legacy schema")?;
+    Ok(if has_tables { 1 } else { 0 })
+}
+
+/// Give every pre-existing row a uuid.
+fn backfill_uuids(connection: &Connection) -> Result<()> {
+    for table in ["todo_lists", "todo_items"] {
+        let ids = connection
+            .prepare(&format!("SELECT id FROM {table} WHERE uuid IS NULL"))
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| row.get::<_, u32>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .with_context(|| format!("backfill_uuids: selecting ids from {table}"))?;
+
+        let mut stmt = connection
+            .prepare(&format!("UPDATE {table} SET uuid = ? WHERE id = ?"))
+            .with_context(|| format!("backfill_uuids: preparing update for {table}"))?;
+        for id in &ids {
+            stmt.execute((Uuid::now_v7(), id))
+                .with_context(|| format!("backfill_uuids: updating {table}"))?;
+        }
+
+        debug!("table" = table, "count" = ids.len(); "backfilled uuids");
+    }
+    Ok(())
+}
+
+/// Give the database a replica identity, and stamp every existing field with a timestamp.
+fn initialize_clocks(connection: &Connection) -> Result<()> {
+    let node = Uuid::now_v7();
+    connection
+        .execute(
+            "INSERT INTO replica (only, node, clock) VALUES (1, ?, ?)",
+            (node, Hlc::new(0, 0, node)),
+        )
+        .context("initialize_clocks: creating replica")?;
+
+    for (table, fields) in [
+        ("todo_lists", &["title"][..]),
+        (
+            "todo_items",
+            &["description", "is_completed", "position"][..],
+        ),
+    ] {
+        let rows = connection
+            .prepare(&format!(
+                "SELECT id, CAST((julianday(COALESCE(updated_at, created_at)) - 2440587.5) * 86400000 AS INTEGER)
+                FROM {table}"
+            ))
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| {
+                    Ok((row.get::<_, u32>(0)?, row.get::<_, Option<i64>>(1)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .with_context(|| format!("initialize_clocks: selecting rows from {table}"))?;
+
+        let set = fields
+            .iter()
+            .map(|field| format!("{field}_clock = :clock"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut stmt = connection
+            .prepare(&format!("UPDATE {table} SET {set} WHERE id = :id"))
+            .with_context(|| format!("initialize_clocks: preparing update for {table}"))?;
+        for &(id, millis) in &rows {
+            let clock = Hlc::new(millis.unwrap_or_default().max(0) as u64, 0, node);
+            stmt.execute(rusqlite::named_params! {":clock": clock, ":id": id})
+                .with_context(|| format!("initialize_clocks: updating {table}"))?;
+        }
+
+        debug!("table" = table, "count" = rows.len(); "stamped existing fields");
+    }
+    Ok(())
 }
@@ -0,0 +1,564 @@
+//! Apply changes from another database, matching rows by uuid rather than by id.
+
+use std::{cell::Cell, cmp::Ordering};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use fallible_streaming_iterator::FallibleStreamingIterator as _;
+use log::debug;
+use rusqlite::{
+    Connection, OptionalExtension as _,
+    hooks::Action,
+    session::ChangesetItem,
+    types::{FromSql as _, Value},
+};
+use uuid::Uuid;
+
+use super::{Columns, Table, changes::Changes, changes::uuid_value, parse_changeset};
+use crate::crdt::{self, Hlc};
+
+/// How to resolve a column which was changed both locally and remotely
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConflictPolicy {
+    /// Each field keeps whichever value was written last, by hybrid logical clock.
+    ///
+    /// Fields without a clock fall back to comparing the rows' update times.
+    /// Deletions win over concurrent edits.
+    /// This is the only policy under which two databases which exchange changes converge.
+    #[default]
+    LastWriteWins,
+    /// Keep the local version
+    KeepLocal,
+    /// Take the remote version
+    TakeRemote,
+    /// Fail the entire apply operation
+    Abort,
+}
+
+/// What happened when applying a set of changes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ApplyReport {
+    /// Number of row changes which were applied, including those which were already present
+    pub applied: usize,
+    /// Number of row changes which conflicted with local changes, and were resolved by policy
+    pub conflicts: usize,
+    /// Number of row changes which could not be applied because the row or its list no longer exists
+    pub skipped: usize,
+}
+
+impl std::ops::AddAssign for ApplyReport {
+    fn add_assign(&mut self, other: Self) {
+        self.applied += other.applied;
+        self.conflicts += other.conflicts;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Column values of one side of a row change.
+///
+/// Updates only carry changed columns; unchanged columns are `None`.
+type Values = Vec<Option<Value>>;
+
+/// A row as it currently exists in the local database
+struct LocalRow {
+    id: i64,
+    values: Vec<Value>,
+}
+
+pub(super) fn apply_changes(
+    connection: &Connection,
+    changes: &Changes,
+    policy: ConflictPolicy,
+) -> Result<ApplyReport> {
+    let lists = Columns::load(connection, Table::Lists)?;
+    let items = Columns::load(connection, Table::Items)?;
+    let mut report = ApplyReport::default();
+    let latest_clock = Cell::new(None);
+
+    let appliers = Table::ALL.map(|table| Applier {
+        connection,
+        changes,
+        table,
+        columns: match table {
+            Table::Lists => &lists,
+            Table::Items => &items,
+        },
+        policy,
+        latest_clock: &latest_clock,
+    });
+
+    let changeset = parse_changeset(&changes.changeset)?;
+    // a changeset lists tables in the order they were first changed, but items can only be placed
+    // once their list exists, so apply one table at a time, lists first
+    for applier in &appliers {
+        let table = applier.table;
+        let mut iter = changeset
+            .iter()
+            .context("apply_changes: iterating changeset")?;
+        while let Some(item) = iter.next().context("apply_changes: reading changeset")? {
+            let op = item.op().context("apply_changes: reading operation")?;
+            if Table::from_name(op.table_name())? != table {
+                continue;
+            }
+            let action = op.code();
+            applier
+                .apply(item, action, &mut report)
+                .with_context(|| format!("applying {action:?} to {}", table.name()))?;
+        }
+    }
+
+    for &uuid in &changes.tombstones {
+        apply_tombstone(&appliers, uuid, &mut report)
+            .with_context(|| format!("applying tombstone of {uuid}"))?;
+    }
+
+    // keep our clock ahead of every write we've seen, so that later local edits supersede them
+    if let Some(latest_clock) = latest_clock.get() {
+        crdt::observe(connection, latest_clock).context("apply_changes: advancing clock")?;
+    }
+
+    Ok(report)
+}
+
+/// Delete the local copy of a row which was deleted remotely, from whichever table holds it
+fn apply_tombstone(appliers: &[Applier<'_>], uuid: Uuid, report: &mut ApplyReport) -> Result<()> {
+    for applier in appliers {
+        if let Some(local) = applier.local_row(uuid)? {
+            // nothing is known of the row's state when it was deleted
+            return applier.delete_local(uuid, local, None, report);
+        }
+    }
+    // already gone, or not yet arrived; either way it must stay gone. Snapshots repeat every
+    // tombstone, so this isn't counted as a change
+    appliers[0].record_deletion(uuid, None)
+}
+
+struct Applier<'a> {
+    connection: &'a Connection,
+    changes: &'a Changes,
+    table: Table,
+    columns: &'a Columns,
+    policy: ConflictPolicy,
+    /// The latest timestamp among the changes applied so far
+    latest_clock: &'a Cell<Option<Hlc>>,
+}
+
+impl Applier<'_> {
+    fn apply(&self, item: &ChangesetItem, action: Action, report: &mut ApplyReport) -> Result<()> {
+        let width = self.columns.names.len();
+        let read = |get: &dyn Fn(usize) -> rusqlite::Result<_>| -> Result<Values> {
+            (0..width)
+                .map(|column| match get(column) {
+                    Ok(value) => Ok(Some(Value::from(value))),
+                    // absent from this side of the change
+                    Err(rusqlite::Error::InvalidColumnIndex(_)) => Ok(None),
+                    Err(err) => Err(err).context("reading changeset value"),
+                })
+                .collect()
+        };
+
+        match action {
+            Action::SQLITE_INSERT => {
+                let new = read(&|column| item.new_value(column))?;
+                self.observe_clocks(&new)?;
+                let uuid = uuid_value(item.new_value(self.columns.uuid)?)?;
+                self.insert(uuid, new, report)
+            }
+            Action::SQLITE_UPDATE => {
+                let old = read(&|column| item.old_value(column))?;
+                let mut new = read(&|column| item.new_value(column))?;
+                self.observe_clocks(&new)?;
+                let remote_id = old[self.columns.id]
+                    .as_ref()
+                    .and_then(|id| u32::column_result(id.into()).ok())
+                    .context("update without primary key")?;
+                // a register whose clock moved but whose value didn't
+                for column in self.columns.data() {
+                    if let Some(clock) = self.columns.clock(column)
+                        && new[clock].is_some()
+                        && new[column].is_none()
+                    {
+                        new[column] = self
+                            .changes
+                            .restated
+                            .get(&(self.table, remote_id, column))
+                            .cloned();
+                    }
+                }
+                let uuid = *self
+                    .changes
+                    .identities
+                    .get(&(self.table, remote_id))
+                    .ok_or_else(|| anyhow!("no identity for remote row {remote_id}"))?;
+                self.update(uuid, old, new, report)
+            }
+            Action::SQLITE_DELETE => {
+                let old = read(&|column| item.old_value(column))?;
+                let uuid = uuid_value(item.old_value(self.columns.uuid)?)?;
+                self.delete(uuid, old, report)
+            }
+            _ => bail!("unknown changeset operation"),
+        }
+    }
+
+    fn insert(&self, uuid: Uuid, new: Values, report: &mut ApplyReport) -> Result<()> {
+        if self.is_deleted(uuid)? {
+            debug!(uuid:display, "table" = self.table.name(); "skipping insert of deleted row");
+            report.skipped += 1;
+            return Ok(());
+        }
+        let Some(new) = self.localize(new)? else {
+            debug!(uuid:display, "table" = self.table.name(); "skipping insert into missing list");
+            report.skipped += 1;
+            return Ok(());
+        };
+
+        match self.local_row(uuid)? {
+            None => {
+                let columns = (0..new.len()).filter(|&column| column != self.columns.id);
+                let (names, values): (Vec<_>, Vec<_>) = columns
+                    .filter_map(|column| {
+                        Some((self.columns.names[column].as_str(), new[column].clone()?))
+                    })
+                    .unzip();
+                let placeholders = vec!["?"; names.len()].join(", ");
+                self.connection
+                    .prepare_cached(&format!(
+                        "INSERT INTO {} ({}) VALUES ({placeholders})",
+                        self.table.name(),
+                        names.join(", ")
+                    ))
+                    .context("preparing insert")?
+                    .execute(rusqlite::params_from_iter(values))
+                    .context("inserting row")?;
+                report.applied += 1;
+                Ok(())
+            }
+            // the same row arrived by another route; nothing is known of its previous state, so
+            // every column which differs is a conflict
+            Some(local) => self.merge(local, None, &new, report),
+        }
+    }
+
+    fn update(&self, uuid: Uuid, old: Values, new: Values, report: &mut ApplyReport) -> Result<()> {
+        let Some(local) = self.local_row(uuid)? else {
+            debug!(uuid:display, "table" = self.table.name(); "skipping update of deleted row");
+            report.skipped += 1;
+            return Ok(());
+        };
+        let (Some(old), Some(new)) = (self.localize(old)?, self.localize(new)?) else {
+            report.skipped += 1;
+            return Ok(());
+        };
+        self.merge(local, Some(&old), &new, report)
+    }
+
+    fn delete(&self, uuid: Uuid, old: Values, report: &mut ApplyReport) -> Result<()> {
+        let Some(local) = self.local_row(uuid)? else {
+            // already gone, or not yet arrived; either way it must stay gone
+            self.record_deletion(uuid, None)?;
+            report.applied += 1;
+            return Ok(());
+        };
+        let old = self.localize(old)?;
+        self.delete_local(uuid, local, old, report)
+    }
+
+    /// Delete the local copy of a row, unless policy keeps it. `old` is `None` when nothing is
+    /// known of the remote row's state before it was deleted.
+    fn delete_local(
+        &self,
+        uuid: Uuid,
+        local: LocalRow,
+        old: Option<Values>,
+        report: &mut ApplyReport,
+    ) -> Result<()> {
+        let unchanged = old.is_some_and(|old| {
+            self.columns
+                .data()
+                .all(|column| old[column].as_ref() == Some(&local.values[column]))
+        });
+        if !unchanged {
+            report.conflicts += 1;
+            match self.policy {
+                ConflictPolicy::LastWriteWins | ConflictPolicy::TakeRemote => {}
+                ConflictPolicy::KeepLocal => return Ok(()),
+                ConflictPolicy::Abort => {
+                    bail!("row {uuid} was deleted remotely but changed locally")
+                }
+            }
+        }
+
+        self.record_deletion(uuid, Some(local.id))?;
+        if self.table == Table::Lists {
+            // mirror `ON DELETE CASCADE`, which only applies when foreign keys are enforced
+            self.connection
+                .prepare_cached("DELETE FROM todo_items WHERE list_id = ?")
+                .context("preparing item delete")?
+                .execute([local.id])
+                .context("deleting items of list")?;
+        }
+        self.connection
+            .prepare_cached(&format!("DELETE FROM {} WHERE id = ?", self.table.name()))
+            .context("preparing delete")?
+            .execute([local.id])
+            .context("deleting row")?;
+        report.applied += 1;
+        Ok(())
+    }
+
+    /// Merge remote column values into a local row.
+    ///
+    /// Under [`ConflictPolicy::LastWriteWins`], registers take whichever value has the later clock.
+    /// Otherwise, columns which only changed remotely are taken, and columns which changed on both
+    /// sides are resolved by policy. `old` is `None` when nothing is known of the remote row's
+    /// previous state.
+    fn merge(
+        &self,
+        local: LocalRow,
+        old: Option<&[Option<Value>]>,
+        new: &[Option<Value>],
+        report: &mut ApplyReport,
+    ) -> Result<()> {
+        let mut newer_registers = Vec::new();
+        let mut concurrent_registers = false;
+        let mut clean = Vec::new();
+        let mut conflicting = Vec::new();
+        for column in self.columns.data() {
+            let Some(new_value) = &new[column] else {
+                continue;
+            };
+            let changed_locally =
+                old.is_none_or(|old| old[column].as_ref() != Some(&local.values[column]));
+
+            if self.policy == ConflictPolicy::LastWriteWins
+                && let Some(clock) = self.columns.clock(column)
+            {
+                concurrent_registers |= changed_locally && *new_value != local.values[column];
+                // take the remote clock even if the values agree, so that the clocks agree too
+                if let Some(remote_clock) = &new[clock]
+                    && compare(remote_clock, &local.values[clock]) == Ordering::Greater
+                {
+                    newer_registers.extend([column, clock]);
+                }
+                continue;
+            }
+
+            if *new_value == local.values[column] {
+                continue;
+            }
+            if changed_locally {
+                conflicting.push(column);
+            } else {
+                clean.push(column);
+            }
+        }
+
+        if concurrent_registers && conflicting.is_empty() {
+            report.conflicts += 1;
+        }
+        let remote_wins = !conflicting.is_empty() && {
+            report.conflicts += 1;
+            match self.policy {
+                ConflictPolicy::LastWriteWins => self.remote_is_newer(&local, new, &conflicting),
+                ConflictPolicy::KeepLocal => false,
+                ConflictPolicy::TakeRemote => true,
+                ConflictPolicy::Abort => bail!(
+                    "{} row {} was changed both locally and remotely",
+                    self.table.name(),
+                    local.id
+                ),
+            }
+        };
+
+        let mut assignments = clean;
+        if remote_wins {
+            assignments.extend(conflicting);
+        }
+        // a register's value and clock travel together
+        let clocks = assignments
+            .iter()
+            .filter_map(|&column| self.columns.clock(column))
+            .filter(|&clock| new[clock].is_some())
+            .collect::<Vec<_>>();
+        assignments.extend(clocks);
+        assignments.extend(newer_registers);
+        // the row is as new as the newest change merged into it
+        let updated_at = self.columns.updated_at;
+        if let Some(remote_updated_at) = &new[updated_at]
+            && compare(remote_updated_at, &local.values[updated_at]) == Ordering::Greater
+        {
+            assignments.push(updated_at);
+        }
+
+        if !assignments.is_empty() {
+            let set = assignments
+                .iter()
+                .map(|&column| format!("{} = ?", self.columns.names[column]))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let values = assignments
+                .iter()
+                .map(|&column| {
+                    new[column]
+                        .clone()
+                        .expect("only present columns are assigned")
+                })
+                .chain([Value::Integer(local.id)]);
+            self.connection
+                .prepare_cached(&format!(
+                    "UPDATE {} SET {set} WHERE id = ?",
+                    self.table.name()
+                ))
+                .context("preparing update")?
+                .execute(rusqlite::params_from_iter(values))
+                .context("updating row")?;
+        }
+
+        report.applied += 1;
+        Ok(())
+    }
+
+    /// Last write wins: compare update timestamps, breaking ties by the conflicting values so that
+    /// both sides of an exchange reach the same decision.
+    fn remote_is_newer(
+        &self,
+        local: &LocalRow,
+        new: &[Option<Value>],
+        conflicting: &[usize],
+    ) -> bool {
+        let updated_at = self.columns.updated_at;
+        let by_time = match &new[updated_at] {
+            Some(remote) => compare(remote, &local.values[updated_at]),
+            None => Ordering::Equal,
+        };
+        by_time
+            .then_with(|| {
+                conflicting
+                    .iter()
+                    .map(|&column| {
+                        let remote = new[column]
+                            .as_ref()
+                            .expect("conflicting columns are present");
+                        compare(remote, &local.values[column])
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .is_gt()
+    }
+
+    /// Note the latest clock among a change's registers
+    fn observe_clocks(&self, new: &[Option<Value>]) -> Result<()> {
+        for clock in self.columns.clocks.iter().flatten() {
+            if let Some(Value::Blob(bytes)) = &new[*clock] {
+                let clock = Hlc::from_bytes(bytes).context("decoding clock")?;
+                self.latest_clock
+                    .set(self.latest_clock.get().max(Some(clock)));
+            }
+        }
+        Ok(())
+    }
+
+    /// `true` if a row with this uuid has been deleted locally
+    fn is_deleted(&self, uuid: Uuid) -> Result<bool> {
+        self.connection
+            .prepare_cached("SELECT EXISTS (SELECT 1 FROM tombstones WHERE uuid = ?)")
+            .context("preparing tombstone lookup")?
+            .query_row([uuid], |row| row.get(0))
+            .context("looking up tombstone")
+    }
+
+    /// Remember that a row was deleted, along with the items of a deleted list
+    fn record_deletion(&self, uuid: Uuid, local_id: Option<i64>) -> Result<()> {
+        self.connection
+            .prepare_cached("INSERT OR IGNORE INTO tombstones (uuid) VALUES (?)")
+            .context("preparing tombstone")?
+            .execute([uuid])
+            .context("recording tombstone")?;
+        if self.table == Table::Lists
+            && let Some(list_id) = local_id
+        {
+            self.connection
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO tombstones (uuid) SELECT uuid FROM todo_items WHERE list_id = ?",
+                )
+                .context("preparing item tombstones")?
+                .execute([list_id])
+                .context("recording item tombstones")?;
+        }
+        Ok(())
+    }
+
+    /// Translate remote values into local terms.
+    ///
+    /// The only column which needs translation is an item's list id.
+    /// Returns `None` if the item refers to a list which doesn't exist locally.
+    fn localize(&self, mut values: Values) -> Result<Option<Values>> {
+        let Some(column) = self.columns.list_id else {
+            return Ok(Some(values));
+        };
+        let Some(remote_list_id) = &values[column] else {
+            return Ok(Some(values));
+        };
+        let remote_list_id =
+            u32::column_result(remote_list_id.into()).context("decoding list id")?;
+        let list_uuid = self
+            .changes
+            .identities
+            .get(&(Table::Lists, remote_list_id))
+            .ok_or_else(|| anyhow!("no identity for remote list {remote_list_id}"))?;
+        let local_list_id = self
+            .connection
+            .prepare_cached("SELECT id FROM todo_lists WHERE uuid = ?")
+            .context("preparing list lookup")?
+            .query_row([list_uuid], |row| row.get::<_, i64>(0))
+            .optional()
+            .context("looking up list")?;
+        Ok(local_list_id.map(|id| {
+            values[column] = Some(Value::Integer(id));
+            values
+        }))
+    }
+
+    /// Load the local copy of a row by its uuid
+    fn local_row(&self, uuid: Uuid) -> Result<Option<LocalRow>> {
+        let names = self.columns.names.join(", ");
+        self.connection
+            .prepare_cached(&format!(
+                "SELECT {names} FROM {} WHERE uuid = ?",
+                self.table.name()
+            ))
+            .context("preparing local row lookup")?
+            .query_row([uuid], |row| {
+                let values = (0..self.columns.names.len())
+                    .map(|column| row.get::<_, Value>(column))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                let id = row.get(self.columns.id)?;
+                Ok(LocalRow { id, values })
+            })
+            .optional()
+            .context("looking up local row")
+    }
+}
+
+/// A total order over sqlite values, following sqlite's own ordering of storage classes
+fn compare(a: &Value, b: &Value) -> Ordering {
+    fn class(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Integer(_) | Value::Real(_) => 1,
+            Value::Text(_) => 2,
+            Value::Blob(_) => 3,
+        }
+    }
+
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::Real(a), Value::Real(b)) => a.total_cmp(b),
+        (Value::Integer(a), Value::Real(b)) => (*a as f64).total_cmp(b),
+        (Value::Real(a), Value::Integer(b)) => a.total_cmp(&(*b as f64)),
+        (Value::Text(a), Value::Text(b)) => a.cmp(b),
+        (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+        _ => class(a).cmp(&class(b)),
+    }
+}
@@ -0,0 +1,389 @@
+//! The serialized form of a set of changes.
+//!
+//! The session extension identifies rows by primary key, but our primary keys are local
+//! autoincrement ids which mean nothing in any other database. So alongside the raw changeset,
+//! we ship the uuid of every row the changeset refers to; the receiving side uses those to find
+//! its own copy of each row.
+//!
+//! When a register's value ends a session where it started but its clock moved on, the changeset
+//! carries the new clock without the value. The receiver can't tell what value goes with that
+//! clock, so we restate the value alongside.
+//!
+//! A changeset can only delete rows which existed when it was recorded, so a snapshot, which is
+//! recorded from nothing, lists the uuids of the rows deleted before it was taken as tombstones.
+//!
+//! Layout, all integers little-endian:
+//!
+//! ```text
+//! magic: [u8; 8] | schema_version: u32 | changeset_len: u32 | changeset: [u8; changeset_len]
+//! | identity_count: u32 | identities: [table: u8, id: u32, uuid: [u8; 16]; identity_count]
+//! | restated_count: u32 | restated: [table: u8, id: u32, column: u8, value; restated_count]
+//! | tombstone_count: u32 | tombstones: [uuid: [u8; 16]; tombstone_count]
+//!
+//! value: tag: u8 | payload
+//!     0 => null | 1 => integer: i64 | 2 => real: f64 | 3 => text: len: u32, [u8; len]
+//!     | 4 => blob: len: u32, [u8; len]
+//! ```
+
+use std::collections::HashMap;
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use fallible_streaming_iterator::FallibleStreamingIterator as _;
+use rusqlite::{
+    Connection, OptionalExtension as _,
+    hooks::Action,
+    session::{Changegroup, ChangesetItem},
+    types::{FromSql as _, Value, ValueRef},
+};
+use uuid::Uuid;
+
+use super::{Columns, Table, parse_changeset};
+use crate::SCHEMA_VERSION;
+
+const MAGIC: &[u8; 8] = b"TDLSYNC\0";
+
+/// A changeset plus the information needed to apply it to a different database
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Changes {
+    pub(crate) changeset: Vec<u8>,
+    /// Uuid of every row referenced by the changeset, keyed by its id in the originating database
+    pub(crate) identities: HashMap<(Table, u32), Uuid>,
+    /// Values of registers whose clock changed but whose value the changeset omits, keyed by table,
+    /// id in the originating database, and column
+    pub(crate) restated: HashMap<(Table, u32, usize), Value>,
+    /// Uuids of rows deleted in the originating database, besides any the changeset deletes
+    pub(crate) tombstones: Vec<Uuid>,
+}
+
+impl Changes {
+    /// Gather the identities referenced by a raw changeset recorded on `connection`.
+    pub(crate) fn new(connection: &Connection, changeset: Vec<u8>) -> Result<Self> {
+        let lists = Columns::load(connection, Table::Lists)?;
+        let items = Columns::load(connection, Table::Items)?;
+
+        let mut identities = HashMap::new();
+        let mut unidentified = Vec::new();
+
+        let parsed = parse_changeset(&changeset)?;
+        let mut iter = parsed.iter().context("Changes::new: iterating changeset")?;
+        while let Some(item) = iter.next().context("Changes::new: reading changeset")? {
+            let op = item.op().context("Changes::new: reading operation")?;
+            let table = Table::from_name(op.table_name())?;
+            let action = op.code();
+            let columns = match table {
+                Table::Lists => &lists,
+                Table::Items => &items,
+            };
+
+            // the primary key is always present in the old values, or the new values for inserts
+            let row_value = |column| match action {
+                Action::SQLITE_INSERT => item.new_value(column),
+                _ => item.old_value(column),
+            };
+            let id = row_value(columns.id)
+                .and_then(|value| Ok(u32::column_result(value)?))
+                .context("Changes::new: getting row id")?;
+
+            // inserts and deletes carry the whole row; updates only carry changed columns
+            if action == Action::SQLITE_UPDATE {
+                unidentified.push((table, id));
+            } else {
+                let uuid = uuid_value(row_value(columns.uuid)?)?;
+                identities.insert((table, id), uuid);
+            }
+
+            // items refer to their list, which the receiver also needs to identify
+            if table == Table::Items {
+                let list_id = columns.list_id.context("items must have a list id")?;
+                for list_id in list_ids(item, action, list_id)? {
+                    unidentified.push((Table::Lists, list_id));
+                }
+            }
+        }
+
+        for key @ (table, id) in unidentified {
+            if identities.contains_key(&key) {
+                continue;
+            }
+            let uuid = connection
+                .prepare_cached(&format!("SELECT uuid FROM {} WHERE id = ?", table.name()))
+                .context("Changes::new: preparing uuid lookup")?
+                .query_row([id], |row| row.get::<_, Uuid>(0))
+                .optional()
+                .context("Changes::new: looking up uuid")?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{} row {id} appears in the changeset but no longer exists",
+                        table.name()
+                    )
+                })?;
+            identities.insert(key, uuid);
+        }
+
+        let mut changes = Self {
+            changeset,
+            identities,
+            restated: HashMap::new(),
+            tombstones: Vec::new(),
+        };
+        changes
+            .restate(connection, &lists, &items)
+            .context("Changes::new: restating registers")?;
+        Ok(changes)
+    }
+
+    /// Look up the current value of every register whose clock changed but whose value is missing
+    /// from the changeset.
+    ///
+    /// Rows which no longer exist are left out; their deletion reached us from elsewhere, and will
+    /// reach any receiver before these changes do.
+    fn restate(&mut self, connection: &Connection, lists: &Columns, items: &Columns) -> Result<()> {
+        let present = |value: rusqlite::Result<ValueRef<'_>>| match value {
+            Ok(_) => Ok(true),
+            Err(rusqlite::Error::InvalidColumnIndex(_)) => Ok(false),
+            Err(err) => Err(err).context("reading changeset value"),
+        };
+
+        let mut missing = Vec::new();
+        let parsed = parse_changeset(&self.changeset)?;
+        let mut iter = parsed.iter().context("iterating changeset")?;
+        while let Some(item) = iter.next().context("reading changeset")? {
+            let op = item.op().context("reading operation")?;
+            if op.code() != Action::SQLITE_UPDATE {
+                continue;
+            }
+            let table = Table::from_name(op.table_name())?;
+            let columns = match table {
+                Table::Lists => lists,
+                Table::Items => items,
+            };
+            let id = u32::column_result(item.old_value(columns.id)?).context("getting row id")?;
+            for column in columns.data() {
+                if let Some(clock) = columns.clock(column)
+                    && present(item.new_value(clock))?
+                    && !present(item.new_value(column))?
+                {
+                    missing.push((table, id, column, &columns.names[column]));
+                }
+            }
+        }
+
+        for (table, id, column, name) in missing {
+            let value = connection
+                .prepare_cached(&format!("SELECT {name} FROM {} WHERE id = ?", table.name()))
+                .context("preparing register lookup")?
+                .query_row([id], |row| row.get::<_, Value>(0))
+                .optional()
+                .context("looking up register")?;
+            if let Some(value) = value {
+                self.restated.insert((table, id, column), value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes which change nothing
+    pub(crate) fn empty() -> Self {
+        Self {
+            changeset: Vec::new(),
+            identities: HashMap::new(),
+            restated: HashMap::new(),
+            tombstones: Vec::new(),
+        }
+    }
+
+    /// Append later changes recorded on `connection`
+    pub(crate) fn extend(&mut self, connection: &Connection, later: Self) -> Result<()> {
+        let mut group = Changegroup::new().context("Changes::extend: creating changegroup")?;
+        for changeset in [&self.changeset, &later.changeset] {
+            group
+                .add_stream(&mut changeset.as_slice())
+                .context("Changes::extend: reading changeset")?;
+        }
+        let mut changeset = Vec::new();
+        group
+            .output_strm(&mut changeset)
+            .context("Changes::extend: writing changeset")?;
+        self.changeset = changeset;
+        // ids are never reused, so the same id means the same row
+        self.identities.extend(later.identities);
+        self.restated.extend(later.restated);
+        self.tombstones.extend(later.tombstones);
+        // a value which changed in one part and changed back in the other is missing from both
+        let lists = Columns::load(connection, Table::Lists)?;
+        let items = Columns::load(connection, Table::Items)?;
+        self.restate(connection, &lists, &items)
+            .context("Changes::extend: restating registers")
+    }
+
+    /// Serialize these changes
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            MAGIC.len() + 12 + self.changeset.len() + self.identities.len() * 21,
+        );
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&SCHEMA_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.changeset.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.changeset);
+        out.extend_from_slice(&(self.identities.len() as u32).to_le_bytes());
+        for (&(table, id), uuid) in &self.identities {
+            out.push(table as u8);
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(uuid.as_bytes());
+        }
+        out.extend_from_slice(&(self.restated.len() as u32).to_le_bytes());
+        for (&(table, id, column), value) in &self.restated {
+            out.push(table as u8);
+            out.extend_from_slice(&id.to_le_bytes());
+            out.push(column as u8);
+            write_value(&mut out, value);
+        }
+        out.extend_from_slice(&(self.tombstones.len() as u32).to_le_bytes());
+        for uuid in &self.tombstones {
+            out.extend_from_slice(uuid.as_bytes());
+        }
+        out
+    }
+
+    /// Deserialize changes produced by [`Self::to_bytes`]
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+
+        ensure!(
+            reader.take(MAGIC.len())? == MAGIC,
+            "not a todo-list changeset"
+        );
+        let schema_version = reader.u32()?;
+        ensure!(
+            schema_version == SCHEMA_VERSION,
+            "changeset has schema version {schema_version} but this database has {SCHEMA_VERSION}"
+        );
+
+        let changeset_len = reader.u32()? as usize;
+        let changeset = reader.take(changeset_len)?.to_vec();
+
+        let identity_count = reader.u32()?;
+        let mut identities = HashMap::with_capacity(identity_count as usize);
+        for _ in 0..identity_count {
+            let table = Table::from_tag(reader.take(1)?[0])?;
+            let id = reader.u32()?;
+            let uuid = Uuid::from_slice(reader.take(16)?).context("decoding uuid")?;
+            identities.insert((table, id), uuid);
+        }
+
+        let restated_count = reader.u32()?;
+        let mut restated = HashMap::with_capacity(restated_count as usize);
+        for _ in 0..restated_count {
+            let table = Table::from_tag(reader.take(1)?[0])?;
+            let id = reader.u32()?;
+            let column = usize::from(reader.take(1)?[0]);
+            restated.insert((table, id, column), reader.value()?);
+        }
+
+        let tombstone_count = reader.u32()?;
+        let tombstones = (0..tombstone_count)
+            .map(|_| Uuid::from_slice(reader.take(16)?).context("decoding tombstone"))
+            .collect::<Result<Vec<_>>>()?;
+
+        ensure!(reader.0.is_empty(), "trailing bytes after changeset");
+
+        Ok(Self {
+            changeset,
+            identities,
+            restated,
+            tombstones,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            bail!("changeset truncated");
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("took 4 bytes")))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("took 8 bytes")))
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        Ok(match self.take(1)?[0] {
+            0 => Value::Null,
+            1 => Value::Integer(self.u64()? as i64),
+            2 => Value::Real(f64::from_bits(self.u64()?)),
+            3 => {
+                let len = self.u32()? as usize;
+                let text = std::str::from_utf8(self.take(len)?).context("decoding text value")?;
+                Value::Text(text.to_owned())
+            }
+            4 => {
+                let len = self.u32()? as usize;
+                Value::Blob(self.take(len)?.to_vec())
+            }
+            tag => bail!("unknown value tag {tag}"),
+        })
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0),
+        Value::Integer(integer) => {
+            out.push(1);
+            out.extend_from_slice(&integer.to_le_bytes());
+        }
+        Value::Real(real) => {
+            out.push(2);
+            out.extend_from_slice(&real.to_bits().to_le_bytes());
+        }
+        Value::Text(text) => {
+            out.push(3);
+            out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+            out.extend_from_slice(text.as_bytes());
+        }
+        Value::Blob(blob) => {
+            out.push(4);
+            out.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+            out.extend_from_slice(blob);
+        }
+    }
+}
+
+/// Interpret a changeset value as a uuid
+pub(super) fn uuid_value(value: ValueRef<'_>) -> Result<Uuid> {
+    Uuid::column_result(value).context("decoding uuid from changeset")
+}
+
+/// Get the list ids an item operation refers to.
+///
+/// Item updates which don't move the item to another list don't mention the list at all.
+fn list_ids(item: &ChangesetItem, action: Action, column: usize) -> Result<Vec<u32>> {
+    let values = match action {
+        Action::SQLITE_INSERT => vec![item.new_value(column)],
+        Action::SQLITE_DELETE => vec![item.old_value(column)],
+        _ => match item.new_value(column) {
+            Ok(new) => vec![item.old_value(column), Ok(new)],
+            Err(rusqlite::Error::InvalidColumnIndex(_)) => Vec::new(),
+            Err(err) => vec![Err(err)],
+        },
+    };
+    values
+        .into_iter()
+        .map(|value| {
+            let value = value.context("getting list id from changeset")?;
+            u32::column_result(value).context("decoding list id from changeset")
+        })
+        .collect()
+}
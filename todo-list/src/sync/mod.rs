@@ -0,0 +1,288 @@
+//! Exchange changes between databases.
+//!
+//! A [`Recorder`] uses the sqlite session extension to track every change made through its
+//! connection. Those changes are exported as opaque bytes, which can be carried to another database
+//! by any means and [`apply`]'d there. [`snapshot`] exports the whole database in the same format,
+//! deletions included, for bringing a fresh replica up to date.
+//!
+//! Rows are matched between databases by their uuid. Concurrent edits to different columns of a row
+//! merge cleanly; edits to the same column are resolved according to a [`ConflictPolicy`].
+
+mod apply;
+mod changes;
+
+use anyhow::{Context as _, Result, anyhow};
+use log::debug;
+use rusqlite::{
+    Connection,
+    session::{Changegroup, Changeset, Session},
+    types::Value,
+};
+
+use uuid::Uuid;
+
+use self::changes::Changes;
+
+pub use self::apply::{ApplyReport, ConflictPolicy};
+
+/// A table which participates in sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub(crate) enum Table {
+    Lists = 0,
+    Items = 1,
+}
+
+impl Table {
+    /// Parents before children, so that inserted items can always find their list
+    const ALL: [Self; 2] = [Self::Lists, Self::Items];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Lists => "todo_lists",
+            Self::Items => "todo_items",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|table| table.name() == name)
+            .ok_or_else(|| anyhow!("changeset refers to unknown table {name:?}"))
+    }
+
+    fn from_tag(tag: u8) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|&table| table as u8 == tag)
+            .ok_or_else(|| anyhow!("changeset refers to unknown table tag {tag}"))
+    }
+}
+
+/// Positions of the columns of a synced table, as they appear in its changesets
+#[derive(Debug)]
+pub(crate) struct Columns {
+    names: Vec<String>,
+    id: usize,
+    uuid: usize,
+    updated_at: usize,
+    /// Only items have a list id
+    list_id: Option<usize>,
+    /// For each column which is a last-write-wins register, the position of its `_clock` column
+    clocks: Vec<Option<usize>>,
+}
+
+impl Columns {
+    fn load(connection: &Connection, table: Table) -> Result<Self> {
+        let names = connection
+            .prepare(&format!("PRAGMA table_info({})", table.name()))
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| row.get::<_, String>("name"))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .with_context(|| format!("Columns::load: getting columns of {}", table.name()))?;
+        let position = |name: &str| names.iter().position(|column| column == name);
+        let required = |name: &str| {
+            position(name).ok_or_else(|| anyhow!("{} has no {name} column", table.name()))
+        };
+
+        let clocks = names
+            .iter()
+            .map(|name| position(&format!("{name}_clock")))
+            .collect();
+
+        Ok(Self {
+            id: required("id")?,
+            uuid: required("uuid")?,
+            updated_at: required("updated_at")?,
+            list_id: position("list_id"),
+            clocks,
+            names,
+        })
+    }
+
+    /// Columns holding row data, as opposed to identity or bookkeeping
+    fn data(&self) -> impl Iterator<Item = usize> {
+        (0..self.names.len()).filter(|&column| {
+            column != self.id
+                && column != self.uuid
+                && column != self.updated_at
+                && !self.clocks.contains(&Some(column))
+        })
+    }
+
+    /// The clock column of a register column
+    fn clock(&self, column: usize) -> Option<usize> {
+        self.clocks[column]
+    }
+}
+
+/// Parse a raw changeset
+fn parse_changeset(bytes: &[u8]) -> Result<Changeset> {
+    let mut group = Changegroup::new().context("parse_changeset: creating changegroup")?;
+    group
+        .add_stream(&mut &*bytes)
+        .context("parse_changeset: reading changeset")?;
+    group
+        .output()
+        .context("parse_changeset: collecting changeset")
+}
+
+/// Start a session which records changes to all synced tables
+fn start_session(connection: &Connection) -> Result<Session<'_>> {
+    let mut session = Session::new(connection).context("start_session: creating session")?;
+    for table in Table::ALL {
+        session
+            .attach(Some(table.name()))
+            .with_context(|| format!("start_session: attaching {}", table.name()))?;
+    }
+    Ok(session)
+}
+
+/// Export everything a session has recorded
+fn export_session(connection: &Connection, session: &mut Session<'_>) -> Result<Changes> {
+    let mut changeset = Vec::new();
+    session
+        .changeset_strm(&mut changeset)
+        .context("export_session: writing changeset")?;
+    Changes::new(connection, changeset).context("export_session: identifying rows")
+}
+
+/// Records changes made through a connection so they can be sent to other databases.
+///
+/// Only changes made while a recorder is alive are recorded.
+/// Use [`snapshot`] to bring another database up to date with changes made before that.
+pub struct Recorder<'conn> {
+    connection: &'conn Connection,
+    session: Session<'conn>,
+    /// Changes exported from earlier sessions but not yet taken
+    pending: Option<Changes>,
+}
+
+impl<'conn> Recorder<'conn> {
+    /// Start recording changes made through this connection
+    pub fn new(connection: &'conn Connection) -> Result<Self> {
+        let session = start_session(connection).context("Recorder::new: starting session")?;
+        Ok(Self {
+            connection,
+            session,
+            pending: None,
+        })
+    }
+
+    /// `true` if no changes have been recorded since the last call to [`Self::take_changes`]
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_none() && self.session.is_empty()
+    }
+
+    /// Export what the session has recorded into the pending changes, and start a fresh session.
+    ///
+    /// Rows are identified at export time, so this must happen before anything the session can't
+    /// see deletes a row the session has recorded.
+    fn seal(&mut self) -> Result<()> {
+        if self.session.is_empty() {
+            return Ok(());
+        }
+        let changes = export_session(self.connection, &mut self.session)?;
+        self.session = start_session(self.connection)?;
+        self.pending = Some(match self.pending.take() {
+            Some(mut pending) => {
+                pending.extend(self.connection, changes)?;
+                pending
+            }
+            None => changes,
+        });
+        Ok(())
+    }
+
+    /// Export the changes recorded so far, and start recording afresh.
+    pub async fn take_changes(&mut self) -> Result<Vec<u8>> {
+        self.seal()
+            .context("Recorder::take_changes: exporting session")?;
+        let changes = self
+            .pending
+            .take()
+            .map(|pending| pending.to_bytes())
+            .unwrap_or_else(|| Changes::empty().to_bytes());
+        debug!("len" = changes.len(); "took recorded changes");
+        Ok(changes)
+    }
+
+    /// Apply changes from another database; see [`apply`].
+    ///
+    /// Changes applied this way are not themselves recorded, so they don't echo back to their source.
+    pub async fn apply(&mut self, changes: &[u8], policy: ConflictPolicy) -> Result<ApplyReport> {
+        // remote deletions could remove rows which local changes refer to
+        self.seal()
+            .context("Recorder::apply: exporting local changes")?;
+        self.session.set_enabled(false);
+        let report = apply(self.connection, changes, policy).await;
+        self.session.set_enabled(true);
+        report
+    }
+}
+
+/// Export the entire contents of the database in the same format as [`Recorder::take_changes`].
+///
+/// Rows which were deleted are included as tombstones, so that applying a snapshot deletes them
+/// wherever they still exist.
+pub async fn snapshot(connection: &Connection) -> Result<Vec<u8>> {
+    // record the insertion of every row into a scratch database with identical ids
+    let scratch = Connection::open_in_memory().context("snapshot: opening scratch database")?;
+    crate::apply_schema(&scratch)
+        .await
+        .context("snapshot: applying schema to scratch database")?;
+    let mut session = start_session(&scratch).context("snapshot: starting session")?;
+
+    for table in Table::ALL {
+        let columns = Columns::load(connection, table)?;
+        let names = columns.names.join(", ");
+        let placeholders = vec!["?"; columns.names.len()].join(", ");
+        let mut insert = scratch
+            .prepare(&format!(
+                "INSERT INTO {} ({names}) VALUES ({placeholders})",
+                table.name()
+            ))
+            .context("snapshot: preparing insert")?;
+
+        let mut select = connection
+            .prepare(&format!("SELECT {names} FROM {}", table.name()))
+            .context("snapshot: preparing select")?;
+        let mut rows = select.query([]).context("snapshot: selecting rows")?;
+        while let Some(row) = rows.next().context("snapshot: reading row")? {
+            insert
+                .execute(rusqlite::params_from_iter(
+                    (0..columns.names.len()).map(|column| Value::from(row.get_ref_unwrap(column))),
+                ))
+                .context("snapshot: copying row")?;
+        }
+    }
+
+    let mut changes = export_session(&scratch, &mut session)?;
+    changes.tombstones = connection
+        .prepare("SELECT uuid FROM tombstones")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get::<_, Uuid>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .context("snapshot: reading tombstones")?;
+    Ok(changes.to_bytes())
+}
+
+/// Apply changes exported from another database.
+///
+/// All changes are applied in a single transaction; if any fails, none are applied.
+pub async fn apply(
+    connection: &Connection,
+    changes: &[u8],
+    policy: ConflictPolicy,
+) -> Result<ApplyReport> {
+    let changes = Changes::from_bytes(changes).context("apply: decoding changes")?;
+    let tx = connection
+        .unchecked_transaction()
+        .context("apply: starting transaction")?;
+    let report = apply::apply_changes(&tx, &changes, policy)?;
+    tx.commit().context("apply: committing")?;
+    debug!(report:debug, policy:debug; "applied remote changes");
+    Ok(report)
+}
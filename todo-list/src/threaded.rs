@@ -0,0 +1,178 @@
+//! A non-blocking database handle for native front-ends.
+//!
+//! The rest of this crate exposes `async fn`s which nonetheless run blocking rusqlite calls inline.
+//! That's fine in wasm, where there is nothing else to do, but it means a native UI which awaits them
+//! stalls for as long as the query takes, or indefinitely if the database is locked.
+//!
+//! [`Database`] instead moves the [`Connection`] onto a dedicated thread and communicates with it over
+//! a channel, so awaiting one of its methods only ever waits on the channel.
+
+use std::{path::Path, thread};
+
+use anyhow::{Context as _, Result, anyhow};
+use futures_lite::future::block_on;
+use log::debug;
+use rusqlite::Connection;
+
+use crate::{ItemId, TodoList, TodoListId, Uuid};
+
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+/// A handle to a connection running on its own thread.
+///
+/// Handles are cheap to clone; all clones share the same connection.
+/// The thread shuts down and the connection closes once the last handle is dropped.
+#[derive(Debug, Clone)]
+pub struct Database {
+    jobs: async_channel::Sender<Job>,
+}
+
+/// The worker's end of the job queue, which refuses further jobs once the worker stops, even by panicking.
+///
+/// Jobs still queued are dropped along with the senders of their results, so their callers stop waiting.
+struct Shutdown(async_channel::Receiver<Job>);
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        self.0.close();
+        while self.0.try_recv().is_ok() {}
+    }
+}
+
+impl Database {
+    /// Open a database at the given path and move its connection onto a worker thread.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let connection = Connection::open(path).context("Database::open: opening connection")?;
+        Self::new(connection)
+    }
+
+    /// Move an existing connection onto a worker thread.
+    pub fn new(mut connection: Connection) -> Result<Self> {
+        let (jobs, receiver) = async_channel::unbounded::<Job>();
+        thread::Builder::new()
+            .name("todo-list-db".into())
+            .spawn(move || {
+                let jobs = Shutdown(receiver);
+                while let Ok(job) = jobs.0.recv_blocking() {
+                    job(&mut connection);
+                }
+                debug!("all database handles dropped; closing connection");
+            })
+            .context("Database::new: spawning worker thread")?;
+        Ok(Self { jobs })
+    }
+
+    /// Run an arbitrary operation against the connection on the worker thread.
+    ///
+    /// Operations run one at a time in the order they were submitted. If one panics, the worker thread stops, and
+    /// it and every later operation fail.
+    pub async fn call<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = async_channel::bounded(1);
+        self.jobs
+            .send(Box::new(move |connection| {
+                // the caller may have stopped waiting; that's fine
+                let _ = sender.send_blocking(f(connection));
+            }))
+            .await
+            .map_err(|_| anyhow!("Database::call: worker thread has shut down"))?;
+        receiver
+            .recv()
+            .await
+            .map_err(|_| anyhow!("Database::call: worker thread dropped the operation"))?
+    }
+
+    /// Apply the schema; see [`crate::apply_schema`]
+    pub async fn apply_schema(&self) -> Result<()> {
+        self.call(|connection| block_on(crate::apply_schema(connection)))
+            .await
+    }
+
+    /// Get the id and title of all todo lists; see [`TodoList::list_all`]
+    pub async fn list_all(&self) -> Result<Vec<(TodoListId, String)>> {
+        self.call(|connection| block_on(TodoList::list_all(connection)))
+            .await
+    }
+
+    /// Create a todo list; see [`TodoList::new`]
+    pub async fn new_list(&self, title: String) -> Result<TodoList> {
+        self.call(move |connection| block_on(TodoList::new(connection, title)))
+            .await
+    }
+
+    /// Retrieve a todo list by its id; see [`TodoList::load`]
+    pub async fn load(&self, id: TodoListId) -> Result<TodoList> {
+        self.call(move |connection| block_on(TodoList::load(connection, id)))
+            .await
+    }
+
+    /// Retrieve a todo list by its globally unique id; see [`TodoList::load_by_uuid`]
+    pub async fn load_by_uuid(&self, uuid: Uuid) -> Result<TodoList> {
+        self.call(move |connection| block_on(TodoList::load_by_uuid(connection, uuid)))
+            .await
+    }
+
+    /// Delete a todo list by its id; see [`TodoList::delete`]
+    pub async fn delete(&self, id: TodoListId) -> Result<bool> {
+        self.call(move |connection| block_on(TodoList::delete(connection, id)))
+            .await
+    }
+
+    /// Persist a todo list and its items; see [`TodoList::save`]
+    ///
+    /// The list is handed back once saved so that its dirty flags are up to date.
+    pub async fn save(&self, mut todo_list: TodoList) -> Result<TodoList> {
+        self.call(move |connection| {
+            block_on(todo_list.save(connection))?;
+            Ok(todo_list)
+        })
+        .await
+    }
+
+    /// Add an item to a todo list; see [`TodoList::add_item`]
+    pub async fn add_item(
+        &self,
+        mut todo_list: TodoList,
+        description: String,
+    ) -> Result<(TodoList, ItemId)> {
+        self.call(move |connection| {
+            let item_id = block_on(todo_list.add_item(connection, description))?;
+            Ok((todo_list, item_id))
+        })
+        .await
+    }
+
+    /// Remove an item from a todo list; see [`TodoList::remove_item`]
+    pub async fn remove_item(
+        &self,
+        mut todo_list: TodoList,
+        item_id: ItemId,
+    ) -> Result<(TodoList, bool)> {
+        self.call(move |connection| {
+            let did_remove = block_on(todo_list.remove_item(connection, item_id))?;
+            Ok((todo_list, did_remove))
+        })
+        .await
+    }
+
+    /// Export the whole database as JSON; see [`crate::export_json`]
+    #[cfg(feature = "serde")]
+    pub async fn export_json(&self) -> Result<String> {
+        self.call(|connection| block_on(crate::export_json(connection)))
+            .await
+    }
+
+    /// Import a JSON document; see [`crate::import_json`]
+    #[cfg(feature = "serde")]
+    pub async fn import_json(
+        &self,
+        json: String,
+        mode: crate::ImportMode,
+    ) -> Result<crate::ImportReport> {
+        self.call(move |connection| block_on(crate::import_json(connection, &json, mode)))
+            .await
+    }
+}
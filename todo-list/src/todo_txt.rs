@@ -0,0 +1,347 @@
+//! The [todo.txt](https://github.com/todotxt/todo.txt) format.
+//!
+//! Each line of a todo.txt file is a [`Task`]:
+//!
+//! ```text
+//! x 2025-01-03 2025-01-01 +Home Fix the sink @hardware_store uuid:0192f5c4-a1b2-7c3d-8e4f-5a6b7c8d9e0f
+//! (A) 2025-01-02 Call the plumber +Home @phone
+//! ```
+//!
+//! Tasks map onto lists and items like so:
+//!
+//! - `x` marks a completed item.
+//! - The creation date is the item's creation date. The completion date is when the item was last
+//!   marked completed; the database dates that by its own clock, so it is only ever exported.
+//! - The item has no priority of its own, so a priority such as `(A)` stays at the start of its
+//!   description, where todo.txt puts it anyway.
+//! - The first `+project` names the item's list, and is removed from the description. Exported
+//!   tasks lead with it, so that projects within a description stay put. Spaces in a list's title
+//!   become underscores in its project. Tasks without a project belong to [`DEFAULT_LIST`].
+//! - `@context`s, further `+project`s, and `key:value` tags stay in the description.
+//! - A `uuid:` tag carries the item's uuid, so that re-importing an exported file updates items
+//!   rather than duplicating them.
+//!
+//! Within a list, items keep the order of their lines. Lists without items have no lines, so they
+//! don't survive a round trip.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use rusqlite::Connection;
+use time::{
+    Date, Time, UtcDateTime, format_description::StaticFormatDescription,
+    macros::format_description,
+};
+use uuid::Uuid;
+
+use crate::{DOCUMENT_VERSION, Document, ImportMode, ImportReport, ItemDocument, ListDocument};
+
+/// The list of tasks which have no project
+pub const DEFAULT_LIST: &str = "Inbox";
+
+static DATE_FORMAT: StaticFormatDescription = format_description!("[year]-[month]-[day]");
+
+/// A single line of a todo.txt file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Task {
+    pub is_completed: bool,
+    /// A capital letter, `A` being the most important
+    pub priority: Option<char>,
+    /// Only completed tasks have a completion date
+    pub completed_on: Option<Date>,
+    pub created_on: Option<Date>,
+    /// Everything after the dates, including projects, contexts, and tags
+    pub text: String,
+}
+
+impl Task {
+    /// Words of the text starting with `+`, without the `+`
+    pub fn projects(&self) -> impl Iterator<Item = &str> {
+        self.words_with_prefix('+')
+    }
+
+    /// Words of the text starting with `@`, without the `@`
+    pub fn contexts(&self) -> impl Iterator<Item = &str> {
+        self.words_with_prefix('@')
+    }
+
+    /// The value of a `key:value` tag in the text
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.text.split_whitespace().find_map(|word| {
+            word.strip_prefix(key)?
+                .strip_prefix(':')
+                .filter(|value| !value.is_empty())
+        })
+    }
+
+    fn words_with_prefix(&self, prefix: char) -> impl Iterator<Item = &str> {
+        self.text
+            .split_whitespace()
+            .filter_map(move |word| word.strip_prefix(prefix).filter(|rest| !rest.is_empty()))
+    }
+}
+
+impl FromStr for Task {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut rest = line.trim();
+        if rest.is_empty() {
+            bail!("Task::from_str: empty line");
+        }
+
+        let is_completed = take_word(&mut rest, |word| word == "x").is_some();
+        let priority = if is_completed {
+            None
+        } else {
+            take_word(&mut rest, |word| priority(word).is_some()).and_then(priority)
+        };
+        // a completed task's completion date comes first, then its creation date
+        let first_date = take_date(&mut rest);
+        let (completed_on, created_on) = if is_completed {
+            let second_date = first_date.and_then(|_| take_date(&mut rest));
+            (first_date, second_date)
+        } else {
+            (None, first_date)
+        };
+
+        Ok(Self {
+            is_completed,
+            priority,
+            completed_on,
+            created_on,
+            text: rest.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dates = [self.completed_on, self.created_on];
+        let mut parts = Vec::new();
+        if self.is_completed {
+            parts.push("x".to_owned());
+        }
+        if let Some(priority) = self.priority {
+            parts.push(format!("({priority})"));
+        }
+        for date in dates.into_iter().flatten() {
+            parts.push(format_date(date));
+        }
+        if !self.text.is_empty() {
+            parts.push(self.text.clone());
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+/// The priority letter of a word such as `(A)`
+fn priority(word: &str) -> Option<char> {
+    let letter = word.strip_prefix('(')?.strip_suffix(')')?;
+    let mut chars = letter.chars();
+    let letter = chars.next().filter(char::is_ascii_uppercase)?;
+    chars.next().is_none().then_some(letter)
+}
+
+/// Remove the first word of `rest` if it satisfies `accept`
+fn take_word<'a>(rest: &mut &'a str, accept: impl Fn(&str) -> bool) -> Option<&'a str> {
+    let (word, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+    if !accept(word) {
+        return None;
+    }
+    *rest = tail.trim_start();
+    Some(word)
+}
+
+fn take_date(rest: &mut &str) -> Option<Date> {
+    let word = take_word(rest, |word| Date::parse(word, DATE_FORMAT).is_ok())?;
+    Date::parse(word, DATE_FORMAT).ok()
+}
+
+fn format_date(date: Date) -> String {
+    date.format(DATE_FORMAT)
+        .expect("every date can be formatted as year-month-day")
+}
+
+/// The project which stands for a list
+pub fn project(title: &str) -> String {
+    let project = title.split_whitespace().collect::<Vec<_>>().join("_");
+    if project.is_empty() {
+        DEFAULT_LIST.to_owned()
+    } else {
+        project
+    }
+}
+
+/// Write every item of a document as a task, list by list
+pub fn from_document(document: &Document) -> String {
+    let mut out = String::new();
+    for list in &document.lists {
+        let project_tag = format!("+{}", project(&list.title));
+        for item in &list.items {
+            let (priority, description) = if item.is_completed {
+                (None, item.description.as_str())
+            } else {
+                split_priority(&item.description)
+            };
+            let uuid_tag = format!("uuid:{}", item.uuid);
+            let text = [project_tag.as_str(), description, uuid_tag.as_str()]
+                .into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let completed_on = item.completed_at.map(UtcDateTime::date);
+            let task = Task {
+                is_completed: item.is_completed,
+                priority,
+                completed_on,
+                // a completed task's only date would read as its completion date
+                created_on: (!item.is_completed || completed_on.is_some())
+                    .then(|| item.created_at.date()),
+                text,
+            };
+            out.push_str(&task.to_string());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Separate a leading priority from a description
+fn split_priority(description: &str) -> (Option<char>, &str) {
+    let mut rest = description;
+    match take_word(&mut rest, |word| priority(word).is_some()).and_then(priority) {
+        Some(priority) => (Some(priority), rest),
+        None => (None, description),
+    }
+}
+
+/// Read tasks into a document.
+///
+/// `existing` is the current contents of the database the document is destined for. A task whose
+/// project matches an existing list belongs to that list. A task without a `uuid:` tag is matched to
+/// an existing item of its list by description, if possible. Everything else is new.
+pub fn to_document(text: &str, existing: &Document) -> Result<Document> {
+    let existing_lists = existing
+        .lists
+        .iter()
+        .map(|list| (project(&list.title), list))
+        .collect::<HashMap<_, _>>();
+    let existing_items = existing
+        .lists
+        .iter()
+        .flat_map(|list| &list.items)
+        .map(|item| (item.uuid, item))
+        .collect::<HashMap<_, _>>();
+    let now = UtcDateTime::now();
+
+    let mut lists: Vec<ListDocument> = Vec::new();
+    let mut list_indices = HashMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let task = line
+            .parse::<Task>()
+            .with_context(|| format!("to_document: parsing line {line_number}"))?;
+
+        let project = task.projects().next().unwrap_or(DEFAULT_LIST).to_owned();
+        let list_index = *list_indices.entry(project.clone()).or_insert_with(|| {
+            lists.push(match existing_lists.get(&project) {
+                Some(list) => ListDocument {
+                    items: Vec::new(),
+                    ..(*list).clone()
+                },
+                None => ListDocument {
+                    uuid: Uuid::now_v7(),
+                    title: project.clone(),
+                    created_at: now,
+                    items: Vec::new(),
+                },
+            });
+            lists.len() - 1
+        });
+        let list = &mut lists[list_index];
+
+        let uuid = task
+            .tag("uuid")
+            .map(|uuid| {
+                Uuid::parse_str(uuid).map_err(|err| anyhow!("line {line_number}: bad uuid: {err}"))
+            })
+            .transpose()
+            .context("to_document: reading uuid")?;
+        let description = description(&task, &project);
+        let existing_item = match uuid {
+            Some(uuid) => existing_items.get(&uuid).copied(),
+            None => existing_lists.get(&project).and_then(|existing| {
+                existing.items.iter().find(|item| {
+                    item.description == description
+                        && !list.items.iter().any(|taken| taken.uuid == item.uuid)
+                })
+            }),
+        };
+
+        let midnight = |date: Date| UtcDateTime::new(date, Time::MIDNIGHT);
+        list.items.push(ItemDocument {
+            uuid: uuid
+                .or(existing_item.map(|item| item.uuid))
+                .unwrap_or_else(Uuid::now_v7),
+            description,
+            is_completed: task.is_completed,
+            position: list.items.len() as i64 + 1,
+            created_at: task
+                .created_on
+                .map(midnight)
+                .or(existing_item.map(|item| item.created_at))
+                .unwrap_or(now),
+            completed_at: task.completed_on.map(midnight),
+        });
+    }
+
+    Ok(Document {
+        version: DOCUMENT_VERSION,
+        lists,
+    })
+}
+
+/// The description of the item a task stands for: its text, less the project which names its list
+/// and its uuid, with its priority in front
+fn description(task: &Task, project: &str) -> String {
+    let project_tag = format!("+{project}");
+    let mut removed_project = false;
+    let words = task.text.split_whitespace().filter(|&word| {
+        if !removed_project && word == project_tag {
+            removed_project = true;
+            return false;
+        }
+        !word.starts_with("uuid:")
+    });
+    let priority = task.priority.map(|priority| format!("({priority})"));
+    priority
+        .as_deref()
+        .into_iter()
+        .chain(words)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Export the whole database as todo.txt
+pub async fn export(connection: &Connection) -> Result<String> {
+    let document = Document::export(connection)
+        .await
+        .context("todo_txt::export: exporting document")?;
+    Ok(from_document(&document))
+}
+
+/// Import todo.txt into the database; see [`to_document`] for how tasks match existing data
+pub async fn import(connection: &Connection, text: &str, mode: ImportMode) -> Result<ImportReport> {
+    let existing = Document::export(connection)
+        .await
+        .context("todo_txt::import: exporting existing data")?;
+    let document = to_document(text, &existing).context("todo_txt::import: reading tasks")?;
+    document
+        .import(connection, mode)
+        .await
+        .context("todo_txt::import: importing document")
+}
@@ -0,0 +1,71 @@
+//! Databases can be backed up while in use, and restored from those backups.
+
+mod common;
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use rusqlite::Connection;
+use todo_list::{
+    BackupProgress, TodoList,
+    encryption::{self, Key},
+};
+
+use common::{TempFile, titles};
+
+#[test]
+fn backup_and_restore() -> Result<()> {
+    let (db, copy) = (TempFile::new(), TempFile::new());
+    let mut connection = db.open(None)?;
+    block_on(TodoList::new(&connection, "Groceries".into()))?;
+
+    let mut reports = Vec::new();
+    todo_list::backup(&connection, copy.path(), None, |progress| {
+        reports.push(progress)
+    })?;
+    let last = reports.last().expect("every backup takes a step");
+    assert_eq!(last.copied, last.total);
+    assert!(last.total > 0);
+    assert_eq!(titles(&copy.open(None)?)?, ["Groceries"]);
+
+    block_on(TodoList::new(&connection, "Chores".into()))?;
+    // a second connection sees the restored database too
+    let other = db.open(None)?;
+    todo_list::restore(&mut connection, copy.path(), None, |_| {})?;
+    assert_eq!(titles(&connection)?, ["Groceries"]);
+    assert_eq!(titles(&other)?, ["Groceries"]);
+    assert_eq!(
+        todo_list::schema_version(&connection)?,
+        todo_list::SCHEMA_VERSION
+    );
+    Ok(())
+}
+
+#[test]
+fn backups_of_encrypted_databases_are_encrypted() -> Result<()> {
+    let key = Key::from("correct horse battery staple");
+    let (db, copy) = (TempFile::new(), TempFile::new());
+    let connection = db.open(Some(&key))?;
+    block_on(TodoList::new(&connection, "Groceries".into()))?;
+
+    todo_list::backup(&connection, copy.path(), Some(&key), |_| {})?;
+    assert!(encryption::file_is_encrypted(copy.path())?);
+    assert!(copy.open(None).is_err());
+    assert_eq!(titles(&copy.open(Some(&key))?)?, ["Groceries"]);
+    Ok(())
+}
+
+#[test]
+fn failed_backups_leave_nothing_behind() -> Result<()> {
+    let (db, copy) = (TempFile::new(), TempFile::new());
+    let connection = db.open(Some(&Key::from("correct horse battery staple")))?;
+
+    // the copy has no key, which SQLCipher refuses
+    let result = todo_list::backup(&connection, copy.path(), None, |_: BackupProgress| {});
+    assert!(result.is_err());
+    assert!(!copy.path().exists());
+
+    let missing = TempFile::new();
+    let mut connection = Connection::open_in_memory()?;
+    assert!(todo_list::restore(&mut connection, missing.path(), None, |_| {}).is_err());
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Fixtures shared by the integration tests; each test uses only some of them.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use rusqlite::Connection;
+use todo_list::{
+    Document, TodoList, Uuid,
+    encryption::{self, Key},
+};
+
+/// An empty database in memory
+pub fn database() -> Result<Connection> {
+    let connection = Connection::open_in_memory()?;
+    block_on(todo_list::apply_schema(&connection))?;
+    Ok(connection)
+}
+
+/// Export a database, leaving out completion times, which each database dates by its own clock
+pub fn export(connection: &Connection) -> Result<Document> {
+    let mut document = block_on(Document::export(connection))?;
+    for list in &mut document.lists {
+        for item in &mut list.items {
+            item.completed_at = None;
+        }
+    }
+    Ok(document)
+}
+
+/// The titles of every list in the database
+pub fn titles(connection: &Connection) -> Result<Vec<String>> {
+    let lists = block_on(TodoList::list_all(connection))?;
+    Ok(lists.into_iter().map(|(_, title)| title).collect())
+}
+
+/// A file in the temp dir, removed again when dropped
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("todo-list-test-{}.sqlite", Uuid::now_v7())))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Whether changing the database's key left a backup beside it
+    pub fn backup_exists(&self) -> bool {
+        let mut backup = self.0.clone().into_os_string();
+        backup.push(".pre-rekey");
+        Path::new(&backup).exists()
+    }
+
+    /// Open the database, unlocking it with `key` if given, and apply the schema
+    pub fn open(&self, key: Option<&Key>) -> Result<Connection> {
+        let connection = Connection::open(self.path())?;
+        if let Some(key) = key {
+            encryption::unlock(&connection, key)?;
+        }
+        block_on(todo_list::apply_schema(&connection))?;
+        Ok(connection)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.path());
+    }
+}
@@ -0,0 +1,303 @@
+//! Merging must be commutative, associative, and idempotent, so that replicas converge regardless of
+//! the order in which they exchange state.
+
+use quickcheck::{Arbitrary, Gen, quickcheck};
+use todo_list::{
+    Uuid,
+    crdt::{Hlc, ItemState, ListState, LwwRegister, Merge, OrSet},
+};
+
+/// Small ranges, so that equal millis and counters are common
+fn hlc(g: &mut Gen) -> Hlc {
+    Hlc::new(
+        u64::from(u8::arbitrary(g) % 8),
+        u32::from(u8::arbitrary(g) % 4),
+        Uuid::from_u128(u128::from(u8::arbitrary(g) % 3)),
+    )
+}
+
+/// A register whose value is determined by its stamp, as it is when a replica never reuses a stamp
+fn register(g: &mut Gen) -> LwwRegister<String> {
+    let stamp = hlc(g);
+    LwwRegister::new(format!("{stamp:?}"), stamp)
+}
+
+fn merged<T: Clone + Merge>(a: &T, b: &T) -> T {
+    let mut out = a.clone();
+    out.merge(b);
+    out
+}
+
+fn obeys_merge_laws<T: Clone + Merge + PartialEq>(a: &T, b: &T, c: &T) -> bool {
+    let commutative = merged(a, b) == merged(b, a);
+    let associative = merged(&merged(a, b), c) == merged(a, &merged(b, c));
+    let idempotent = merged(a, a) == *a && merged(&merged(a, b), b) == merged(a, b);
+    commutative && associative && idempotent
+}
+
+#[derive(Debug, Clone)]
+struct ArbHlc(Hlc);
+
+impl Arbitrary for ArbHlc {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Self(hlc(g))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ArbRegister(LwwRegister<String>);
+
+impl Arbitrary for ArbRegister {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Self(register(g))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ArbSet(OrSet<LwwRegister<String>>);
+
+impl Arbitrary for ArbSet {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let mut set = OrSet::default();
+        for _ in 0..u8::arbitrary(g) % 8 {
+            let tag = Uuid::from_u128(u128::from(u8::arbitrary(g) % 6));
+            if bool::arbitrary(g) {
+                set.add(tag, register(g));
+            } else {
+                set.remove(tag);
+            }
+        }
+        Self(set)
+    }
+}
+
+/// An edit made by one replica, or an exchange of state between two
+#[derive(Debug, Clone)]
+enum Op {
+    SetTitle { replica: u8, millis: u8 },
+    AddItem { replica: u8, millis: u8 },
+    SetDescription { replica: u8, item: u8, millis: u8 },
+    SetCompleted { replica: u8, item: u8, millis: u8 },
+    SetPosition { replica: u8, item: u8, millis: u8 },
+    RemoveItem { replica: u8, item: u8 },
+    Merge { from: u8, to: u8 },
+}
+
+impl Arbitrary for Op {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let replica = u8::arbitrary(g);
+        let item = u8::arbitrary(g);
+        // wall clocks which disagree and sometimes run backwards
+        let millis = u8::arbitrary(g) % 16;
+        match u8::arbitrary(g) % 7 {
+            0 => Self::SetTitle { replica, millis },
+            1 => Self::AddItem { replica, millis },
+            2 => Self::SetDescription {
+                replica,
+                item,
+                millis,
+            },
+            3 => Self::SetCompleted {
+                replica,
+                item,
+                millis,
+            },
+            4 => Self::SetPosition {
+                replica,
+                item,
+                millis,
+            },
+            5 => Self::RemoveItem { replica, item },
+            _ => Self::Merge {
+                from: replica,
+                to: item,
+            },
+        }
+    }
+}
+
+const REPLICAS: usize = 3;
+
+struct Replica {
+    node: Uuid,
+    latest: Hlc,
+    state: ListState,
+}
+
+impl Replica {
+    fn tick(&mut self, millis: u8) -> Hlc {
+        self.latest = Hlc::next(self.latest, millis.into(), self.node);
+        self.latest
+    }
+
+    fn item(&mut self, item: u8) -> Option<&mut ItemState> {
+        let tag = self
+            .state
+            .items
+            .iter()
+            .map(|(tag, _)| tag)
+            .nth(usize::from(item) % self.state.items.len().max(1))?;
+        self.state.items.get_mut(tag)
+    }
+}
+
+fn run(ops: &[Op]) -> Vec<ListState> {
+    let origin = Hlc::new(0, 0, Uuid::nil());
+    let initial = ListState {
+        title: LwwRegister::new(String::new(), origin),
+        items: OrSet::default(),
+    };
+    let mut replicas = (1..=REPLICAS as u128)
+        .map(|node| Replica {
+            node: Uuid::from_u128(node),
+            latest: origin,
+            state: initial.clone(),
+        })
+        .collect::<Vec<_>>();
+    let mut next_tag = 0;
+
+    for op in ops {
+        let pick = |replica: u8| usize::from(replica) % REPLICAS;
+        match *op {
+            Op::SetTitle { replica, millis } => {
+                let replica = &mut replicas[pick(replica)];
+                let stamp = replica.tick(millis);
+                replica.state.title.set(format!("{stamp:?}"), stamp);
+            }
+            Op::AddItem { replica, millis } => {
+                let replica = &mut replicas[pick(replica)];
+                let stamp = replica.tick(millis);
+                next_tag += 1;
+                replica.state.items.add(
+                    Uuid::from_u128(next_tag),
+                    ItemState {
+                        description: LwwRegister::new(format!("{stamp:?}"), stamp),
+                        is_completed: LwwRegister::new(false, stamp),
+                        position: LwwRegister::new(next_tag as i64, stamp),
+                    },
+                );
+            }
+            Op::SetDescription {
+                replica,
+                item,
+                millis,
+            } => {
+                let replica = &mut replicas[pick(replica)];
+                let stamp = replica.tick(millis);
+                if let Some(item) = replica.item(item) {
+                    item.description.set(format!("{stamp:?}"), stamp);
+                }
+            }
+            Op::SetCompleted {
+                replica,
+                item,
+                millis,
+            } => {
+                let replica = &mut replicas[pick(replica)];
+                let stamp = replica.tick(millis);
+                if let Some(item) = replica.item(item) {
+                    let is_completed = !*item.is_completed.value();
+                    item.is_completed.set(is_completed, stamp);
+                }
+            }
+            Op::SetPosition {
+                replica,
+                item,
+                millis,
+            } => {
+                let replica = &mut replicas[pick(replica)];
+                let stamp = replica.tick(millis);
+                if let Some(item) = replica.item(item) {
+                    item.position.set(i64::from(millis), stamp);
+                }
+            }
+            Op::RemoveItem { replica, item } => {
+                let replica = &mut replicas[pick(replica)];
+                let tag = replica
+                    .state
+                    .items
+                    .iter()
+                    .map(|(tag, _)| tag)
+                    .nth(usize::from(item) % replica.state.items.len().max(1));
+                if let Some(tag) = tag {
+                    replica.state.items.remove(tag);
+                }
+            }
+            Op::Merge { from, to } => {
+                let from = replicas[pick(from)].state.clone();
+                replicas[pick(to)].state.merge(&from);
+            }
+        }
+    }
+
+    // everyone hears from everyone, in some order
+    for to in 0..REPLICAS {
+        for from in 0..REPLICAS {
+            let state = replicas[from].state.clone();
+            replicas[to].state.merge(&state);
+        }
+    }
+    for to in 0..REPLICAS {
+        let state = replicas[REPLICAS - 1].state.clone();
+        replicas[to].state.merge(&state);
+    }
+
+    replicas.into_iter().map(|replica| replica.state).collect()
+}
+
+#[test]
+fn hlc_next_moves_to_the_next_millisecond_when_the_counter_runs_out() {
+    let node = Uuid::from_u128(7);
+    let latest = Hlc::new(1_000, u32::MAX, node);
+    assert_eq!(Hlc::next(latest, 1_000, node), Hlc::new(1_001, 0, node));
+    assert_eq!(Hlc::next(latest, 0, node), Hlc::new(1_001, 0, node));
+}
+
+#[test]
+fn hlc_far_ahead_is_clamped() {
+    let node = Uuid::from_u128(7);
+    let remote = Hlc::new(u64::MAX, u32::MAX, node);
+    assert_eq!(
+        remote.clamp_to(1_000),
+        Hlc::new(1_000 + Hlc::MAX_DRIFT_MILLIS, 0, node)
+    );
+    let near = Hlc::new(1_000 + Hlc::MAX_DRIFT_MILLIS, 3, node);
+    assert_eq!(near.clamp_to(1_000), near);
+}
+
+quickcheck! {
+    fn hlc_bytes_sort_like_hlcs(a: ArbHlc, b: ArbHlc) -> bool {
+        let (a, b) = (a.0, b.0);
+        a.cmp(&b) == a.to_bytes().cmp(&b.to_bytes())
+            && Hlc::from_bytes(&a.to_bytes()).ok() == Some(a)
+    }
+
+    fn hlc_next_is_later(latest: ArbHlc, now: u8) -> bool {
+        let node = Uuid::from_u128(7);
+        Hlc::next(latest.0, now.into(), node) > latest.0
+    }
+
+    fn hlc_clamped_is_within_drift(remote: ArbHlc, now: u8) -> bool {
+        let clamped = remote.0.clamp_to(now.into());
+        clamped <= remote.0 && clamped.millis() <= u64::from(now) + Hlc::MAX_DRIFT_MILLIS
+    }
+
+    fn register_merge_laws(a: ArbRegister, b: ArbRegister, c: ArbRegister) -> bool {
+        obeys_merge_laws(&a.0, &b.0, &c.0)
+    }
+
+    fn or_set_merge_laws(a: ArbSet, b: ArbSet, c: ArbSet) -> bool {
+        obeys_merge_laws(&a.0, &b.0, &c.0)
+    }
+
+    fn removed_tags_stay_removed(a: ArbSet, b: ArbSet) -> bool {
+        let tags = merged(&a.0, &b.0).iter().map(|(tag, _)| tag).collect::<Vec<_>>();
+        tags.into_iter()
+            .all(|tag| !a.0.is_removed(tag) && !b.0.is_removed(tag))
+    }
+
+    fn replicas_converge(ops: Vec<Op>) -> bool {
+        let states = run(&ops);
+        states.windows(2).all(|pair| pair[0] == pair[1])
+    }
+}
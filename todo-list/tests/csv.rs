@@ -0,0 +1,128 @@
+//! Items survive a trip through CSV, and spreadsheets import row by row through a column mapping.
+
+mod common;
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use todo_list::{
+    ImportMode, ImportReport, TodoList,
+    csv::{self, ColumnMapping, Field},
+};
+
+use common::{database, export};
+
+#[test]
+fn round_trip() -> Result<()> {
+    let source = database()?;
+    block_on(async {
+        let mut list = TodoList::new(&source, "Groceries, etc.".into()).await?;
+        list.add_item(&source, "Milk, \"oat\"\nnot almond".into())
+            .await?;
+        let eggs = list.add_item(&source, "Eggs".into()).await?;
+        list.item_mut(eggs).unwrap().set_is_completed(true);
+        list.save(&source).await?;
+        anyhow::Ok(())
+    })?;
+
+    let text = block_on(csv::export(&source))?;
+    assert!(text.starts_with(
+        "list,list_uuid,uuid,description,completed,position,created_at,completed_at\n"
+    ));
+
+    let destination = database()?;
+    let imported = block_on(csv::import(
+        &destination,
+        &text,
+        &ColumnMapping::default(),
+        ImportMode::Merge,
+    ))?;
+    assert_eq!(imported.errors, []);
+    assert_eq!(imported.report.created, 3);
+    let (source, destination_document) = (export(&source)?, export(&destination)?);
+    assert_eq!(destination_document.lists[0].uuid, source.lists[0].uuid);
+    assert_eq!(destination_document.lists[0].title, source.lists[0].title);
+    assert_eq!(destination_document.lists[0].items, source.lists[0].items);
+
+    // importing the same file again changes nothing
+    let imported = block_on(csv::import(
+        &destination,
+        &text,
+        &ColumnMapping::default(),
+        ImportMode::Merge,
+    ))?;
+    assert_eq!(imported.report, ImportReport::default());
+    Ok(())
+}
+
+#[test]
+fn bad_rows_are_skipped_and_reported() -> Result<()> {
+    let text = "\
+Project,Task,Done?,Notes,Due
+Work,Write report,yes,,2025-02-01
+Work,,no,a row without a task
+Work,Review PR,maybe,
+Home,\"Fix the
+sink\",,,
+,Call mom,x,
+";
+    let mapping = ColumnMapping::empty()
+        .map("Project", Field::List)
+        .map("task", Field::Description)
+        .map(" Done? ", Field::Completed);
+
+    let connection = database()?;
+    let imported = block_on(csv::import(&connection, text, &mapping, ImportMode::Merge))?;
+    let lines = imported
+        .errors
+        .iter()
+        .map(|error| error.line)
+        .collect::<Vec<_>>();
+    assert_eq!(lines, [3, 4], "{:?}", imported.errors);
+    assert!(
+        imported.errors[1].message.contains("maybe"),
+        "{}",
+        imported.errors[1]
+    );
+    // Work, Home, and Inbox, with an item each
+    assert_eq!(imported.report.created, 6);
+
+    let document = export(&connection)?;
+    let titles = document
+        .lists
+        .iter()
+        .map(|list| list.title.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, ["Work", "Home", "Inbox"]);
+    assert!(document.lists[0].items[0].is_completed);
+    assert_eq!(document.lists[1].items[0].description, "Fix the\nsink");
+    assert!(document.lists[2].items[0].is_completed);
+
+    // without uuids, rows match existing items by description
+    let imported = block_on(csv::import(&connection, text, &mapping, ImportMode::Merge))?;
+    assert_eq!(imported.report, ImportReport::default());
+
+    // replacing would delete whatever the bad rows stood for
+    assert!(
+        block_on(csv::import(
+            &connection,
+            text,
+            &mapping,
+            ImportMode::Replace
+        ))
+        .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn mappings_must_be_unambiguous() -> Result<()> {
+    let connection = database()?;
+    let import = |text: &str, mapping: &ColumnMapping| {
+        block_on(csv::import(&connection, text, mapping, ImportMode::Merge))
+    };
+    assert!(import("list,notes\nWork,Write report\n", &ColumnMapping::default()).is_err());
+    let mapping = ColumnMapping::default().map("notes", Field::Description);
+    assert!(import("description,notes\nWrite report,Review PR\n", &mapping).is_err());
+    assert!(import("list,notes\nWork,Write report\n", &mapping).is_ok());
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Deleting a list or an item records its tombstone together with the deletion, or neither.
+
+mod common;
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use rusqlite::Connection;
+use todo_list::TodoList;
+
+fn tombstones(connection: &Connection) -> Result<u32> {
+    Ok(connection.query_row("SELECT count(*) FROM tombstones", (), |row| row.get(0))?)
+}
+
+/// A list with an item, in a database which refuses to delete lists
+fn stubborn_database() -> Result<(Connection, TodoList)> {
+    let connection = common::database()?;
+    let list = block_on(async {
+        let mut list = TodoList::new(&connection, "Groceries".into()).await?;
+        list.add_item(&connection, "Milk".into()).await?;
+        anyhow::Ok(list)
+    })?;
+    connection.execute_batch(
+        "CREATE TRIGGER keep_lists BEFORE DELETE ON todo_lists BEGIN SELECT RAISE(ABORT, 'lists stay'); END;",
+    )?;
+    Ok((connection, list))
+}
+
+#[test]
+fn a_failed_delete_leaves_no_tombstones() -> Result<()> {
+    let (connection, list) = stubborn_database()?;
+    assert!(block_on(TodoList::delete(&connection, list.id())).is_err());
+    assert_eq!(tombstones(&connection)?, 0);
+    assert_eq!(
+        block_on(TodoList::load(&connection, list.id()))?
+            .items()
+            .len(),
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn a_failed_delete_inside_a_transaction_leaves_the_rest_of_it() -> Result<()> {
+    let (connection, list) = stubborn_database()?;
+    let tx = connection.unchecked_transaction()?;
+    block_on(TodoList::new(&tx, "Chores".into()))?;
+    assert!(block_on(TodoList::delete(&tx, list.id())).is_err());
+    tx.commit()?;
+
+    assert_eq!(tombstones(&connection)?, 0);
+    assert_eq!(common::titles(&connection)?, ["Groceries", "Chores"]);
+    assert_eq!(
+        block_on(TodoList::load(&connection, list.id()))?
+            .items()
+            .len(),
+        1
+    );
+    Ok(())
+}
+
+#[test]
+fn items_are_deleted_with_their_tombstones() -> Result<()> {
+    let connection = common::database()?;
+    block_on(async {
+        let mut list = TodoList::new(&connection, "Groceries".into()).await?;
+        let milk = list.add_item(&connection, "Milk".into()).await?;
+        assert!(list.remove_item(&connection, milk).await?);
+        assert!(TodoList::delete(&connection, list.id()).await?);
+        anyhow::Ok(())
+    })?;
+    assert_eq!(tombstones(&connection)?, 2);
+    Ok(())
+}
@@ -0,0 +1,223 @@
+//! Databases can be encrypted, unlocked, re-keyed, and decrypted again without losing anything.
+
+mod common;
+
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use futures_lite::future::block_on;
+use rusqlite::Connection;
+use todo_list::{
+    TodoList,
+    encryption::{
+        self, Cipher, CipherConfig, DatabaseFile, Key, SQLITE_MAGIC, Stage, Strength,
+        WrongPassphrase,
+    },
+};
+
+use common::{TempFile, titles};
+
+#[test]
+fn headers() -> Result<()> {
+    let mut header = SQLITE_MAGIC.to_vec();
+    header.extend_from_slice(&[0x10, 0x00]);
+    assert!(!encryption::is_encrypted(&header)?);
+    assert!(encryption::is_encrypted(&[0xaa; 16])?);
+    assert!(!encryption::is_encrypted(&[])?);
+    assert!(encryption::is_encrypted(b"SQLite").is_err());
+
+    let db = TempFile::new();
+    assert!(!encryption::file_is_encrypted(db.path())?);
+    Ok(())
+}
+
+#[test]
+fn encrypt_rekey_and_decrypt() -> Result<()> {
+    let (horse, staple) = (Key::from("correct horse"), Key::from("battery staple"));
+    let db = TempFile::new();
+    let mut connection = db.open(None)?;
+    block_on(TodoList::new(&connection, "Groceries".into()))?;
+    let version = todo_list::schema_version(&connection)?;
+
+    encryption::rekey(&mut connection, db.path(), None, &horse)?;
+    assert!(encryption::file_is_encrypted(db.path())?);
+    assert!(
+        !db.backup_exists(),
+        "backups of unencrypted databases don't outlive encrypting them"
+    );
+    assert_eq!(titles(&connection)?, ["Groceries"]);
+    drop(connection);
+
+    let connection = Connection::open(db.path())?;
+    let err = encryption::unlock(&connection, &staple).unwrap_err();
+    assert!(err.is::<WrongPassphrase>(), "{err:#}");
+    drop(connection);
+
+    let mut connection = db.open(Some(&horse))?;
+    encryption::rekey(&mut connection, db.path(), Some(&horse), &staple)?;
+    drop(connection);
+    let mut connection = db.open(Some(&staple))?;
+    assert_eq!(titles(&connection)?, ["Groceries"]);
+
+    encryption::remove_encryption(&mut connection, db.path(), &staple, true)?;
+    assert!(!encryption::file_is_encrypted(db.path())?);
+    assert_eq!(todo_list::schema_version(&connection)?, version);
+    drop(connection);
+    assert_eq!(titles(&db.open(None)?)?, ["Groceries"]);
+    Ok(())
+}
+
+#[test]
+fn new_databases_are_created_encrypted() -> Result<()> {
+    let db = TempFile::new();
+    db.open(Some(&Key::from("correct horse")))?;
+    assert!(encryption::file_is_encrypted(db.path())?);
+    assert!(db.open(None).is_err());
+    Ok(())
+}
+
+#[test]
+fn raw_keys_and_key_files() -> Result<()> {
+    let hex = "2DD29CA851E7B56E4697B0E1F08507293D761A05CE4D1B628663F411A8086D99";
+    let key = Key::from_hex(hex)?;
+    assert_eq!(Key::from_hex(&hex.to_lowercase())?, key);
+    for bad in ["", &hex[1..], &format!("{hex}00"), &hex.replace('D', "G")] {
+        assert!(Key::from_hex(bad).is_err(), "{bad:?}");
+    }
+    let Key::Raw(bytes) = key else {
+        unreachable!("hex makes raw keys")
+    };
+
+    let (binary_file, hex_file) = (TempFile::new(), TempFile::new());
+    std::fs::write(binary_file.path(), bytes)?;
+    std::fs::write(hex_file.path(), format!("{hex}\n"))?;
+    assert_eq!(Key::from_file(binary_file.path())?, key);
+    assert_eq!(Key::from_file(hex_file.path())?, key);
+    std::fs::write(hex_file.path(), "correct horse")?;
+    assert!(Key::from_file(hex_file.path()).is_err());
+
+    let db = TempFile::new();
+    let mut connection = db.open(Some(&key))?;
+    block_on(TodoList::new(&connection, "Groceries".into()))?;
+    drop(connection);
+    // the hex digits aren't a passphrase
+    assert!(db.open(Some(&Key::from(hex))).is_err());
+
+    connection = db.open(Some(&key))?;
+    let passphrase = Key::from("correct horse");
+    encryption::rekey(&mut connection, db.path(), Some(&key), &passphrase)?;
+    drop(connection);
+    assert_eq!(titles(&db.open(Some(&passphrase))?)?, ["Groceries"]);
+    Ok(())
+}
+
+#[test]
+fn decrypting_needs_confirmation() -> Result<()> {
+    let key = Key::from("correct horse");
+    let db = TempFile::new();
+    let mut connection = db.open(Some(&key))?;
+    assert!(encryption::remove_encryption(&mut connection, db.path(), &key, false).is_err());
+    assert!(encryption::file_is_encrypted(db.path())?);
+    block_on(TodoList::new(&connection, "Groceries".into()))?;
+    Ok(())
+}
+
+/// A database file whose new key never works, as if the key change had gone wrong halfway
+struct Unverifiable<'a>(&'a Path);
+
+impl DatabaseFile for Unverifiable<'_> {
+    fn open(&self, stage: Stage) -> Result<Connection> {
+        match stage {
+            Stage::Before => self.0.open(stage),
+            Stage::After => bail!("the new key doesn't work"),
+        }
+    }
+
+    fn back_up(&self) -> Result<()> {
+        self.0.back_up()
+    }
+
+    fn restore(&self) -> Result<()> {
+        self.0.restore()
+    }
+
+    fn remove_backup(&self) -> Result<()> {
+        self.0.remove_backup()
+    }
+}
+
+#[test]
+fn failed_key_changes_restore_the_backup() -> Result<()> {
+    let (horse, staple) = (Key::from("correct horse"), Key::from("battery staple"));
+    let db = TempFile::new();
+    let mut connection = db.open(None)?;
+    block_on(TodoList::new(&connection, "Groceries".into()))?;
+
+    let file = Unverifiable(db.path());
+    assert!(encryption::rekey(&mut connection, &file, None, &horse).is_err());
+    assert!(!encryption::file_is_encrypted(db.path())?);
+    assert!(!db.backup_exists(), "restoring uses the backup up");
+    assert_eq!(titles(&connection)?, ["Groceries"]);
+
+    encryption::rekey(&mut connection, db.path(), None, &horse)?;
+    // SQLCipher re-encrypts in place, but the check afterwards still fails
+    assert!(encryption::rekey(&mut connection, &file, Some(&horse), &staple).is_err());
+    assert_eq!(titles(&connection)?, ["Groceries"]);
+    drop(connection);
+    assert_eq!(titles(&db.open(Some(&horse))?)?, ["Groceries"]);
+    Ok(())
+}
+
+#[test]
+fn empty_and_weak_passphrases_are_refused() -> Result<()> {
+    let db = TempFile::new();
+    let mut connection = db.open(None)?;
+    block_on(TodoList::new(&connection, "Groceries".into()))?;
+
+    for passphrase in ["", "password1"] {
+        let key = Key::from(passphrase);
+        assert!(
+            encryption::rekey(&mut connection, db.path(), None, &key).is_err(),
+            "{passphrase:?}"
+        );
+        assert!(!encryption::file_is_encrypted(db.path())?);
+        assert!(!db.backup_exists());
+    }
+    assert_eq!(titles(&connection)?, ["Groceries"]);
+    Ok(())
+}
+
+#[test]
+fn passphrase_strength() {
+    for (passphrase, strength) in [
+        ("", Strength::Weak),
+        ("password1", Strength::Weak),
+        ("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", Strength::Weak),
+        ("Tr0ub4dor&3", Strength::Fair),
+        ("correct horse battery staple", Strength::Strong),
+    ] {
+        assert_eq!(Strength::of(passphrase), strength, "{passphrase:?}");
+    }
+}
+
+#[test]
+fn ciphers_are_named_as_sqlite3mc_names_them() -> Result<()> {
+    for cipher in Cipher::ALL {
+        assert_eq!(cipher.to_string().parse::<Cipher>()?, cipher);
+    }
+    assert_eq!(" ChaCha20 ".parse::<Cipher>()?, Cipher::default());
+    assert!("rot13".parse::<Cipher>().is_err());
+    Ok(())
+}
+
+#[test]
+fn sqlcipher_has_no_cipher_to_choose() -> Result<()> {
+    let connection = Connection::open_in_memory()?;
+    let config = CipherConfig {
+        cipher: Cipher::SqlCipher,
+        kdf_iter: Some(256_000),
+        legacy: Some(4),
+    };
+    assert!(config.apply(&connection).is_err());
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Lists survive a trip through iCalendar, and other clients' to-dos import once.
+
+mod common;
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use todo_list::{ImportMode, ImportReport, TodoList, ical};
+
+use common::{database, export};
+
+#[test]
+fn round_trip() -> Result<()> {
+    let source = database()?;
+    block_on(async {
+        let mut list = TodoList::new(&source, "Errands; and, chores".into()).await?;
+        list.add_item(&source, "Milk\nthe oat kind, not the almond kind\\".into())
+            .await?;
+        let post = list
+            .add_item(&source, "Post the letter ".repeat(10))
+            .await?;
+        list.item_mut(post).unwrap().set_is_completed(true);
+        list.save(&source).await?;
+        TodoList::new(&source, "Empty".into()).await?;
+        anyhow::Ok(())
+    })?;
+
+    let text = block_on(ical::export(&source))?;
+    assert_eq!(text.matches("BEGIN:VCALENDAR").count(), 2);
+    assert!(text.contains("STATUS:COMPLETED\r\n"), "{text}");
+    assert!(
+        text.split("\r\n").all(|line| line.len() <= 75),
+        "lines are folded: {text}"
+    );
+
+    let destination = database()?;
+    let report = block_on(ical::import(&destination, &text, ImportMode::Merge))?;
+    assert_eq!(report.created, 4);
+    let (mut source_document, mut destination_document) = (export(&source)?, export(&destination)?);
+    // iCalendar only has times to the second
+    for document in [&mut source_document, &mut destination_document] {
+        for list in &mut document.lists {
+            for item in &mut list.items {
+                item.created_at = item.created_at.replace_nanosecond(0)?;
+            }
+        }
+    }
+    for (source, destination) in source_document
+        .lists
+        .iter()
+        .zip(&destination_document.lists)
+    {
+        assert_eq!(destination.uuid, source.uuid);
+        assert_eq!(destination.title, source.title);
+        assert_eq!(destination.items, source.items);
+    }
+
+    // importing the same file again changes nothing
+    let report = block_on(ical::import(&destination, &text, ImportMode::Merge))?;
+    assert_eq!(report, ImportReport::default());
+    Ok(())
+}
+
+#[test]
+fn other_clients_to_dos_import_once() -> Result<()> {
+    let text = "\
+BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Example Corp.//CalDAV Client//EN\r
+x-wr-calname:Work\r
+BEGIN:VEVENT\r
+UID:not-a-to-do@example.com\r
+SUMMARY:Standup\r
+END:VEVENT\r
+BEGIN:VTODO\r
+UID:20070313T123432Z-456553@example.com\r
+DTSTAMP:20070313T123432Z\r
+CREATED;TZID=\"America/New_York\":20070313T083432\r
+SUMMARY:Submit Quebec Income Tax Return for 20\r
+ 06\r
+STATUS:NEEDS-ACTION\r
+BEGIN:VALARM\r
+ACTION:AUDIO\r
+TRIGGER;VALUE=DATE-TIME:19980403T120000Z\r
+SUMMARY:Not the to-do's summary\r
+END:VALARM\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:19970901T130000Z-123404@example.com\r
+SUMMARY:Renew passport\\, driver's licence\r
+COMPLETED;VALUE=DATE:20070407\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+    let connection = database()?;
+    let report = block_on(ical::import(&connection, text, ImportMode::Merge))?;
+    assert_eq!(report.created, 3);
+
+    let document = export(&connection)?;
+    assert_eq!(document.lists.len(), 1);
+    assert_eq!(document.lists[0].title, "Work");
+    let items = &document.lists[0].items;
+    assert_eq!(
+        items[0].description,
+        "Submit Quebec Income Tax Return for 2006"
+    );
+    assert!(!items[0].is_completed);
+    assert_eq!(items[0].created_at.to_string(), "2007-03-13 8:34:32.0 +00");
+    assert_eq!(items[1].description, "Renew passport, driver's licence");
+    assert!(items[1].is_completed);
+
+    // uids which aren't uuids, and calendars without one, still match up again
+    let report = block_on(ical::import(&connection, text, ImportMode::Merge))?;
+    assert_eq!(report, ImportReport::default());
+    Ok(())
+}
+
+#[test]
+fn malformed_files_are_rejected() -> Result<()> {
+    let connection = database()?;
+    for text in [
+        "BEGIN:VTODO\r\nEND:VTODO\r\n",
+        "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nEND:VCALENDAR\r\n",
+        "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nCREATED:yesterday\r\nEND:VTODO\r\nEND:VCALENDAR\r\n",
+        "BEGIN:VCALENDAR\r\n",
+    ] {
+        assert!(
+            block_on(ical::import(&connection, text, ImportMode::Merge)).is_err(),
+            "{text:?}"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,128 @@
+//! Exported documents import into any database, and importing merges or replaces as asked.
+
+mod common;
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use rusqlite::Connection;
+use todo_list::{ImportMode, ImportReport, TodoList, export_json, import_json};
+
+use common::{database, export};
+
+/// A database with two lists, one of which has a completed and an uncompleted item
+fn populated() -> Result<Connection> {
+    let connection = database()?;
+    block_on(async {
+        let mut groceries = TodoList::new(&connection, "Groceries".into()).await?;
+        groceries.add_item(&connection, "Milk".into()).await?;
+        let eggs = groceries.add_item(&connection, "Eggs".into()).await?;
+        groceries.item_mut(eggs).unwrap().set_is_completed(true);
+        groceries.save(&connection).await?;
+        TodoList::new(&connection, "Chores".into()).await?;
+        anyhow::Ok(())
+    })?;
+    Ok(connection)
+}
+
+#[test]
+fn round_trip() -> Result<()> {
+    let source = populated()?;
+    let json = block_on(export_json(&source))?;
+
+    let destination = database()?;
+    let report = block_on(import_json(&destination, &json, ImportMode::Merge))?;
+    assert_eq!(
+        report,
+        ImportReport {
+            created: 4,
+            ..Default::default()
+        }
+    );
+    assert_eq!(export(&destination)?, export(&source)?);
+
+    // importing the same document again changes nothing
+    let report = block_on(import_json(&destination, &json, ImportMode::Merge))?;
+    assert_eq!(report, ImportReport::default());
+    Ok(())
+}
+
+#[test]
+fn merge_keeps_and_replace_deletes() -> Result<()> {
+    let source = populated()?;
+    let mut document = export(&source)?;
+    document.lists.truncate(1);
+    document.lists[0].title = "Shopping".into();
+    document.lists[0].items.pop();
+
+    let merged = populated()?;
+    block_on(async {
+        // make the destination share uuids with the source
+        let json = export_json(&source).await?;
+        import_json(&merged, &json, ImportMode::Replace).await?;
+        let report = document.import(&merged, ImportMode::Merge).await?;
+        assert_eq!(
+            report,
+            ImportReport {
+                updated: 1,
+                ..Default::default()
+            }
+        );
+        anyhow::Ok(())
+    })?;
+    let lists = export(&merged)?.lists;
+    assert_eq!(lists.len(), 2);
+    assert_eq!(lists[0].title, "Shopping");
+    assert_eq!(lists[0].items.len(), 2);
+
+    let report = block_on(document.import(&merged, ImportMode::Replace))?;
+    assert_eq!(
+        report,
+        ImportReport {
+            deleted: 2,
+            ..Default::default()
+        }
+    );
+    assert_eq!(export(&merged)?.lists, document.lists);
+    Ok(())
+}
+
+#[test]
+fn deleted_rows_stay_deleted() -> Result<()> {
+    let connection = populated()?;
+    let json = block_on(export_json(&connection))?;
+    let mut document = export(&connection)?;
+    block_on(async {
+        let mut groceries = TodoList::load_by_uuid(&connection, document.lists[0].uuid).await?;
+        let milk = groceries.items().keys().next().copied().unwrap();
+        groceries.remove_item(&connection, milk).await?;
+        let chores = TodoList::load_by_uuid(&connection, document.lists[1].uuid).await?;
+        TodoList::delete(&connection, chores.id()).await?;
+        anyhow::Ok(())
+    })?;
+
+    // importing the old export again neither brings them back nor duplicates them
+    for _ in 0..2 {
+        let report = block_on(import_json(&connection, &json, ImportMode::Merge))?;
+        assert_eq!(
+            report,
+            ImportReport {
+                skipped: 2,
+                ..Default::default()
+            }
+        );
+    }
+    document.lists.pop();
+    document.lists[0].items.remove(0);
+    assert_eq!(export(&connection)?.lists, document.lists);
+    Ok(())
+}
+
+#[test]
+fn newer_versions_are_rejected() -> Result<()> {
+    let connection = populated()?;
+    let mut document = export(&connection)?;
+    document.version += 1;
+    let json = serde_json::to_string(&document)?;
+    assert!(block_on(import_json(&connection, &json, ImportMode::Merge)).is_err());
+    Ok(())
+}
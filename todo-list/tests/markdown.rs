@@ -0,0 +1,80 @@
+//! Lists survive a trip through markdown, and checklists are found wherever they are in a document.
+
+mod common;
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use todo_list::{
+    TodoList,
+    markdown::{self, Checklist, ChecklistItem},
+};
+
+use common::database;
+
+fn item(is_completed: bool, description: &str) -> ChecklistItem {
+    ChecklistItem {
+        is_completed,
+        description: description.into(),
+    }
+}
+
+#[test]
+fn lists_round_trip() -> Result<()> {
+    let connection = database()?;
+    let exported = block_on(async {
+        let mut list = TodoList::new(&connection, "Release".into()).await?;
+        list.add_item(&connection, "Bump the version".into())
+            .await?;
+        let tag = list.add_item(&connection, "Tag `v1.0`".into()).await?;
+        list.item_mut(tag).unwrap().set_is_completed(true);
+        list.save(&connection).await?;
+        markdown::export(&connection, list.id()).await
+    })?;
+    assert_eq!(
+        exported,
+        "# Release\n\n- [ ] Bump the version\n- [x] Tag `v1.0`\n"
+    );
+
+    let imported = block_on(markdown::import(&connection, &exported, "Untitled"))?;
+    assert_eq!(imported.len(), 1);
+    assert_eq!(markdown::from_list(&imported[0]), exported);
+    Ok(())
+}
+
+#[test]
+fn checklists_are_found_among_prose() {
+    let text = "\
+- [ ] Before any heading
+
+# Release #
+
+Some prose, and a list which isn't a checklist:
+
+- Not a task
+- [] Not a task either
+
+## Checklist
+* [X] Completed
+  - [ ] Nested
+1. [ ]   Numbered
+
+## Empty
+";
+    assert_eq!(
+        markdown::parse(text),
+        [
+            Checklist {
+                title: None,
+                items: vec![item(false, "Before any heading")],
+            },
+            Checklist {
+                title: Some("Checklist".into()),
+                items: vec![
+                    item(true, "Completed"),
+                    item(false, "Nested"),
+                    item(false, "Numbered"),
+                ],
+            },
+        ]
+    );
+}
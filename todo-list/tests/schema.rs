@@ -0,0 +1,93 @@
+//! Databases from every earlier version of the schema migrate to the current one without losing anything.
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use rusqlite::Connection;
+use todo_list::{SCHEMA_VERSION, TodoList};
+
+/// A database as the first release left it: the initial schema, with no `user_version` recorded
+fn legacy_database() -> Result<Connection> {
+    let connection = Connection::open_in_memory()?;
+    connection.execute_batch(
+        "CREATE TABLE todo_lists (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE todo_items (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            list_id INTEGER NOT NULL,
+            description TEXT NOT NULL,
+            is_completed BOOLEAN NOT NULL DEFAULT 0,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (list_id) REFERENCES todo_lists(id) ON DELETE CASCADE
+        );
+        CREATE INDEX todo_items_by_list ON todo_items (list_id);
+
+        INSERT INTO todo_lists (title) VALUES ('Groceries'), ('Chores');
+        INSERT INTO todo_items (list_id, description, is_completed) VALUES
+            (1, 'Milk', 0),
+            (1, 'Eggs', 1),
+            (2, 'Laundry', 0);",
+    )?;
+    Ok(connection)
+}
+
+#[test]
+fn legacy_databases_migrate_to_the_current_schema() -> Result<()> {
+    let connection = legacy_database()?;
+    assert_eq!(todo_list::schema_version(&connection)?, 1);
+
+    block_on(todo_list::apply_schema(&connection))?;
+    assert_eq!(todo_list::schema_version(&connection)?, SCHEMA_VERSION);
+
+    let lists = block_on(TodoList::list_all(&connection))?;
+    let titles = lists
+        .iter()
+        .map(|(_, title)| title.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, ["Groceries", "Chores"]);
+
+    let groceries = block_on(TodoList::load(&connection, lists[0].0))?;
+    let items = groceries
+        .items()
+        .values()
+        .map(|item| (item.description().as_str(), item.is_completed()))
+        .collect::<Vec<_>>();
+    assert_eq!(items, [("Milk", false), ("Eggs", true)]);
+
+    // every row got a uuid of its own
+    let uuids = connection.query_row(
+        "SELECT count(DISTINCT uuid) FROM (SELECT uuid FROM todo_lists UNION ALL SELECT uuid FROM todo_items)",
+        (),
+        |row| row.get::<_, u32>(0),
+    )?;
+    assert_eq!(uuids, 5);
+
+    // migrating again changes nothing
+    block_on(todo_list::apply_schema(&connection))?;
+    assert_eq!(todo_list::schema_version(&connection)?, SCHEMA_VERSION);
+    Ok(())
+}
+
+#[test]
+fn uuids_are_required() -> Result<()> {
+    let connection = legacy_database()?;
+    block_on(todo_list::apply_schema(&connection))?;
+
+    for (table, values) in [
+        ("todo_lists", "(title) VALUES ('Errands')"),
+        ("todo_items", "(list_id, description) VALUES (1, 'Bread')"),
+    ] {
+        let err = connection
+            .execute(&format!("INSERT INTO {table} {values}"), ())
+            .unwrap_err();
+        assert!(err.to_string().contains("NOT NULL"), "{table}: {err}");
+
+        let err = connection
+            .execute(&format!("UPDATE {table} SET uuid = NULL"), ())
+            .unwrap_err();
+        assert!(err.to_string().contains("NOT NULL"), "{table}: {err}");
+    }
+    Ok(())
+}
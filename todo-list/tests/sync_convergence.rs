@@ -0,0 +1,273 @@
+//! Databases which edit concurrently and exchange changes through a shared log, as they would
+//! through the sync server, must end up with identical contents.
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+use rusqlite::{Connection, types::Value};
+use todo_list::{
+    TodoList,
+    sync::{ConflictPolicy, Recorder},
+};
+
+const REPLICAS: usize = 3;
+
+#[derive(Debug, Clone)]
+enum Op {
+    AddList {
+        replica: u8,
+    },
+    Rename {
+        replica: u8,
+        list: u8,
+    },
+    DeleteList {
+        replica: u8,
+        list: u8,
+    },
+    AddItem {
+        replica: u8,
+        list: u8,
+    },
+    Describe {
+        replica: u8,
+        list: u8,
+        item: u8,
+    },
+    Toggle {
+        replica: u8,
+        list: u8,
+        item: u8,
+    },
+    Move {
+        replica: u8,
+        list: u8,
+        item: u8,
+        position: i8,
+    },
+    RemoveItem {
+        replica: u8,
+        list: u8,
+        item: u8,
+    },
+    Push {
+        replica: u8,
+    },
+    Pull {
+        replica: u8,
+    },
+}
+
+impl Op {
+    fn replica(&self) -> usize {
+        let (Self::AddList { replica }
+        | Self::Rename { replica, .. }
+        | Self::DeleteList { replica, .. }
+        | Self::AddItem { replica, .. }
+        | Self::Describe { replica, .. }
+        | Self::Toggle { replica, .. }
+        | Self::Move { replica, .. }
+        | Self::RemoveItem { replica, .. }
+        | Self::Push { replica }
+        | Self::Pull { replica }) = self;
+        usize::from(*replica) % REPLICAS
+    }
+}
+
+impl Arbitrary for Op {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let replica = u8::arbitrary(g);
+        let list = u8::arbitrary(g);
+        let item = u8::arbitrary(g);
+        match u8::arbitrary(g) % 14 {
+            0 | 1 => Self::AddList { replica },
+            2 => Self::Rename { replica, list },
+            3 => Self::DeleteList { replica, list },
+            4 | 5 => Self::AddItem { replica, list },
+            6 => Self::Describe {
+                replica,
+                list,
+                item,
+            },
+            7 => Self::Toggle {
+                replica,
+                list,
+                item,
+            },
+            8 => Self::Move {
+                replica,
+                list,
+                item,
+                position: i8::arbitrary(g),
+            },
+            9 => Self::RemoveItem {
+                replica,
+                list,
+                item,
+            },
+            10 | 11 => Self::Push { replica },
+            _ => Self::Pull { replica },
+        }
+    }
+}
+
+/// Load the nth list of a replica, if it has any
+async fn nth_list(connection: &Connection, n: u8) -> Result<Option<TodoList>> {
+    let lists = TodoList::list_all(connection).await?;
+    if lists.is_empty() {
+        return Ok(None);
+    }
+    let (id, _) = lists[usize::from(n) % lists.len()];
+    Ok(Some(TodoList::load(connection, id).await?))
+}
+
+fn nth_item(list: &TodoList, n: u8) -> Option<todo_list::ItemId> {
+    let ids = list.items().keys().copied().collect::<Vec<_>>();
+    (!ids.is_empty()).then(|| ids[usize::from(n) % ids.len()])
+}
+
+async fn edit(connection: &Connection, op: &Op, counter: usize) -> Result<()> {
+    match *op {
+        Op::AddList { .. } => {
+            TodoList::new(connection, format!("list {counter}")).await?;
+        }
+        Op::Rename { list, .. } => {
+            if let Some(mut list) = nth_list(connection, list).await? {
+                list.set_title(format!("title {counter}"));
+                list.save(connection).await?;
+            }
+        }
+        Op::DeleteList { list, .. } => {
+            if let Some(list) = nth_list(connection, list).await? {
+                TodoList::delete(connection, list.id()).await?;
+            }
+        }
+        Op::AddItem { list, .. } => {
+            if let Some(mut list) = nth_list(connection, list).await? {
+                list.add_item(connection, format!("item {counter}")).await?;
+            }
+        }
+        Op::Describe { list, item, .. }
+        | Op::Toggle { list, item, .. }
+        | Op::Move { list, item, .. }
+        | Op::RemoveItem { list, item, .. } => {
+            let Some(mut list) = nth_list(connection, list).await? else {
+                return Ok(());
+            };
+            let Some(item_id) = nth_item(&list, item) else {
+                return Ok(());
+            };
+            let item = list.item_mut(item_id).expect("item exists");
+            match *op {
+                Op::Describe { .. } => item.set_description(format!("description {counter}")),
+                Op::Toggle { .. } => item.set_is_completed(!item.is_completed()),
+                Op::Move { position, .. } => item.set_position(position.into()),
+                _ => {
+                    list.remove_item(connection, item_id).await?;
+                }
+            }
+            list.save(connection).await?;
+        }
+        Op::Push { .. } | Op::Pull { .. } => unreachable!("not an edit"),
+    }
+    Ok(())
+}
+
+/// Every synced column of every row, keyed by uuid rather than local id
+fn contents(connection: &Connection) -> Result<Vec<Vec<Value>>> {
+    let mut rows = Vec::new();
+    for query in [
+        "SELECT uuid, title, title_clock FROM todo_lists ORDER BY uuid",
+        "SELECT i.uuid, l.uuid, i.description, i.description_clock, i.is_completed,
+            i.is_completed_clock, i.position, i.position_clock
+        FROM todo_items i JOIN todo_lists l ON l.id = i.list_id ORDER BY i.uuid",
+    ] {
+        let mut stmt = connection.prepare(query)?;
+        let width = stmt.column_count();
+        rows.extend(
+            stmt.query_map([], |row| (0..width).map(|column| row.get(column)).collect())?
+                .collect::<rusqlite::Result<Vec<Vec<Value>>>>()?,
+        );
+    }
+    Ok(rows)
+}
+
+/// Batches in the order they were pushed, with the replica which pushed them, as the server keeps them
+type Log = Vec<(usize, Vec<u8>)>;
+
+fn push(replica: usize, recorder: &mut Recorder, log: &mut Log) -> Result<()> {
+    if !recorder.is_empty() {
+        log.push((replica, block_on(recorder.take_changes())?));
+    }
+    Ok(())
+}
+
+/// Apply everything other replicas pushed since the last pull
+fn pull(replica: usize, recorder: &mut Recorder, log: &Log, cursor: &mut usize) -> Result<()> {
+    for (source, batch) in &log[*cursor..] {
+        if *source != replica {
+            block_on(recorder.apply(batch, ConflictPolicy::LastWriteWins))?;
+        }
+    }
+    *cursor = log.len();
+    Ok(())
+}
+
+fn converges(ops: Vec<Op>) -> Result<bool> {
+    let connections = (0..REPLICAS)
+        .map(|_| {
+            let connection = Connection::open_in_memory()?;
+            block_on(todo_list::apply_schema(&connection))?;
+            Ok(connection)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut recorders = connections
+        .iter()
+        .map(Recorder::new)
+        .collect::<Result<Vec<_>>>()?;
+    let mut log = Log::new();
+    let mut cursors = [0; REPLICAS];
+
+    for (counter, op) in ops.iter().enumerate() {
+        let replica = op.replica();
+        match op {
+            Op::Push { .. } => push(replica, &mut recorders[replica], &mut log)?,
+            Op::Pull { .. } => pull(
+                replica,
+                &mut recorders[replica],
+                &log,
+                &mut cursors[replica],
+            )?,
+            op => block_on(edit(&connections[replica], op, counter))?,
+        }
+    }
+
+    for (replica, recorder) in recorders.iter_mut().enumerate() {
+        push(replica, recorder, &mut log)?;
+    }
+    for (replica, recorder) in recorders.iter_mut().enumerate() {
+        pull(replica, recorder, &log, &mut cursors[replica])?;
+    }
+
+    let expected = contents(&connections[0])?;
+    for connection in &connections[1..] {
+        if contents(connection)? != expected {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[test]
+fn replicas_converge() {
+    fn property(ops: Vec<Op>) -> TestResult {
+        match converges(ops) {
+            Ok(converged) => TestResult::from_bool(converged),
+            Err(err) => TestResult::error(format!("{err:#}")),
+        }
+    }
+
+    QuickCheck::new()
+        .tests(200)
+        .quickcheck(property as fn(Vec<Op>) -> TestResult);
+}
@@ -0,0 +1,117 @@
+//! A database on its own thread behaves as the connection would, one operation at a time.
+
+use std::{future::Future, pin::Pin, sync::mpsc};
+
+use anyhow::{Result, bail};
+use futures_lite::future::{block_on, poll_once};
+use rusqlite::Connection;
+use todo_list::{TodoListId, threaded::Database};
+
+fn database() -> Result<Database> {
+    let database = Database::new(Connection::open_in_memory()?)?;
+    block_on(database.apply_schema())?;
+    Ok(database)
+}
+
+#[test]
+fn round_trip() -> Result<()> {
+    let database = database()?;
+    block_on(async {
+        let list = database.new_list("Groceries".into()).await?;
+        let (mut list, milk) = database.add_item(list, "Milk".into()).await?;
+        list.item_mut(milk).unwrap().set_is_completed(true);
+        let list = database.save(list).await?;
+
+        let loaded = database.load(list.id()).await?;
+        assert_eq!(loaded.title(), "Groceries");
+        assert!(loaded.item(milk).unwrap().is_completed());
+        assert_eq!(database.load_by_uuid(list.uuid()).await?.id(), list.id());
+        assert_eq!(
+            database.list_all().await?,
+            [(list.id(), "Groceries".to_owned())]
+        );
+
+        let (list, removed) = database.remove_item(loaded, milk).await?;
+        assert!(removed);
+        assert!(database.load(list.id()).await?.items().is_empty());
+        assert!(database.delete(list.id()).await?);
+        assert!(database.list_all().await?.is_empty());
+        anyhow::Ok(())
+    })
+}
+
+#[test]
+fn errors_reach_the_caller() -> Result<()> {
+    let database = database()?;
+    block_on(async {
+        assert!(database.load(TodoListId::from(42)).await.is_err());
+        let err = database
+            .call(|_| -> Result<()> { bail!("no thanks") })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "no thanks");
+
+        // the worker carries on after a failed operation
+        database.new_list("Groceries".into()).await?;
+        assert_eq!(database.list_all().await?.len(), 1);
+        anyhow::Ok(())
+    })
+}
+
+#[test]
+fn operations_run_in_the_order_they_were_submitted() -> Result<()> {
+    let database = database()?;
+    // hold the worker up until every operation has been submitted, so none of them finishes early
+    let (release, gate) = mpsc::channel::<()>();
+    let mut held = Box::pin(database.call(move |_| Ok(gate.recv()?)));
+    let mut calls = (0..20)
+        .map(|i| -> Pin<Box<dyn Future<Output = Result<()>>>> {
+            Box::pin(database.call(move |connection| {
+                connection.execute(
+                    "INSERT INTO todo_lists (title, uuid) VALUES (?1, randomblob(16))",
+                    [i.to_string()],
+                )?;
+                Ok(())
+            }))
+        })
+        .collect::<Vec<_>>();
+    block_on(async {
+        // the first poll submits each operation
+        assert!(poll_once(&mut held).await.is_none());
+        for call in &mut calls {
+            assert!(poll_once(call).await.is_none());
+        }
+        release.send(())?;
+        held.await?;
+        // waiting for them in the opposite order doesn't change the order they ran in
+        for call in calls.into_iter().rev() {
+            call.await?;
+        }
+        let titles = database
+            .list_all()
+            .await?
+            .into_iter()
+            .map(|(_, title)| title)
+            .collect::<Vec<_>>();
+        assert_eq!(titles, (0..20).map(|i| i.to_string()).collect::<Vec<_>>());
+        anyhow::Ok(())
+    })
+}
+
+#[test]
+fn a_worker_which_panicked_refuses_further_operations() -> Result<()> {
+    let database = database()?;
+    block_on(async {
+        let err = database
+            .call(|_| -> Result<()> { panic!("the worker goes down with this") })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("dropped the operation"), "{err:#}");
+
+        // depending on whether the worker had stopped yet, this is refused or dropped, but never left waiting
+        assert!(database.list_all().await.is_err());
+        let err = database.list_all().await.unwrap_err();
+        assert!(err.to_string().contains("shut down"), "{err:#}");
+        anyhow::Ok(())
+    })
+}
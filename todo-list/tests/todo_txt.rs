@@ -0,0 +1,211 @@
+//! todo.txt lines survive parsing and writing, and lists survive a trip through todo.txt.
+
+mod common;
+
+use anyhow::Result;
+use futures_lite::future::block_on;
+use quickcheck::{Arbitrary, Gen, quickcheck};
+use time::{Date, Month};
+use todo_list::{
+    ImportMode, ImportReport, TodoList,
+    todo_txt::{self, Task},
+};
+
+use common::{database, export};
+
+#[test]
+fn lines_round_trip() {
+    for line in [
+        "Call mom",
+        "(A) Call mom",
+        "2025-01-01 Call mom",
+        "(B) 2025-01-01 Call mom +Family @phone",
+        "x Call mom",
+        "x 2025-01-02 Call mom",
+        "x 2025-01-02 2025-01-01 Call mom +Family @phone due:2025-01-05",
+        "x 2025-01-02 2025-01-01 (A) Call mom",
+        "2025-01-01 2025-01-02 is not a creation date",
+        "(a) is not a priority",
+        "xylophone lessons",
+    ] {
+        let task = line.parse::<Task>().unwrap();
+        assert_eq!(task.to_string(), line);
+    }
+}
+
+#[test]
+fn parts_are_recognized() {
+    let task = "x 2025-01-02 2025-01-01 Call mom +Family @phone +Chores due:2025-01-05"
+        .parse::<Task>()
+        .unwrap();
+    assert!(task.is_completed);
+    assert_eq!(task.priority, None);
+    assert_eq!(
+        task.completed_on,
+        Some(Date::from_calendar_date(2025, Month::January, 2).unwrap())
+    );
+    assert_eq!(
+        task.created_on,
+        Some(Date::from_calendar_date(2025, Month::January, 1).unwrap())
+    );
+    assert_eq!(task.projects().collect::<Vec<_>>(), ["Family", "Chores"]);
+    assert_eq!(task.contexts().collect::<Vec<_>>(), ["phone"]);
+    assert_eq!(task.tag("due"), Some("2025-01-05"));
+
+    let task = "(C) 2025-01-01 2025-01-02 Call mom"
+        .parse::<Task>()
+        .unwrap();
+    assert_eq!(task.priority, Some('C'));
+    assert_eq!(task.completed_on, None);
+    assert_eq!(task.text, "2025-01-02 Call mom");
+}
+
+#[derive(Debug, Clone)]
+struct ArbTask(Task);
+
+impl Arbitrary for ArbTask {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let date = |g: &mut Gen| {
+            Date::from_calendar_date(
+                2000 + i32::from(u8::arbitrary(g) % 50),
+                Month::try_from(1 + u8::arbitrary(g) % 12).unwrap(),
+                1 + u8::arbitrary(g) % 28,
+            )
+            .unwrap()
+        };
+        let words = [
+            "call",
+            "mom",
+            "+Family",
+            "@phone",
+            "due:2025-01-05",
+            "(A)",
+            "x",
+        ];
+        let is_completed = bool::arbitrary(g);
+        let completed_on = (is_completed && bool::arbitrary(g)).then(|| date(g));
+        let created_on = (!is_completed || completed_on.is_some())
+            .then(|| date(g))
+            .filter(|_| bool::arbitrary(g));
+        // text starts with a word which can't be mistaken for anything else
+        let text = std::iter::once("task")
+            .chain((0..u8::arbitrary(g) % 6).map(|_| *g.choose(&words).unwrap()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self(Task {
+            is_completed,
+            priority: (!is_completed && bool::arbitrary(g))
+                .then(|| char::from(b'A' + u8::arbitrary(g) % 26)),
+            completed_on,
+            created_on,
+            text,
+        })
+    }
+}
+
+quickcheck! {
+    fn tasks_round_trip(task: ArbTask) -> bool {
+        task.0.to_string().parse::<Task>().ok() == Some(task.0)
+    }
+}
+
+#[test]
+fn lists_round_trip() -> Result<()> {
+    let source = database()?;
+    block_on(async {
+        let mut home = TodoList::new(&source, "Home Improvement".into()).await?;
+        home.add_item(&source, "(A) Call the plumber @phone".into())
+            .await?;
+        let sink = home
+            .add_item(&source, "Fix the sink +Kitchen @hardware_store".into())
+            .await?;
+        home.item_mut(sink).unwrap().set_is_completed(true);
+        home.save(&source).await?;
+        let mut inbox = TodoList::new(&source, "Inbox".into()).await?;
+        inbox.add_item(&source, "Water the plants".into()).await?;
+        anyhow::Ok(())
+    })?;
+
+    let text = block_on(todo_txt::export(&source))?;
+    assert!(text.contains("(A) "), "priority leads the line: {text}");
+    assert!(text.contains("+Home_Improvement "), "{text}");
+
+    let destination = database()?;
+    let report = block_on(todo_txt::import(&destination, &text, ImportMode::Merge))?;
+    assert_eq!(report.created, 5);
+    let (source_document, destination_document) = (export(&source)?, export(&destination)?);
+    for (source, destination) in source_document
+        .lists
+        .iter()
+        .zip(&destination_document.lists)
+    {
+        // a list's creation time and title spaces don't survive
+        assert_eq!(destination.title, source.title.replace(' ', "_"));
+        for (source, destination) in source.items.iter().zip(&destination.items) {
+            assert_eq!(destination.uuid, source.uuid);
+            assert_eq!(destination.description, source.description);
+            assert_eq!(destination.is_completed, source.is_completed);
+            assert_eq!(destination.created_at.date(), source.created_at.date());
+        }
+    }
+
+    // importing the same file again changes nothing
+    let report = block_on(todo_txt::import(&destination, &text, ImportMode::Merge))?;
+    assert_eq!(report, ImportReport::default());
+    Ok(())
+}
+
+#[test]
+fn hand_written_files_import_once() -> Result<()> {
+    let text = "\
+(A) Call mom +Family @phone
+2025-01-01 Buy milk
+x Take out the trash +Chores
+
+Visit grandma +Family
+";
+    let connection = database()?;
+    let report = block_on(todo_txt::import(&connection, text, ImportMode::Merge))?;
+    assert_eq!(report.created, 7);
+
+    let document = export(&connection)?;
+    let titles = document
+        .lists
+        .iter()
+        .map(|list| list.title.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, ["Family", "Inbox", "Chores"]);
+    let family = &document.lists[0].items;
+    assert_eq!(family[0].description, "(A) Call mom @phone");
+    assert_eq!(family[1].description, "Visit grandma");
+    assert!(document.lists[2].items[0].is_completed);
+
+    // without uuids, tasks match existing items by description
+    let report = block_on(todo_txt::import(&connection, text, ImportMode::Merge))?;
+    assert_eq!(report, ImportReport::default());
+    Ok(())
+}
+
+#[test]
+fn replace_removes_missing_tasks() -> Result<()> {
+    let connection = database()?;
+    block_on(todo_txt::import(
+        &connection,
+        "Call mom +Family\nBuy milk +Errands\n",
+        ImportMode::Merge,
+    ))?;
+    let report = block_on(todo_txt::import(
+        &connection,
+        "Call mom +Family\n",
+        ImportMode::Replace,
+    ))?;
+    assert_eq!(
+        report,
+        ImportReport {
+            deleted: 2,
+            ..Default::default()
+        }
+    );
+    assert_eq!(export(&connection)?.lists.len(), 1);
+    Ok(())
+}